@@ -0,0 +1,207 @@
+//! Maintenance commands run as `server <command>` instead of starting the HTTP server.
+
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+use crate::{config::HashConfig, utils::{get_hashed_password, KeyAlg, KeyRing, SigningKey}};
+
+const DEFAULT_GRACE: i64 = 60 * 60 * 24;
+const DEFAULT_TARGET_MS: u64 = 250;
+const DEFAULT_MAX_MEMORY_KIB: u32 = 64 * 1024;
+// beyond this more passes buy little, better to allow more memory
+const MAX_ITERATIONS: u32 = 16;
+
+/// Runs the command named by the first argument, `None` when there is none and the server should start.
+pub fn run(args: &[String]) -> Option<Result<String, String>>{
+    let command = args.first()?;
+
+    let res = match command.as_str() {
+        "rotate-keys" => rotate_keys(&args[1..]),
+        "bench-hash" => bench_hash(&args[1..]),
+        other => Err(format!("Unknown command '{}', available: rotate-keys, bench-hash", other)),
+    };
+
+    Some(res)
+}
+
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str>{
+    let index = args.iter().position(|a| a == name)?;
+    args.get(index + 1).map(String::as_str)
+}
+
+/// `rotate-keys [--keyring <file>] [--grace <seconds>] [--alg RS256|EdDSA --private-key <pem> --public-key <pem>]`
+///
+/// Adds a new active key to the key ring file, creating the file if needed. Without `--alg`
+/// the key is a generated HMAC secret, otherwise it signs with the given PEM files. The previous key
+/// keeps verifying for the grace period, which defaults to `JWT_TTL_SECONDS` or else one day, so
+/// tokens it signed stay valid until they expire. Restart the server to pick up the new key.
+fn rotate_keys(args: &[String]) -> Result<String, String>{
+    let path = flag(args, "--keyring")
+        .map(String::from)
+        .or_else(|| std::env::var("JWT_KEYRING_FILE").ok());
+
+    if path.is_none(){
+        return Err(String::from("Pass --keyring <file> or set JWT_KEYRING_FILE"));
+    }
+
+    let path = path.unwrap();
+
+    let grace = flag(args, "--grace")
+        .map(String::from)
+        .or_else(|| std::env::var("JWT_TTL_SECONDS").ok());
+
+    let grace = match grace {
+        Some(grace) => grace.parse::<i64>().map_err(|_| format!("Invalid grace period '{}'", grace))?,
+        None => DEFAULT_GRACE,
+    };
+
+    let mut ring = match std::path::Path::new(&path).exists() {
+        true => KeyRing::load(&path)?,
+        false => KeyRing{active: String::new(), keys: vec![]},
+    };
+
+    let now = Utc::now().timestamp();
+
+    let kid = match flag(args, "--alg").map(KeyAlg::parse).transpose()? {
+        None | Some(KeyAlg::HS256) => ring.rotate(now, grace),
+        Some(alg) => {
+            let private_key = flag(args, "--private-key").map(String::from);
+            let public_key = flag(args, "--public-key").map(String::from);
+
+            if private_key.is_none() || public_key.is_none(){
+                return Err(format!("--alg {} needs --private-key and --public-key", alg.name()));
+            }
+
+            let kid = format!("{}-{}", now, alg.name().to_lowercase());
+            ring.rotate_to(now, grace, SigningKey::from_pem_files(&kid, alg, private_key, public_key, now)?)
+        },
+    };
+
+    ring.save(&path)?;
+
+    Ok(format!("Active signing key is now {}, {} key(s) in {}", kid, ring.keys.len(), path))
+}
+
+
+/// `bench-hash [--target-ms <ms>] [--max-memory-kib <KiB>] [--parallelism <lanes>]`
+///
+/// Suggests `ARGON2_*` settings for this machine: as much memory as fits in the target time,
+/// up to `--max-memory-kib`, then as many passes as still fit. Run it where the server runs.
+fn bench_hash(args: &[String]) -> Result<String, String>{
+    let number = |name: &str, default: u64| -> Result<u64, String>{
+        match flag(args, name) {
+            Some(value) => value.parse::<u64>().ok().filter(|n| *n > 0).ok_or_else(|| format!("Invalid {} '{}'", name, value)),
+            None => Ok(default),
+        }
+    };
+
+    let target = Duration::from_millis(number("--target-ms", DEFAULT_TARGET_MS)?);
+    let max_memory_kib = number("--max-memory-kib", DEFAULT_MAX_MEMORY_KIB as u64)? as u32;
+    let parallelism = number("--parallelism", 1)? as u32;
+
+    let measure = |config: &HashConfig| -> Result<Duration, String>{
+        let start = Instant::now();
+        get_hashed_password(config, "correct horse battery staple")?;
+        Ok(start.elapsed())
+    };
+
+    let (config, took) = pick_hash_params(target, max_memory_kib, parallelism, measure)?;
+
+    Ok(format!(
+        "Hashing takes {} ms with these settings, aiming for {} ms:\nARGON2_MEMORY_KIB={}\nARGON2_ITERATIONS={}\nARGON2_PARALLELISM={}",
+        took.as_millis(), target.as_millis(), config.memory_kib, config.iterations, config.parallelism,
+    ))
+}
+
+// Halves the memory from the maximum until one pass fits in `target`, then adds passes while they fit
+fn pick_hash_params(target: Duration, max_memory_kib: u32, parallelism: u32, measure: impl Fn(&HashConfig) -> Result<Duration, String>) -> Result<(HashConfig, Duration), String>{
+    // the smallest memory Argon2 accepts
+    let min_memory_kib = 8 * parallelism;
+
+    let mut config = HashConfig{memory_kib: max_memory_kib.max(min_memory_kib), iterations: 1, parallelism};
+    config.params()?;
+
+    let mut took = measure(&config)?;
+
+    while took > target && config.memory_kib / 2 >= min_memory_kib {
+        config.memory_kib /= 2;
+        took = measure(&config)?;
+    }
+
+    while config.iterations < MAX_ITERATIONS {
+        let next = HashConfig{iterations: config.iterations + 1, ..config.clone()};
+        let next_took = measure(&next)?;
+
+        if next_took > target {
+            break;
+        }
+
+        config = next;
+        took = next_took;
+    }
+
+    Ok((config, took))
+}
+
+
+#[cfg(test)]
+mod tests{
+    use std::time::Duration;
+
+    use crate::utils::{KeyAlg, KeyRing};
+
+    use super::{pick_hash_params, run};
+
+    #[test]
+    fn should_rotate_keys_in_file(){
+        let path = std::env::temp_dir().join(format!("rust-int-keyring-{}.json", std::process::id()));
+        let path_str = path.to_str().unwrap().to_string();
+        let args = |grace: &str| vec!["rotate-keys".to_string(), "--keyring".to_string(), path_str.clone(), "--grace".to_string(), grace.to_string()];
+
+        assert!(run(&args("3600")).unwrap().is_ok());
+        let first = KeyRing::load(&path_str).unwrap();
+        assert_eq!(first.keys.len(), 1);
+
+        // the ring holds signing secrets
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path).unwrap().permissions()) & 0o777, 0o600);
+
+        assert!(run(&args("3600")).unwrap().is_ok());
+        let second = KeyRing::load(&path_str).unwrap();
+        assert_eq!(second.keys.len(), 2);
+        assert_ne!(second.active, first.active);
+        assert!(second.keys[0].accept_until.is_some());
+
+        let mut rsa = args("3600");
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/keys");
+        rsa.extend(["--alg", "RS256", "--private-key", &format!("{}/rsa_private.pem", dir), "--public-key", &format!("{}/rsa_public.pem", dir)].map(String::from));
+        assert!(run(&rsa).unwrap().is_ok());
+
+        let third = KeyRing::load(&path_str).unwrap();
+        assert_eq!(third.active_key().unwrap().alg, KeyAlg::RS256);
+        assert_eq!(third.jwks(0).unwrap().len(), 1);
+
+        std::fs::remove_file(path).unwrap();
+
+        assert!(run(&[]).is_none());
+        assert!(run(&["nope".to_string()]).unwrap().is_err());
+    }
+
+    #[test]
+    fn should_pick_hash_params_for_target(){
+        // a millisecond per MiB and pass
+        let measure = |config: &crate::config::HashConfig| Ok(Duration::from_millis((config.memory_kib / 1024 * config.iterations) as u64));
+
+        let (config, took) = pick_hash_params(Duration::from_millis(100), 64 * 1024, 1, measure).unwrap();
+        assert_eq!((config.memory_kib, config.iterations), (64 * 1024, 1));
+        assert_eq!(took, Duration::from_millis(64));
+
+        let (config, _) = pick_hash_params(Duration::from_millis(40), 64 * 1024, 1, measure).unwrap();
+        assert_eq!((config.memory_kib, config.iterations), (32 * 1024, 1));
+
+        let (config, took) = pick_hash_params(Duration::from_millis(250), 64 * 1024, 2, measure).unwrap();
+        assert_eq!((config.memory_kib, config.iterations, config.parallelism), (64 * 1024, 3, 2));
+        assert_eq!(took, Duration::from_millis(192));
+    }
+}
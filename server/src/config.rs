@@ -0,0 +1,588 @@
+use std::{env, path::PathBuf, sync::Arc};
+
+use argon2::Params;
+use serde::{Deserialize, Serialize};
+
+use crate::{middleware::ADMIN_ROLE, policy::{BreachedList, PasswordPolicy, SHIPPED_BREACHED_LIST}, utils::{hash_token, KeyAlg, KeyRing, SigningKey}};
+
+/// Signing keys and claim settings for the JWTs handed out by `signin`.
+#[derive(Clone, Debug)]
+pub struct JwtConfig{
+    pub keys: KeyRing,
+    /// lifetime of an access token in seconds
+    pub ttl: i64,
+    /// lifetime of a refresh token in seconds, each refresh starts it over
+    pub refresh_ttl: i64,
+    /// how long the refresh tokens of one signin work in all, refreshing does not extend it
+    pub refresh_family_ttl: i64,
+    pub issuer: String,
+    pub audience: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MailTransport{
+    /// write each mail as a file into this directory
+    Outbox(PathBuf),
+    Smtp{host: String, port: u16},
+}
+
+#[derive(Clone, Debug)]
+pub struct MailConfig{
+    pub from: String,
+    pub transport: MailTransport,
+    /// where users reach the server, links in mail start with it. Never taken from the request,
+    /// whose Host header the sender picks
+    pub public_url: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct VerificationConfig{
+    /// Whether accounts have to verify their email before using most of the API
+    pub required: bool,
+    /// Paths under `/authed` an unverified account may still use, a path also covers the ones below it
+    pub allowed_paths: Vec<String>,
+}
+
+impl VerificationConfig {
+    pub fn allows(&self, path: &str) -> bool{
+        path == RESEND_VERIFICATION_PATH || self.allowed_paths.iter().any(|allowed| {
+            path == allowed || path.strip_prefix(allowed.as_str()).is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+}
+
+/// How failed signins slow down and then lock out an account or a client IP.
+#[derive(Clone, Debug)]
+pub struct LockoutConfig{
+    /// failures of an account before each further one adds a doubling delay, from one second
+    pub free_attempts: u32,
+    /// failures of an account that lock it for `lockout_seconds`
+    pub threshold: u32,
+    /// failures from one IP that lock it out, there is no backoff before as many users may share it
+    pub ip_threshold: u32,
+    /// how long a lockout lasts, also how long a failure is remembered
+    pub lockout_seconds: i64,
+}
+
+impl LockoutConfig {
+    /// Seconds to block for after `failures` failures, and whether that is a lockout.
+    pub fn delay(&self, failures: u32, free_attempts: u32, threshold: u32) -> (i64, bool){
+        if failures >= threshold {
+            return (self.lockout_seconds, true);
+        }
+
+        if failures <= free_attempts {
+            return (0, false);
+        }
+
+        let doublings = (failures - free_attempts - 1).min(30);
+        ((1i64 << doublings).min(self.lockout_seconds), false)
+    }
+}
+
+/// Argon2id cost of new password hashes. Hashes keep the parameters they were made with, so
+/// raising these upgrades each account at its next signin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HashConfig{
+    /// memory per hash in KiB
+    pub memory_kib: u32,
+    /// passes over that memory
+    pub iterations: u32,
+    /// lanes computed in parallel
+    pub parallelism: u32,
+}
+
+impl HashConfig {
+    pub fn params(&self) -> Result<Params, String>{
+        Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| format!("Invalid Argon2 parameters m={} t={} p={}: {}", self.memory_kib, self.iterations, self.parallelism, e))
+    }
+}
+
+/// An OpenID Connect provider users can sign in with, one entry of `OIDC_PROVIDERS_FILE`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct OidcProvider{
+    /// in the login path, `/user/oidc/{name}/login`
+    pub name: String,
+    /// discovery is read from `{issuer}/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// must lead to `/user/oidc/{name}/callback`
+    pub redirect_uri: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_oidc_scopes() -> Vec<String>{
+    ["openid", "email", "profile"].iter().map(|s| s.to_string()).collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct AppConfig{
+    /// Relaxes the startup checks, e.g. a missing JWT secret falls back to a built in one
+    pub dev_mode: bool,
+    pub jwt: JwtConfig,
+    pub mail: MailConfig,
+    pub verification: VerificationConfig,
+    pub password: PasswordPolicy,
+    pub lockout: LockoutConfig,
+    pub hashing: HashConfig,
+    /// password hashes computed at the same time, the rest wait their turn
+    pub hash_concurrency: usize,
+    pub oidc: Vec<OidcProvider>,
+    /// accounts that get the admin role when they are created
+    pub admin_emails: Vec<String>,
+}
+
+const DEV_SECRET: &str = "rust-int-development-secret-do-not-use-in-production";
+const DEFAULT_TTL: i64 = 60 * 15;
+const DEFAULT_REFRESH_TTL: i64 = 60 * 60 * 24 * 30;
+const DEFAULT_REFRESH_FAMILY_TTL: i64 = 60 * 60 * 24 * 90;
+const DEFAULT_ISSUER: &str = "rust-int";
+const DEFAULT_AUDIENCE: &str = "rust-int";
+const MIN_SECRET_LEN: usize = 32;
+const DEFAULT_MAIL_FROM: &str = "rust-int <no-reply@localhost>";
+const DEFAULT_PUBLIC_URL: &str = "http://localhost:8080";
+const DEFAULT_OUTBOX: &str = "outbox";
+const DEFAULT_SMTP_PORT: u16 = 25;
+// always allowed, or an unverified account whose link expired would be stuck
+const RESEND_VERIFICATION_PATH: &str = "/authed/resend-verification";
+const DEFAULT_PASSWORD_MIN_LENGTH: usize = 8;
+const DEFAULT_PASSWORD_MAX_LENGTH: usize = 128;
+const DEFAULT_PASSWORD_MIN_CLASSES: usize = 3;
+const DEFAULT_LOCKOUT_FREE_ATTEMPTS: u32 = 3;
+const DEFAULT_LOCKOUT_THRESHOLD: u32 = 10;
+const DEFAULT_LOCKOUT_IP_THRESHOLD: u32 = 100;
+const DEFAULT_LOCKOUT_SECONDS: i64 = 60 * 15;
+const DEFAULT_UNVERIFIED_PATHS: [&str; 3] = ["/authed/sessions", "/authed/logout", "/authed/logout-all"];
+
+// Values that show up in tutorials and .env examples
+const WEAK_SECRETS: [&str; 8] = ["secret", "changeme", "change-me", "password", "jwt-secret", "your-256-bit-secret", "mysecret", "test"];
+
+impl AppConfig {
+
+    /// The configuration the tests and a local `cargo run` use.
+    pub fn dev() -> AppConfig{
+        AppConfig{
+            dev_mode: true,
+            jwt: JwtConfig{
+                keys: KeyRing::single("dev", DEV_SECRET),
+                ttl: DEFAULT_TTL,
+                refresh_ttl: DEFAULT_REFRESH_TTL,
+                refresh_family_ttl: DEFAULT_REFRESH_FAMILY_TTL,
+                issuer: DEFAULT_ISSUER.to_string(),
+                audience: DEFAULT_AUDIENCE.to_string(),
+            },
+            mail: MailConfig{
+                from: DEFAULT_MAIL_FROM.to_string(),
+                // keeps test runs from filling the working directory with mail
+                transport: MailTransport::Outbox(env::temp_dir().join("rust-int-outbox")),
+                public_url: DEFAULT_PUBLIC_URL.to_string(),
+            },
+            // tests and local runs sign up without opening mail
+            verification: VerificationConfig{
+                required: false,
+                allowed_paths: DEFAULT_UNVERIFIED_PATHS.map(String::from).to_vec(),
+            },
+            password: PasswordPolicy{
+                min_length: DEFAULT_PASSWORD_MIN_LENGTH,
+                max_length: DEFAULT_PASSWORD_MAX_LENGTH,
+                min_classes: DEFAULT_PASSWORD_MIN_CLASSES,
+                breached: Arc::new(BreachedList::parse(SHIPPED_BREACHED_LIST).unwrap_or_default()),
+            },
+            lockout: LockoutConfig{
+                free_attempts: DEFAULT_LOCKOUT_FREE_ATTEMPTS,
+                threshold: DEFAULT_LOCKOUT_THRESHOLD,
+                ip_threshold: DEFAULT_LOCKOUT_IP_THRESHOLD,
+                lockout_seconds: DEFAULT_LOCKOUT_SECONDS,
+            },
+            hashing: HashConfig{
+                memory_kib: Params::DEFAULT_M_COST,
+                iterations: Params::DEFAULT_T_COST,
+                parallelism: Params::DEFAULT_P_COST,
+            },
+            hash_concurrency: default_hash_concurrency(),
+            oidc: vec![],
+            admin_emails: vec![],
+        }
+    }
+
+    /// The roles an account with this email gets once the email is proven, by the verification
+    /// link or by an OpenID provider.
+    pub fn initial_roles(&self, email: &str) -> Vec<String>{
+        if self.admin_emails.iter().any(|e| e.eq_ignore_ascii_case(email)) {
+            return vec![ADMIN_ROLE.to_string()];
+        }
+
+        vec![]
+    }
+
+    /// Reads the configuration from the environment:
+    ///
+    /// - `APP_ENV`: `dev` or `development` turns on dev mode
+    /// - `JWT_SECRET`, or `JWT_SECRET_FILE` naming a file that holds it, or `JWT_KEYRING_FILE`
+    ///   naming a key ring kept by `server rotate-keys`
+    /// - `JWT_ALG`: `RS256` or `EdDSA` sign with `JWT_PRIVATE_KEY_FILE` and publish
+    ///   `JWT_PUBLIC_KEY_FILE` instead of using a secret, defaults to `HS256`
+    /// - `JWT_TTL_SECONDS`, defaults to 15 minutes
+    /// - `REFRESH_TTL_SECONDS`, defaults to 30 days
+    /// - `REFRESH_FAMILY_TTL_SECONDS`, how long after signin refreshing stops working, defaults to 90 days
+    /// - `JWT_ISSUER` and `JWT_AUDIENCE`, both default to `rust-int`
+    /// - `SMTP_HOST` and `SMTP_PORT` to relay mail, otherwise it is written to `MAIL_OUTBOX_DIR`
+    /// - `MAIL_FROM`, the sender of that mail
+    /// - `PUBLIC_URL`, how users reach the server, for links in mail, defaults to `http://localhost:8080`
+    /// - `REQUIRE_EMAIL_VERIFICATION`, `true` or `false`, defaults to `true` outside dev mode
+    /// - `UNVERIFIED_ALLOWED_PATHS`, comma separated paths unverified accounts may use
+    /// - `PASSWORD_MIN_LENGTH`, `PASSWORD_MAX_LENGTH` and `PASSWORD_MIN_CLASSES` (0 to 4), defaults 8, 128 and 3
+    /// - `BREACHED_PASSWORDS_FILE`, a list of SHA-1 hashes to use instead of the shipped one
+    /// - `LOCKOUT_FREE_ATTEMPTS`, `LOCKOUT_THRESHOLD`, `LOCKOUT_IP_THRESHOLD` and `LOCKOUT_SECONDS`,
+    ///   defaults 3, 10, 100 and 15 minutes
+    /// - `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM` for new password hashes,
+    ///   defaults 19456 (19 MiB), 2 and 1, `server bench-hash` suggests values for this machine
+    /// - `HASH_CONCURRENCY`, how many password hashes may run at once, defaults to the number of CPUs
+    /// - `OIDC_PROVIDERS_FILE`, a JSON list of [`OidcProvider`]s to offer sign in with
+    /// - `ADMIN_EMAILS`, comma separated emails whose accounts get the admin role once verified
+    pub fn from_env() -> Result<AppConfig, String>{
+        AppConfig::from_lookup(|key| env::var(key).ok())
+    }
+
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<AppConfig, String>{
+        let dev_mode = matches!(lookup("APP_ENV").as_deref(), Some("dev") | Some("development"));
+
+        let alg = KeyAlg::parse(lookup("JWT_ALG").as_deref().unwrap_or("HS256"))?;
+
+        let keys = match alg {
+            KeyAlg::HS256 => AppConfig::hmac_keys(&lookup, dev_mode)?,
+            _ => AppConfig::pem_keys(&lookup, alg)?,
+        };
+
+        if !dev_mode {
+            for key in keys.keys.iter().filter(|k| k.alg == KeyAlg::HS256) {
+                check_secret_strength(&key.secret).map_err(|e| format!("{} (kid {})", e, key.kid))?;
+            }
+        }
+
+        let ttl = positive_seconds(&lookup, "JWT_TTL_SECONDS", DEFAULT_TTL)?;
+        let refresh_ttl = positive_seconds(&lookup, "REFRESH_TTL_SECONDS", DEFAULT_REFRESH_TTL)?;
+        let refresh_family_ttl = positive_seconds(&lookup, "REFRESH_FAMILY_TTL_SECONDS", DEFAULT_REFRESH_FAMILY_TTL)?;
+
+        let transport = match lookup("SMTP_HOST") {
+            Some(host) => {
+                let port = match lookup("SMTP_PORT") {
+                    Some(port) => port.parse::<u16>().map_err(|_| format!("SMTP_PORT must be a port number, got '{}'", port))?,
+                    None => DEFAULT_SMTP_PORT,
+                };
+                MailTransport::Smtp{host, port}
+            },
+            None => MailTransport::Outbox(PathBuf::from(lookup("MAIL_OUTBOX_DIR").unwrap_or_else(|| DEFAULT_OUTBOX.to_string()))),
+        };
+
+        let required = match lookup("REQUIRE_EMAIL_VERIFICATION").as_deref() {
+            Some("true") | Some("1") => true,
+            Some("false") | Some("0") => false,
+            Some(other) => return Err(format!("REQUIRE_EMAIL_VERIFICATION must be true or false, got '{}'", other)),
+            None => !dev_mode,
+        };
+
+        let allowed_paths = match lookup("UNVERIFIED_ALLOWED_PATHS") {
+            Some(paths) => paths.split(',').map(str::trim).filter(|p| !p.is_empty()).map(String::from).collect(),
+            None => DEFAULT_UNVERIFIED_PATHS.map(String::from).to_vec(),
+        };
+
+        let min_length = positive_number(&lookup, "PASSWORD_MIN_LENGTH", DEFAULT_PASSWORD_MIN_LENGTH)?;
+        let max_length = positive_number(&lookup, "PASSWORD_MAX_LENGTH", DEFAULT_PASSWORD_MAX_LENGTH)?;
+        let min_classes = match lookup("PASSWORD_MIN_CLASSES") {
+            Some(value) => match value.parse::<usize>() {
+                Ok(classes) if classes <= 4 => classes,
+                _ => return Err(format!("PASSWORD_MIN_CLASSES must be between 0 and 4, got '{}'", value)),
+            },
+            None => DEFAULT_PASSWORD_MIN_CLASSES,
+        };
+
+        if min_length > max_length {
+            return Err(String::from("PASSWORD_MIN_LENGTH can not be above PASSWORD_MAX_LENGTH"));
+        }
+
+        let breached = match lookup("BREACHED_PASSWORDS_FILE") {
+            Some(path) => {
+                let content = std::fs::read_to_string(&path).map_err(|e| format!("Could not read BREACHED_PASSWORDS_FILE {}: {}", path, e))?;
+                BreachedList::parse(&content).map_err(|e| format!("{} in {}", e, path))?
+            },
+            None => BreachedList::parse(SHIPPED_BREACHED_LIST)?,
+        };
+
+        let lockout = LockoutConfig{
+            free_attempts: positive_number(&lookup, "LOCKOUT_FREE_ATTEMPTS", DEFAULT_LOCKOUT_FREE_ATTEMPTS as usize)? as u32,
+            threshold: positive_number(&lookup, "LOCKOUT_THRESHOLD", DEFAULT_LOCKOUT_THRESHOLD as usize)? as u32,
+            ip_threshold: positive_number(&lookup, "LOCKOUT_IP_THRESHOLD", DEFAULT_LOCKOUT_IP_THRESHOLD as usize)? as u32,
+            lockout_seconds: positive_seconds(&lookup, "LOCKOUT_SECONDS", DEFAULT_LOCKOUT_SECONDS)?,
+        };
+
+        let hashing = HashConfig{
+            memory_kib: positive_number(&lookup, "ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST as usize)? as u32,
+            iterations: positive_number(&lookup, "ARGON2_ITERATIONS", Params::DEFAULT_T_COST as usize)? as u32,
+            parallelism: positive_number(&lookup, "ARGON2_PARALLELISM", Params::DEFAULT_P_COST as usize)? as u32,
+        };
+
+        hashing.params()?;
+
+        let hash_concurrency = positive_number(&lookup, "HASH_CONCURRENCY", default_hash_concurrency())?;
+
+        let oidc = match lookup("OIDC_PROVIDERS_FILE") {
+            Some(path) => {
+                let content = std::fs::read_to_string(&path).map_err(|e| format!("Could not read OIDC_PROVIDERS_FILE {}: {}", path, e))?;
+                let providers: Vec<OidcProvider> = serde_json::from_str(&content).map_err(|e| format!("Invalid OIDC_PROVIDERS_FILE {}: {}", path, e))?;
+
+                if let Some(provider) = providers.iter().find(|p| !p.scopes.iter().any(|s| s == "openid")) {
+                    return Err(format!("OIDC provider {} has to ask for the openid scope", provider.name));
+                }
+
+                providers
+            },
+            None => vec![],
+        };
+
+        let public_url = lookup("PUBLIC_URL").unwrap_or_else(|| DEFAULT_PUBLIC_URL.to_string());
+
+        if !public_url.starts_with("https://") && !public_url.starts_with("http://") {
+            return Err(format!("PUBLIC_URL must be an http(s) URL, got '{}'", public_url));
+        }
+
+        let public_url = public_url.trim_end_matches('/').to_string();
+
+        let admin_emails = lookup("ADMIN_EMAILS")
+            .map(|emails| emails.split(',').map(|e| e.trim().to_string()).filter(|e| !e.is_empty()).collect())
+            .unwrap_or_default();
+
+        Ok(AppConfig{
+            dev_mode,
+            jwt: JwtConfig{
+                keys,
+                ttl,
+                refresh_ttl,
+                refresh_family_ttl,
+                issuer: lookup("JWT_ISSUER").unwrap_or_else(|| DEFAULT_ISSUER.to_string()),
+                audience: lookup("JWT_AUDIENCE").unwrap_or_else(|| DEFAULT_AUDIENCE.to_string()),
+            },
+            mail: MailConfig{
+                from: lookup("MAIL_FROM").unwrap_or_else(|| DEFAULT_MAIL_FROM.to_string()),
+                transport,
+                public_url,
+            },
+            verification: VerificationConfig{
+                required,
+                allowed_paths,
+            },
+            password: PasswordPolicy{
+                min_length,
+                max_length,
+                min_classes,
+                breached: Arc::new(breached),
+            },
+            lockout,
+            hashing,
+            hash_concurrency,
+            oidc,
+            admin_emails,
+        })
+    }
+
+    fn hmac_keys(lookup: &impl Fn(&str) -> Option<String>, dev_mode: bool) -> Result<KeyRing, String>{
+        let secret = match (lookup("JWT_SECRET"), lookup("JWT_SECRET_FILE"), lookup("JWT_KEYRING_FILE")) {
+            (Some(secret), None, None) => Some(secret),
+            (None, Some(path), None) => match std::fs::read_to_string(&path) {
+                Ok(secret) => Some(secret.trim().to_string()),
+                Err(e) => return Err(format!("Could not read JWT_SECRET_FILE {}: {}", path, e)),
+            },
+            (None, None, None) => None,
+            (None, None, Some(_)) => None,
+            _ => return Err(String::from("Set only one of JWT_SECRET, JWT_SECRET_FILE and JWT_KEYRING_FILE")),
+        };
+
+        match (secret, lookup("JWT_KEYRING_FILE")) {
+            // the kid is derived from the secret so it stays the same across restarts
+            (Some(secret), _) => Ok(KeyRing::single(&hash_token(&secret)[..8], &secret)),
+            (None, Some(path)) => KeyRing::load(&path),
+            (None, None) if dev_mode => Ok(KeyRing::single("dev", DEV_SECRET)),
+            (None, None) => Err(String::from("JWT_SECRET, JWT_SECRET_FILE or JWT_KEYRING_FILE must be set outside dev mode (APP_ENV=dev)")),
+        }
+    }
+
+    fn pem_keys(lookup: &impl Fn(&str) -> Option<String>, alg: KeyAlg) -> Result<KeyRing, String>{
+        if lookup("JWT_SECRET").is_some() || lookup("JWT_SECRET_FILE").is_some() || lookup("JWT_KEYRING_FILE").is_some() {
+            return Err(format!("JWT_ALG={} takes JWT_PRIVATE_KEY_FILE and JWT_PUBLIC_KEY_FILE, not a secret or key ring", alg.name()));
+        }
+
+        let (private_key_file, public_key_file) = match (lookup("JWT_PRIVATE_KEY_FILE"), lookup("JWT_PUBLIC_KEY_FILE")) {
+            (Some(private_key_file), Some(public_key_file)) => (private_key_file, public_key_file),
+            _ => return Err(format!("JWT_ALG={} needs JWT_PRIVATE_KEY_FILE and JWT_PUBLIC_KEY_FILE", alg.name())),
+        };
+
+        let public_pem = std::fs::read_to_string(&public_key_file)
+            .map_err(|e| format!("Could not read JWT_PUBLIC_KEY_FILE {}: {}", public_key_file, e))?;
+
+        // like the HMAC kid, stable for as long as the key pair is
+        let kid = &hash_token(&public_pem)[..8];
+
+        let key = SigningKey::from_pem_files(kid, alg, Some(private_key_file), Some(public_key_file), 0)?;
+
+        Ok(KeyRing::with_key(key))
+    }
+}
+
+// more would only have hashes take turns on the same cores while holding their memory
+fn default_hash_concurrency() -> usize{
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+fn positive_seconds(lookup: &impl Fn(&str) -> Option<String>, name: &str, default: i64) -> Result<i64, String>{
+    match lookup(name) {
+        Some(value) => match value.parse::<i64>() {
+            Ok(seconds) if seconds > 0 => Ok(seconds),
+            _ => Err(format!("{} must be a positive number of seconds, got '{}'", name, value)),
+        },
+        None => Ok(default),
+    }
+}
+
+fn positive_number(lookup: &impl Fn(&str) -> Option<String>, name: &str, default: usize) -> Result<usize, String>{
+    match lookup(name) {
+        Some(value) => match value.parse::<usize>() {
+            Ok(number) if number > 0 => Ok(number),
+            _ => Err(format!("{} must be a positive number, got '{}'", name, value)),
+        },
+        None => Ok(default),
+    }
+}
+
+fn check_secret_strength(secret: &str) -> Result<(), String>{
+    if secret.len() < MIN_SECRET_LEN {
+        return Err(format!("JWT secret must be at least {} bytes", MIN_SECRET_LEN));
+    }
+
+    if secret == DEV_SECRET || WEAK_SECRETS.iter().any(|weak| secret.eq_ignore_ascii_case(weak)) {
+        return Err(String::from("JWT secret is a well known value, generate a random one"));
+    }
+
+    let mut distinct: Vec<char> = secret.chars().collect();
+    distinct.sort();
+    distinct.dedup();
+
+    if distinct.len() < 8 {
+        return Err(String::from("JWT secret is too repetitive, generate a random one"));
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests{
+    use std::collections::HashMap;
+
+    use super::{AppConfig, MailTransport};
+
+    fn config(vars: &[(&str, &str)]) -> Result<AppConfig, String>{
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        AppConfig::from_lookup(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn should_require_strong_secret_outside_dev(){
+        assert!(config(&[]).is_err());
+        assert!(config(&[("JWT_SECRET", "secret")]).is_err());
+        assert!(config(&[("JWT_SECRET", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa")]).is_err());
+
+        let config = config(&[("JWT_SECRET", "4f9c2a7e1b8d6f3a0c5e9b2d7a4f1c8e"), ("JWT_TTL_SECONDS", "900"), ("JWT_ISSUER", "https://todos.example.com")]).unwrap();
+        assert!(!config.dev_mode);
+        assert_eq!(config.jwt.ttl, 900);
+        assert_eq!(config.jwt.issuer, "https://todos.example.com");
+        assert_eq!(config.jwt.audience, "rust-int");
+        assert!(config.verification.required);
+    }
+
+    #[test]
+    fn should_allow_default_secret_in_dev(){
+        let dev = config(&[("APP_ENV", "dev")]).unwrap();
+        assert!(dev.dev_mode);
+        assert!(dev.jwt.keys.active_key().is_some());
+
+        assert!(config(&[("APP_ENV", "dev"), ("JWT_TTL_SECONDS", "-1")]).is_err());
+        assert!(config(&[("APP_ENV", "dev"), ("REFRESH_TTL_SECONDS", "soon")]).is_err());
+
+        let smtp = config(&[("APP_ENV", "dev"), ("SMTP_HOST", "localhost"), ("SMTP_PORT", "2525")]).unwrap();
+        assert_eq!(smtp.mail.transport, MailTransport::Smtp{host: "localhost".to_string(), port: 2525});
+
+        let verification = config(&[("APP_ENV", "dev"), ("REQUIRE_EMAIL_VERIFICATION", "true"), ("UNVERIFIED_ALLOWED_PATHS", "/authed/todos, /authed/sessions")]).unwrap().verification;
+        assert!(verification.required);
+        assert!(verification.allows("/authed/todos"));
+        assert!(verification.allows("/authed/sessions/abc"));
+        assert!(verification.allows("/authed/resend-verification"));
+        assert!(!verification.allows("/authed/todos-bulk"));
+        assert!(!verification.allows("/authed/logout"));
+
+        let password = config(&[("APP_ENV", "dev"), ("PASSWORD_MIN_LENGTH", "12"), ("PASSWORD_MIN_CLASSES", "4")]).unwrap().password;
+        assert_eq!((password.min_length, password.min_classes), (12, 4));
+        assert!(!password.breached.is_empty());
+
+        assert!(config(&[("APP_ENV", "dev"), ("PASSWORD_MIN_CLASSES", "5")]).is_err());
+        assert!(config(&[("APP_ENV", "dev"), ("PASSWORD_MIN_LENGTH", "200")]).is_err());
+    }
+
+    #[test]
+    fn should_read_argon2_parameters(){
+        let hashing = config(&[("APP_ENV", "dev"), ("ARGON2_MEMORY_KIB", "65536"), ("ARGON2_ITERATIONS", "3")]).unwrap().hashing;
+        assert_eq!((hashing.memory_kib, hashing.iterations, hashing.parallelism), (65536, 3, 1));
+
+        assert_eq!(config(&[("APP_ENV", "dev")]).unwrap().hashing, AppConfig::dev().hashing);
+
+        // less than 8 KiB per lane is refused by Argon2
+        assert!(config(&[("APP_ENV", "dev"), ("ARGON2_MEMORY_KIB", "8"), ("ARGON2_PARALLELISM", "4")]).is_err());
+        assert!(config(&[("APP_ENV", "dev"), ("ARGON2_ITERATIONS", "0")]).is_err());
+
+        assert_eq!(config(&[("APP_ENV", "dev"), ("HASH_CONCURRENCY", "2")]).unwrap().hash_concurrency, 2);
+        assert!(config(&[("APP_ENV", "dev"), ("HASH_CONCURRENCY", "0")]).is_err());
+    }
+
+    #[test]
+    fn should_back_off_then_lock_out(){
+        let lockout = AppConfig::dev().lockout;
+        let delays: Vec<(i64, bool)> = (1..=10).map(|failures| lockout.delay(failures, 3, 10)).collect();
+
+        assert_eq!(delays[..3], [(0, false); 3]);
+        assert_eq!(delays[3..9].iter().map(|d| d.0).collect::<Vec<_>>(), vec![1, 2, 4, 8, 16, 32]);
+        assert_eq!(delays[9], (lockout.lockout_seconds, true));
+    }
+
+    #[test]
+    fn should_load_asymmetric_key_files(){
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/keys");
+        let private_key = format!("{}/rsa_private.pem", dir);
+        let public_key = format!("{}/rsa_public.pem", dir);
+
+        let rsa = config(&[("JWT_ALG", "RS256"), ("JWT_PRIVATE_KEY_FILE", &private_key), ("JWT_PUBLIC_KEY_FILE", &public_key)]).unwrap();
+        assert_eq!(rsa.jwt.keys.active_key().unwrap().alg.name(), "RS256");
+
+        // an Ed25519 key file does not make an RSA key
+        let ed_public_key = format!("{}/ed25519_public.pem", dir);
+        assert!(config(&[("JWT_ALG", "RS256"), ("JWT_PRIVATE_KEY_FILE", &private_key), ("JWT_PUBLIC_KEY_FILE", &ed_public_key)]).is_err());
+
+        assert!(config(&[("JWT_ALG", "RS256"), ("JWT_PUBLIC_KEY_FILE", &public_key)]).is_err());
+        assert!(config(&[("JWT_ALG", "RS256"), ("JWT_SECRET", "4f9c2a7e1b8d6f3a0c5e9b2d7a4f1c8e")]).is_err());
+        assert!(config(&[("JWT_ALG", "ES256")]).is_err());
+    }
+
+    #[test]
+    fn should_load_oidc_providers(){
+        let file = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/oidc-providers.json");
+        let providers = config(&[("APP_ENV", "dev"), ("OIDC_PROVIDERS_FILE", file)]).unwrap().oidc;
+
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].name, "corp");
+        assert_eq!(providers[0].scopes, vec!["openid", "email", "profile"]);
+
+        assert!(AppConfig::dev().oidc.is_empty());
+        assert!(config(&[("APP_ENV", "dev"), ("OIDC_PROVIDERS_FILE", "/nonexistent/providers.json")]).is_err());
+    }
+}
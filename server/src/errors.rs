@@ -1,4 +1,4 @@
-use actix_web::{ResponseError};
+use actix_web::{http::StatusCode, ResponseError};
 use derive_more::derive::{Display, Error};
 
 #[derive(Display, Error, Debug)]
@@ -6,7 +6,25 @@ pub enum AppError{
     #[display("Token Not found")]
     TokenNotFound,
     #[display("Internal Error Occurred")]
-    InternalError
+    InternalError,
+    #[display("Token Revoked")]
+    TokenRevoked,
+    #[display("Verify your email first")]
+    EmailNotVerified,
+    #[display("Token lacks the scope this route requires")]
+    InsufficientScope,
+    #[display("This route needs the {_0} role")]
+    MissingRole(#[error(not(source))] &'static str),
+    #[display("Account disabled")]
+    AccountDisabled,
 }
 
-impl ResponseError for AppError{}
\ No newline at end of file
+impl ResponseError for AppError{
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::TokenRevoked => StatusCode::UNAUTHORIZED,
+            AppError::EmailNotVerified | AppError::InsufficientScope | AppError::MissingRole(_) | AppError::AccountDisabled => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
\ No newline at end of file
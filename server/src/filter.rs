@@ -0,0 +1,109 @@
+use store::todo::Todo;
+
+/// A filter expression is a space separated list of `key:value` terms that must all match,
+/// e.g. `done:true project:work title:"go to"`.
+///
+/// - `done:true|false`
+/// - `project:<name>`, or `project:none` for todos without a project
+/// - `title:<text>`, a case-insensitive substring match
+#[derive(Debug, Default, PartialEq)]
+pub struct TodoFilter {
+    pub done: Option<bool>,
+    pub project: Option<Option<String>>,
+    pub title: Option<String>,
+}
+
+impl TodoFilter {
+    pub fn matches(&self, todo: &Todo) -> bool {
+        if let Some(done) = self.done {
+            if todo.done != done {
+                return false;
+            }
+        }
+
+        if let Some(project) = &self.project {
+            if todo.project != *project {
+                return false;
+            }
+        }
+
+        if let Some(title) = &self.title {
+            if !todo.title.to_lowercase().contains(title) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+pub fn parse_filter(expr: &str) -> Result<TodoFilter, String> {
+    let mut filter = TodoFilter::default();
+    let terms = split_terms(expr)?;
+
+    if terms.is_empty() {
+        return Err(String::from("Filter cannot be empty"));
+    }
+
+    for term in terms {
+        let parts = term.split_once(':');
+
+        if parts.is_none() {
+            return Err(format!("Expected key:value, got '{}'", term));
+        }
+
+        let (key, value) = parts.unwrap();
+
+        match key {
+            "done" => {
+                let done = value.parse::<bool>();
+
+                if done.is_err() {
+                    return Err(format!("'done' must be true or false, got '{}'", value));
+                }
+
+                filter.done = Some(done.unwrap());
+            },
+            "project" => {
+                if value == "none" {
+                    filter.project = Some(None);
+                } else {
+                    filter.project = Some(Some(value.to_string()));
+                }
+            },
+            "title" => filter.title = Some(value.to_lowercase()),
+            _ => return Err(format!("Unknown filter key '{}'", key)),
+        }
+    }
+
+    Ok(filter)
+}
+
+// Splits on whitespace, keeping double quoted values together and dropping the quotes.
+fn split_terms(expr: &str) -> Result<Vec<String>, String> {
+    let mut terms = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in expr.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    terms.push(std::mem::take(&mut current));
+                }
+            },
+            c => current.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(String::from("Unterminated quote in filter"));
+    }
+
+    if !current.is_empty() {
+        terms.push(current);
+    }
+
+    Ok(terms)
+}
@@ -0,0 +1,286 @@
+use actix_web::{delete, get, post, web::{Data, Json, Path}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use store::access_token::AccessToken;
+
+use crate::{handlers::{todo::Message, user::ValidationResponse}, policy::FieldError, scopes::{self, GRANTABLE}, utils::{generate_secret_token, hash_token}, GlobalState};
+
+/// Sets access tokens apart from JWTs in the `Authorization` header.
+pub const TOKEN_PREFIX: &str = "pat_";
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(Deserialize, Serialize)]
+pub struct CreateTokenInput {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// seconds from now, no expiry when left out
+    pub expires_in: Option<i64>,
+}
+
+/// A token as listed, without the token itself.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenView{
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used: Option<i64>,
+    /// set for tokens issued to an OAuth app
+    pub client_id: Option<String>,
+}
+
+/// `token` is shown this one time only.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatedTokenResponse{
+    pub token: String,
+    #[serde(flatten)]
+    pub view: TokenView,
+}
+
+impl From<AccessToken> for TokenView {
+    fn from(token: AccessToken) -> TokenView{
+        TokenView{
+            id: token.id,
+            name: token.name,
+            scopes: token.scopes,
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            last_used: token.last_used,
+            client_id: token.client_id,
+        }
+    }
+}
+
+fn validate(input: &CreateTokenInput) -> Vec<FieldError>{
+    let mut errors = vec![];
+    let name = input.name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        errors.push(FieldError::new("name", "invalid", format!("Use 1 to {} characters", MAX_NAME_LENGTH)));
+    }
+
+    if input.scopes.is_empty() {
+        errors.push(FieldError::new("scopes", "empty", String::from("Pick at least one scope")));
+    }
+
+    for scope in input.scopes.iter().filter(|s| !GRANTABLE.contains(&s.as_str())) {
+        errors.push(FieldError::new("scopes", "unknown", format!("'{}' is not one of {}", scope, GRANTABLE.join(", "))));
+    }
+
+    if input.expires_in.is_some_and(|e| e <= 0) {
+        errors.push(FieldError::new("expires_in", "invalid", String::from("Use a positive number of seconds")));
+    }
+
+    errors
+}
+
+#[get("/tokens", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn get_tokens(req:HttpRequest, data:Data<GlobalState>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let state = state_result.unwrap();
+
+    let tokens: Vec<TokenView> = AccessToken::get_user_tokens(&state.access_tokens, &email)
+        .into_iter()
+        .map(TokenView::from)
+        .collect();
+
+    HttpResponse::Ok().json(tokens)
+}
+
+#[post("/tokens", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn create_token(req:HttpRequest, data:Data<GlobalState>, input:Json<CreateTokenInput>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let errors = validate(&input);
+
+    if !errors.is_empty(){
+        return HttpResponse::BadRequest().json(ValidationResponse{data:String::from("Check the highlighted fields"), errors});
+    }
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    let token = format!("{}{}", TOKEN_PREFIX, generate_secret_token());
+    let now = Utc::now().timestamp();
+
+    let mut scopes = input.scopes.clone();
+    scopes.sort();
+    scopes.dedup();
+
+    let record = AccessToken{
+        id: generate_secret_token()[..16].to_string(),
+        user_email: email,
+        name: input.name.trim().to_string(),
+        token_hash: hash_token(&token),
+        scopes,
+        created_at: now,
+        expires_at: input.expires_in.map(|e| now + e),
+        last_used: None,
+        client_id: None,
+    };
+
+    AccessToken::add_token(&mut state.access_tokens, record.clone());
+
+    HttpResponse::Created().json(CreatedTokenResponse{token, view: TokenView::from(record)})
+}
+
+#[delete("/tokens/{id}", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn revoke_token(req:HttpRequest, data:Data<GlobalState>, path:Path<String>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    match AccessToken::revoke(&mut state.access_tokens, &path.into_inner(), &email) {
+        Ok(val) => HttpResponse::Ok().json(Message{message:val}),
+        Err(e) => HttpResponse::NotFound().json(Message{message:e}),
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+
+    use crate::{handlers::{access_token::{CreateTokenInput, CreatedTokenResponse, TokenView}, password::ChangePasswordInput, user::{SigninInput, SignupInput, TokenResponse}}, init_app, prepare_global_state};
+
+    fn status(res: Result<actix_web::dev::ServiceResponse, actix_web::Error>) -> u16{
+        match res {
+            Ok(res) => res.status().as_u16(),
+            Err(e) => e.as_response_error().status_code().as_u16(),
+        }
+    }
+
+    #[actix_web::test]
+    pub async fn should_authorize_access_tokens_by_scope(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"vk@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let req = TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+        let jwt = test::call_and_read_body_json::<_, _, TokenResponse>(&app, req).await.data;
+
+        let req = TestRequest::post().uri("/authed/tokens").insert_header(("Authorization", jwt.clone()))
+            .set_json(CreateTokenInput{name:"".to_string(), scopes:vec!["account".to_string()], expires_in:None}).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 400);
+
+        let req = TestRequest::post().uri("/authed/tokens").insert_header(("Authorization", jwt.clone()))
+            .set_json(CreateTokenInput{name:"backup script".to_string(), scopes:vec!["todos:read".to_string()], expires_in:Some(3600)}).to_request();
+        let created: CreatedTokenResponse = test::call_and_read_body_json(&app, req).await;
+
+        assert!(created.token.starts_with("pat_"));
+        assert_eq!(created.view.scopes, vec!["todos:read"]);
+
+        let pat = created.token;
+
+        let req = TestRequest::get().uri("/authed/todos").insert_header(("Authorization", pat.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::post().uri("/authed/todo").insert_header(("Authorization", pat.clone()))
+            .set_json(serde_json::json!({"title": "from a script"})).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 403);
+
+        // account routes are out of reach, a token can not mint another one
+        let req = TestRequest::get().uri("/authed/tokens").insert_header(("Authorization", pat.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 403);
+
+        let req = TestRequest::get().uri("/authed/tokens").insert_header(("Authorization", jwt.clone())).to_request();
+        let tokens: Vec<TokenView> = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0].last_used.is_some());
+
+        let req = TestRequest::delete().uri(&format!("/authed/tokens/{}", tokens[0].id)).insert_header(("Authorization", jwt)).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::get().uri("/authed/todos").insert_header(("Authorization", pat)).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 401);
+    }
+
+    #[actix_web::test]
+    pub async fn should_revoke_access_tokens_with_the_password(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{email:"vk@gmail.com".to_string(), name:"VK".to_string(), password:"Random1234".to_string()};
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let req = TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+        let jwt = test::call_and_read_body_json::<_, _, TokenResponse>(&app, req).await.data;
+
+        let create = || TestRequest::post().uri("/authed/tokens").insert_header(("Authorization", jwt.clone()))
+            .set_json(CreateTokenInput{name:"sync".to_string(), scopes:vec!["todos:read".to_string()], expires_in:None}).to_request();
+
+        // whoever changed the password may have minted a token with it
+        let pat = test::call_and_read_body_json::<_, _, CreatedTokenResponse>(&app, create()).await.token;
+
+        let req = TestRequest::post().uri("/authed/password").insert_header(("Authorization", jwt.clone()))
+            .set_json(ChangePasswordInput{current_password:"Random1234".to_string(), new_password:"Another5678".to_string()}).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::get().uri("/authed/todos").insert_header(("Authorization", pat)).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 401);
+
+        let pat = test::call_and_read_body_json::<_, _, CreatedTokenResponse>(&app, create()).await.token;
+
+        // a route that does not exist is not a scope question
+        let req = TestRequest::get().uri("/authed/nothing-here").insert_header(("Authorization", jwt.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 404);
+
+        let req = TestRequest::post().uri("/authed/logout-all").insert_header(("Authorization", jwt.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::get().uri("/authed/todos").insert_header(("Authorization", pat)).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 401);
+    }
+}
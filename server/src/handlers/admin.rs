@@ -0,0 +1,348 @@
+use actix_web::{delete, get, post, web::{Data, Path, Query}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use store::{access_token::AccessToken, feed::FeedToken, lockout::FailedAttempts, mfa::{MfaChallenge, TotpEnrollment}, oauth::{AuthorizationCode, Consent, OAuthClient}, oidc::OidcIdentity, refresh::RefreshToken, reset::ResetToken, session::Session, todo::Todo, user::User, verification::VerificationToken};
+
+use crate::{handlers::{todo::Message, user::account_key}, middleware::{ADMIN_ROLE, ROLES}, scopes, CombinedState, GlobalState};
+
+#[derive(Deserialize, Serialize)]
+pub struct UserSearch {
+    /// part of an email or name, every user when left out
+    pub q: Option<String>,
+}
+
+/// A user as an admin sees it, without the password hash.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AdminUserView{
+    pub email: String,
+    pub name: String,
+    pub verified: bool,
+    pub roles: Vec<String>,
+    pub disabled: bool,
+}
+
+impl From<User> for AdminUserView {
+    fn from(user: User) -> AdminUserView{
+        AdminUserView{
+            email: user.email,
+            name: user.name,
+            verified: user.verified,
+            roles: user.roles,
+            disabled: user.disabled,
+        }
+    }
+}
+
+// Signs the user out everywhere, the middleware rejects whatever access tokens they still hold
+fn end_sessions(state: &mut CombinedState, email: &str){
+    RefreshToken::revoke_user(&mut state.refresh_tokens, email);
+    Session::revoke_user(&mut state.sessions, email);
+}
+
+// Everything that belongs to the user, including the OAuth apps they registered
+fn remove_account(state: &mut CombinedState, email: &String){
+    end_sessions(state, email);
+    Todo::delete_user_todos(email, &mut state.todos);
+    let _ = FeedToken::revoke(&mut state.feeds, email);
+    AccessToken::revoke_user(&mut state.access_tokens, email);
+    TotpEnrollment::remove(&mut state.totp_enrollments, email);
+    MfaChallenge::revoke_user(&mut state.mfa_challenges, email);
+    ResetToken::revoke_user(&mut state.reset_tokens, email);
+    VerificationToken::revoke_user(&mut state.verification_tokens, email);
+    FailedAttempts::reset(&mut state.failed_attempts, &account_key(email));
+    Consent::revoke_user(&mut state.oauth_consents, email);
+    OidcIdentity::remove_user(&mut state.oidc_identities, email);
+
+    for client in OAuthClient::get_user_clients(&state.oauth_clients, email) {
+        let _ = OAuthClient::remove(&mut state.oauth_clients, &client.client_id, email);
+        Consent::revoke_client(&mut state.oauth_consents, &client.client_id);
+        AuthorizationCode::revoke_client(&mut state.authorization_codes, &client.client_id);
+        AccessToken::revoke_client(&mut state.access_tokens, &client.client_id, None);
+    }
+}
+
+#[get("/users", wrap = "scopes::require(scopes::ADMIN)")]
+pub async fn get_users(data:Data<GlobalState>, query:Query<UserSearch>) -> impl Responder {
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let state = state_result.unwrap();
+
+    let users: Vec<AdminUserView> = User::search(&state.users, query.q.as_deref().unwrap_or_default())
+        .into_iter()
+        .map(AdminUserView::from)
+        .collect();
+
+    HttpResponse::Ok().json(users)
+}
+
+#[post("/users/{email}/disable", wrap = "scopes::require(scopes::ADMIN)")]
+pub async fn disable_user(req:HttpRequest, data:Data<GlobalState>, path:Path<String>) -> impl Responder {
+
+    let admin = req.extensions().get::<String>().cloned().unwrap_or_default();
+    let email = path.into_inner();
+
+    // the last admin could otherwise lock everyone out
+    if email == admin {
+        return HttpResponse::BadRequest().json(Message{message:String::from("You can not disable your own account")});
+    }
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    match User::set_disabled(&mut state.users, &email, true) {
+        Ok(val) => {
+            end_sessions(&mut state, &email);
+            println!("{} disabled {}", admin, email);
+            HttpResponse::Ok().json(Message{message:val})
+        },
+        Err(e) => HttpResponse::NotFound().json(Message{message:e}),
+    }
+}
+
+#[post("/users/{email}/enable", wrap = "scopes::require(scopes::ADMIN)")]
+pub async fn enable_user(req:HttpRequest, data:Data<GlobalState>, path:Path<String>) -> impl Responder {
+
+    let admin = req.extensions().get::<String>().cloned().unwrap_or_default();
+    let email = path.into_inner();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    match User::set_disabled(&mut state.users, &email, false) {
+        Ok(val) => {
+            println!("{} enabled {}", admin, email);
+            HttpResponse::Ok().json(Message{message:val})
+        },
+        Err(e) => HttpResponse::NotFound().json(Message{message:e}),
+    }
+}
+
+#[delete("/users/{email}", wrap = "scopes::require(scopes::ADMIN)")]
+pub async fn delete_user(req:HttpRequest, data:Data<GlobalState>, path:Path<String>) -> impl Responder {
+
+    let admin = req.extensions().get::<String>().cloned().unwrap_or_default();
+    let email = path.into_inner();
+
+    if email == admin {
+        return HttpResponse::BadRequest().json(Message{message:String::from("You can not delete your own account")});
+    }
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    match User::remove_user(&mut state.users, &email) {
+        Ok(val) => {
+            remove_account(&mut state, &email);
+            println!("{} deleted {}", admin, email);
+            HttpResponse::Ok().json(Message{message:val})
+        },
+        Err(e) => HttpResponse::NotFound().json(Message{message:e}),
+    }
+}
+
+#[post("/users/{email}/roles/{role}", wrap = "scopes::require(scopes::ADMIN)")]
+pub async fn grant_role(req:HttpRequest, data:Data<GlobalState>, path:Path<(String, String)>) -> impl Responder {
+
+    let admin = req.extensions().get::<String>().cloned().unwrap_or_default();
+    let (email, role) = path.into_inner();
+
+    if !ROLES.contains(&role.as_str()) {
+        return HttpResponse::BadRequest().json(Message{message:format!("Unknown role, available: {}", ROLES.join(", "))});
+    }
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    match User::grant_role(&mut state.users, &email, &role) {
+        Ok(val) => {
+            println!("{} granted {} to {}", admin, role, email);
+            HttpResponse::Ok().json(Message{message:val})
+        },
+        Err(e) => HttpResponse::NotFound().json(Message{message:e}),
+    }
+}
+
+#[delete("/users/{email}/roles/{role}", wrap = "scopes::require(scopes::ADMIN)")]
+pub async fn revoke_role(req:HttpRequest, data:Data<GlobalState>, path:Path<(String, String)>) -> impl Responder {
+
+    let admin = req.extensions().get::<String>().cloned().unwrap_or_default();
+    let (email, role) = path.into_inner();
+
+    if email == admin && role == ADMIN_ROLE {
+        return HttpResponse::BadRequest().json(Message{message:String::from("You can not revoke your own admin role")});
+    }
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    match User::revoke_role(&mut state.users, &email, &role) {
+        Ok(val) => {
+            println!("{} revoked {} from {}", admin, role, email);
+            HttpResponse::Ok().json(Message{message:val})
+        },
+        Err(e) => HttpResponse::NotFound().json(Message{message:e}),
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+
+    use chrono::Utc;
+
+    use crate::{config::AppConfig, handlers::{admin::AdminUserView, feed::FeedUrl, password::ForgotPasswordInput, user::{SigninInput, SignupInput, TokenResponse}, verification::issue_verification}, init_app, prepare_global_state_with};
+
+    fn status(res: Result<actix_web::dev::ServiceResponse, actix_web::Error>) -> u16{
+        match res {
+            Ok(res) => res.status().as_u16(),
+            Err(e) => e.as_response_error().status_code().as_u16(),
+        }
+    }
+
+    #[actix_web::test]
+    pub async fn should_let_admins_manage_users(){
+        let mut config = AppConfig::dev();
+        config.admin_emails = vec!["admin@gmail.com".to_string()];
+
+        let state = prepare_global_state_with(config);
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let mut tokens = vec![];
+
+        for email in ["admin@gmail.com", "vk@gmail.com"] {
+            let input = SignupInput{email:email.to_string(), name:"VK".to_string(), password:"Random1234".to_string()};
+            TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+            let req = TestRequest::post().uri("/user/signin")
+                .set_json(SigninInput{email:email.to_string(), password:"Random1234".to_string()}).to_request();
+            tokens.push(test::call_and_read_body_json::<_, _, TokenResponse>(&app, req).await.data);
+        }
+
+        let (admin, user) = (tokens[0].clone(), tokens[1].clone());
+
+        // ADMIN_EMAILS count once the address is verified
+        let req = TestRequest::get().uri("/admin/users").insert_header(("Authorization", admin.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 403);
+
+        for email in ["admin@gmail.com", "vk@gmail.com"] {
+            let token = issue_verification(&mut state.overall_state.lock().unwrap(), email, Utc::now().timestamp());
+            let req = TestRequest::get().uri(&format!("/user/verify-email?token={}", token)).to_request();
+            assert_eq!(status(test::try_call_service(&app, req).await), 200);
+        }
+
+        let req = TestRequest::get().uri("/admin/users").insert_header(("Authorization", user.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 403);
+
+        let req = TestRequest::get().uri("/admin/users?q=VK@GMAIL").insert_header(("Authorization", admin.clone())).to_request();
+        let users: Vec<AdminUserView> = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].email, "vk@gmail.com");
+        assert!(users[0].roles.is_empty());
+
+        // roles are read from the account, the tokens issued before the change follow it
+        let req = TestRequest::post().uri("/admin/users/vk@gmail.com/roles/owner").insert_header(("Authorization", admin.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 400);
+
+        let req = TestRequest::post().uri("/admin/users/vk@gmail.com/roles/admin").insert_header(("Authorization", admin.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::get().uri("/admin/users").insert_header(("Authorization", user.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::delete().uri("/admin/users/vk@gmail.com/roles/admin").insert_header(("Authorization", admin.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::get().uri("/admin/users").insert_header(("Authorization", user.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 403);
+
+        let req = TestRequest::delete().uri("/admin/users/admin@gmail.com/roles/admin").insert_header(("Authorization", admin.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 400);
+
+        let req = TestRequest::post().uri("/admin/users/admin@gmail.com/disable").insert_header(("Authorization", admin.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 400);
+
+        let req = TestRequest::post().uri("/authed/feed").insert_header(("Authorization", user.clone())).to_request();
+        let feed: FeedUrl = test::call_and_read_body_json(&app, req).await;
+        let feed = feed.url.split_once("/feeds/").map(|(_, p)| format!("/feeds/{}", p)).unwrap();
+
+        let req = TestRequest::post().uri("/admin/users/vk@gmail.com/disable").insert_header(("Authorization", admin.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        // nor is their feed published
+        let req = TestRequest::get().uri(&feed).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 404);
+
+        // the token is still valid, the account behind it is not
+        let req = TestRequest::get().uri("/authed/todos").insert_header(("Authorization", user.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 403);
+
+        let req = TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 403);
+
+        let req = TestRequest::post().uri("/admin/users/vk@gmail.com/enable").insert_header(("Authorization", admin.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::get().uri(&feed).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        // leftovers the account must not leave behind
+        let req = TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"wrong".to_string()}).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 400);
+
+        let req = TestRequest::post().uri("/user/forgot-password").set_json(ForgotPasswordInput{email:"vk@gmail.com".to_string()}).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::delete().uri("/admin/users/vk@gmail.com").insert_header(("Authorization", admin.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        {
+            let combined = state.overall_state.lock().unwrap();
+            assert!(combined.reset_tokens.iter().all(|t| t.user_email != "vk@gmail.com"));
+            assert!(combined.failed_attempts.iter().all(|a| a.key != "account:vk@gmail.com"));
+        }
+
+        let req = TestRequest::get().uri("/admin/users").insert_header(("Authorization", admin)).to_request();
+        let users: Vec<AdminUserView> = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].roles, vec!["admin"]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use actix_web::{post, web::{Data, Json}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use store::todo::{Todo, TodoIds};
+
+use crate::{scopes, GlobalState};
+
+/// Points at an existing todo by id, or at one created earlier in the same batch by its `"$ref"`.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum TodoRef{
+    Id(u32),
+    Ref(String),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation{
+    Create{
+        #[serde(rename = "ref")]
+        reference: Option<String>,
+        title: String,
+        done: bool,
+    },
+    Update{id: TodoRef, title: String, done: bool},
+    Patch{id: TodoRef, title: Option<String>, done: Option<bool>},
+    Delete{id: TodoRef},
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BatchInput{
+    pub operations: Vec<BatchOperation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus{
+    Ok,
+    Failed,
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchResult{
+    pub index: usize,
+    pub status: BatchStatus,
+    pub id: Option<u32>,
+    pub todo: Option<Todo>,
+    pub message: Option<String>,
+}
+
+/// `committed` is false when any operation failed, in which case none of them were applied.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchResponse{
+    pub committed: bool,
+    pub results: Vec<BatchResult>,
+}
+
+fn resolve(id: &TodoRef, refs: &HashMap<String, u32>) -> Result<u32, String>{
+    match id {
+        TodoRef::Id(id) => Ok(*id),
+        TodoRef::Ref(name) => {
+            let name = name.strip_prefix('$').unwrap_or(name);
+
+            match refs.get(name) {
+                Some(id) => Ok(*id),
+                None => Err(format!("Unknown ref '{}'", name)),
+            }
+        },
+    }
+}
+
+fn apply(operation: &BatchOperation, email: &str, refs: &mut HashMap<String, u32>, todos: &mut Vec<Todo>, ids: &mut TodoIds) -> Result<(u32, Option<Todo>), String>{
+    match operation {
+        BatchOperation::Create{reference, title, done} => {
+            let todo = Todo::add_todo(title.clone(), *done, email.to_string(), todos, ids);
+
+            if let Some(reference) = reference {
+                if refs.insert(reference.clone(), todo.id).is_some() {
+                    return Err(format!("Ref '{}' is already used in this batch", reference));
+                }
+            }
+
+            Ok((todo.id, Some(todo)))
+        },
+        BatchOperation::Update{id, title, done} => {
+            let id = resolve(id, refs)?;
+            Todo::update_todo(id, email.to_string(), title.clone(), *done, todos)?;
+            Ok((id, Todo::get_todo(id, todos)))
+        },
+        BatchOperation::Patch{id, title, done} => {
+            let id = resolve(id, refs)?;
+            let todo = Todo::patch_todo(id, email.to_string(), title.clone(), *done, todos)?;
+            Ok((id, Some(todo)))
+        },
+        BatchOperation::Delete{id} => {
+            let id = resolve(id, refs)?;
+            Todo::delete_todos(&[id], email.to_string(), todos)?;
+            Ok((id, None))
+        },
+    }
+}
+
+#[post("/batch", wrap = "scopes::require(scopes::TODOS_WRITE)")]
+pub async fn batch(req:HttpRequest, data:Data<GlobalState>, input:Json<BatchInput>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    // Run against a copy and only swap it in once every operation succeeded
+    let mut todos = state.todos.clone();
+    let mut refs = HashMap::new();
+    let mut results = vec![];
+    let mut failed = false;
+
+    for (index, operation) in input.operations.iter().enumerate() {
+        if failed {
+            results.push(BatchResult{index, status: BatchStatus::Skipped, id: None, todo: None, message: None});
+            continue;
+        }
+
+        match apply(operation, &email, &mut refs, &mut todos, &mut state.todo_ids) {
+            Ok((id, todo)) => {
+                results.push(BatchResult{index, status: BatchStatus::Ok, id: Some(id), todo, message: None});
+            },
+            Err(e) => {
+                failed = true;
+                results.push(BatchResult{index, status: BatchStatus::Failed, id: None, todo: None, message: Some(e)});
+            },
+        }
+    }
+
+    if failed {
+        return HttpResponse::BadRequest().json(BatchResponse{committed: false, results});
+    }
+
+    state.todos = todos;
+
+    HttpResponse::Ok().json(BatchResponse{committed: true, results})
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+    use store::todo::Todo;
+
+    use crate::{handlers::{batch::{BatchResponse, BatchStatus}, user::{AppResponse, SigninInput, SignupInput}}, init_app, prepare_global_state};
+
+    #[actix_web::test]
+    pub async fn should_run_batch(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"batch1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"batch1@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = actix_web::test::read_body_json(res).await;
+        let token = res.data;
+
+        let batch = serde_json::json!({"operations":[
+            {"op":"create", "ref":"gym", "title":"Go to Gym", "done":false},
+            {"op":"create", "ref":"movie", "title":"Go to Movie", "done":false},
+            {"op":"patch", "id":"$gym", "done":true},
+            {"op":"update", "id":"$movie", "title":"Go to Theatre", "done":false},
+            {"op":"create", "title":"Read", "done":false},
+            {"op":"delete", "id":"$movie"},
+        ]});
+
+        let res = TestRequest::post()
+        .uri("/authed/batch").set_json(batch)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+        let res: BatchResponse = actix_web::test::read_body_json(res).await;
+        assert!(res.committed);
+        assert!(res.results.iter().all(|r| r.status == BatchStatus::Ok));
+        assert!(res.results[2].todo.as_ref().unwrap().done);
+        assert_eq!(res.results[3].todo.as_ref().unwrap().title, "Go to Theatre");
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", token))
+        .send_request(&app).await;
+
+        let res: Vec<Todo> = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].title, "Go to Gym");
+        assert_eq!(res[1].title, "Read");
+    }
+
+    #[actix_web::test]
+    pub async fn should_roll_back_failed_batch(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"batch2@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"batch2@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = actix_web::test::read_body_json(res).await;
+        let token = res.data;
+
+        let batch = serde_json::json!({"operations":[
+            {"op":"create", "ref":"gym", "title":"Go to Gym", "done":false},
+            {"op":"patch", "id":"$nope", "done":true},
+            {"op":"create", "title":"Read", "done":false},
+        ]});
+
+        let res = TestRequest::post()
+        .uri("/authed/batch").set_json(batch)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 400);
+        let res: BatchResponse = actix_web::test::read_body_json(res).await;
+        assert!(!res.committed);
+        assert_eq!(res.results[0].status, BatchStatus::Ok);
+        assert_eq!(res.results[1].status, BatchStatus::Failed);
+        assert_eq!(res.results[1].message.as_deref(), Some("Unknown ref 'nope'"));
+        assert_eq!(res.results[2].status, BatchStatus::Skipped);
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", token))
+        .send_request(&app).await;
+
+        let res: Vec<Todo> = actix_web::test::read_body_json(res).await;
+        assert!(res.is_empty());
+    }
+
+}
@@ -0,0 +1,931 @@
+//! A CalDAV (RFC 4791) subset so native task apps can sync todos both ways.
+//!
+//! ```text
+//! /caldav/                         principal discovery
+//! /caldav/{email}/                 calendar home
+//! /caldav/{email}/tasks/           the user's todos as one VTODO collection
+//! /caldav/{email}/tasks/{uid}.ics  a single todo
+//! ```
+//!
+//! Clients authenticate with HTTP Basic, using the same email and password as `/user/signin`.
+//! Accounts with two-factor authentication use a personal access token as the password instead,
+//! with `todos:read` to sync and `todos:write` to change todos.
+
+use actix_web::{http::{header, Method, StatusCode}, web::{self, Bytes, Data, Path}, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use store::{access_token::AccessToken, formats::{ical, TodoRecord}, lockout::FailedAttempts, mfa::TotpEnrollment, todo::Todo, user::User};
+
+use crate::{handlers::{access_token::TOKEN_PREFIX, user::{count_failure, not_throttled, signin_keys, throttled}}, hashing, scopes, utils::{hash_token, percent_decode, percent_encode}, GlobalState};
+
+const COLLECTION: &str = "tasks";
+const REALM: &str = "Basic realm=\"rust-int\"";
+const XML: &str = "application/xml; charset=utf-8";
+// RFC 6578 wants sync tokens to be URIs
+const SYNC_TOKEN_BASE: &str = "http://rust-int/ns/sync/";
+
+fn propfind() -> Method{
+    Method::from_bytes(b"PROPFIND").unwrap()
+}
+
+fn report() -> Method{
+    Method::from_bytes(b"REPORT").unwrap()
+}
+
+pub fn config(cfg: &mut web::ServiceConfig){
+    cfg.service(
+        web::scope("/caldav")
+        .service(web::resource(["", "/"])
+            .route(web::method(propfind()).to(principal))
+            .route(web::method(Method::OPTIONS).to(options)))
+        .service(web::resource(["/{user}", "/{user}/"])
+            .route(web::method(propfind()).to(home))
+            .route(web::method(Method::OPTIONS).to(options)))
+        .service(web::resource(["/{user}/tasks", "/{user}/tasks/"])
+            .route(web::method(propfind()).to(collection))
+            .route(web::method(report()).to(collection_report))
+            .route(web::method(Method::OPTIONS).to(options)))
+        .service(web::resource("/{user}/tasks/{name}")
+            .route(web::get().to(get_resource))
+            .route(web::put().to(put_resource))
+            .route(web::delete().to(delete_resource))
+            .route(web::method(propfind()).to(resource_propfind))
+            .route(web::method(Method::OPTIONS).to(options)))
+    );
+}
+
+fn unauthorized() -> HttpResponse{
+    HttpResponse::Unauthorized()
+    .insert_header((header::WWW_AUTHENTICATE, REALM))
+    .finish()
+}
+
+/// Checks the Basic credentials and returns the user's email. Wrong ones count towards the same
+/// lockout as `/user/signin`.
+async fn authenticate(req: &HttpRequest, data: &GlobalState) -> Result<String, HttpResponse>{
+    let credentials = req.headers().get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Basic "))
+        .and_then(|h| STANDARD.decode(h.trim()).ok())
+        .and_then(|h| String::from_utf8(h).ok());
+
+    if credentials.is_none(){
+        return Err(unauthorized());
+    }
+
+    let credentials = credentials.unwrap();
+    let parts = credentials.split_once(':');
+
+    if parts.is_none(){
+        return Err(unauthorized());
+    }
+
+    let (email, password) = parts.unwrap();
+    let keys = signin_keys(req, email);
+
+    let user = {
+        let state_result = data.overall_state.lock();
+
+        if state_result.is_err(){
+            return Err(HttpResponse::InternalServerError().finish());
+        }
+
+        let state = state_result.unwrap();
+
+        if let Some(res) = throttled(&state, &keys, Utc::now().timestamp()) {
+            return Err(res);
+        }
+
+        User::get_user(&state.users, &email.to_string())
+    };
+
+    // a personal access token in place of the password is looked up below, under the lock
+    let is_token = password.starts_with(TOKEN_PREFIX);
+
+    let password_valid = match &user {
+        Some(user) if !is_token => hashing::verify_password_if(data, &user.password, password, || not_throttled(data, &keys)).await.unwrap_or(false),
+        // as slow as a wrong password, so the answer does not tell which accounts exist
+        None if !is_token => hashing::verify_dummy(data, password).await,
+        _ => false,
+    };
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return Err(HttpResponse::InternalServerError().finish());
+    }
+
+    let mut state = state_result.unwrap();
+    let now = Utc::now().timestamp();
+
+    // guesses sent at once all got past the first check
+    if let Some(res) = throttled(&state, &keys, now) {
+        return Err(res);
+    }
+
+    let access_token = match &user {
+        Some(user) if is_token => AccessToken::find(&state.access_tokens, &hash_token(password), now).filter(|t| t.user_email == user.email),
+        _ => None,
+    };
+
+    if !password_valid && access_token.is_none() {
+        count_failure(&mut state, &data.config.lockout, &keys);
+        return Err(unauthorized());
+    }
+
+    let user = user.unwrap();
+
+    // CalDAV clients can not be let through selectively, so it is all or nothing
+    if user.disabled || (data.config.verification.required && !user.verified) {
+        return Err(HttpResponse::Forbidden().finish());
+    }
+
+    match access_token {
+        Some(access_token) => {
+            let required_scope = match *req.method() {
+                Method::PUT | Method::DELETE => scopes::TODOS_WRITE,
+                _ => scopes::TODOS_READ,
+            };
+
+            AccessToken::touch(&mut state.access_tokens, &access_token.id, now);
+
+            if !access_token.scopes.iter().any(|s| s == required_scope) {
+                return Err(HttpResponse::Forbidden().body(format!("The token needs the {} scope", required_scope)));
+            }
+        },
+        // Basic auth has no room for a code, the password alone must not bypass the second factor
+        None if TotpEnrollment::is_enabled(&state.totp_enrollments, &user.email) => {
+            return Err(HttpResponse::Forbidden().body("Two-factor authentication is on, use a personal access token as the password"));
+        },
+        None => {},
+    }
+
+    FailedAttempts::reset(&mut state.failed_attempts, &keys.0);
+
+    Ok(user.email)
+}
+
+// The user in the path has to be the one who authenticated.
+async fn authenticate_owner(req: &HttpRequest, data: &GlobalState, user: &str) -> Result<String, HttpResponse>{
+    let email = authenticate(req, data).await?;
+
+    if email != user {
+        return Err(HttpResponse::Forbidden().finish());
+    }
+
+    Ok(email)
+}
+
+fn user_records(data: &GlobalState, email: &str) -> Result<Vec<TodoRecord>, HttpResponse>{
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return Err(HttpResponse::InternalServerError().finish());
+    }
+
+    let mut state = state_result.unwrap();
+    let todos = Todo::get_user_todos(email.to_string(), &mut state.todos);
+
+    Ok(todos.iter().map(TodoRecord::from_todo).collect())
+}
+
+fn resource_name(record: &TodoRecord) -> String{
+    format!("{}.ics", ical::uid_for(record))
+}
+
+// Derived from the todo's fields, not the served body, whose DTSTAMP changes on every request.
+fn etag(record: &TodoRecord) -> String{
+    let json = serde_json::to_string(record).unwrap_or_default();
+    format!("\"{}\"", &hash_token(&json)[..32])
+}
+
+// Changes whenever a todo in the collection does
+fn collection_version(records: &[TodoRecord]) -> String{
+    let etags: String = records.iter().map(etag).collect();
+    hash_token(&etags)[..32].to_string()
+}
+
+fn ctag(records: &[TodoRecord]) -> String{
+    format!("\"{}\"", collection_version(records))
+}
+
+fn sync_token(records: &[TodoRecord]) -> String{
+    format!("{}{}", SYNC_TOKEN_BASE, collection_version(records))
+}
+
+fn dtstamp() -> String{
+    Utc::now().format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape_xml(text: &str) -> String{
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape_xml(text: &str) -> String{
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The text of every element whose local name is `href`, whatever namespace prefix the client uses.
+fn extract_hrefs(body: &str) -> Vec<String>{
+    let mut hrefs = vec![];
+    let mut rest = body;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        let name = tag.split_whitespace().next().unwrap_or("");
+        let local = name.rsplit(':').next().unwrap_or(name);
+
+        if local == "href" && !name.starts_with('/') && !tag.ends_with('/') {
+            let text = &rest[end.min(rest.len())..];
+            let text = text.strip_prefix('>').unwrap_or(text);
+            let close = text.find('<').unwrap_or(text.len());
+            hrefs.push(unescape_xml(text[..close].trim()));
+        }
+    }
+
+    hrefs
+}
+
+fn multistatus(responses: Vec<String>) -> HttpResponse{
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:multistatus xmlns:d=\"DAV:\" xmlns:c=\"urn:ietf:params:xml:ns:caldav\" xmlns:cs=\"http://calendarserver.org/ns/\">\n{}</d:multistatus>\n",
+        responses.concat()
+    );
+
+    HttpResponse::build(StatusCode::MULTI_STATUS)
+    .content_type(XML)
+    .body(body)
+}
+
+fn response(href: &str, props: &str) -> String{
+    format!(
+        "<d:response><d:href>{}</d:href><d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>\n",
+        escape_xml(href), props
+    )
+}
+
+fn depth(req: &HttpRequest) -> u8{
+    match req.headers().get("Depth").and_then(|h| h.to_str().ok()) {
+        Some("0") => 0,
+        _ => 1,
+    }
+}
+
+// Hrefs are URIs, so the email and the todo's UID are percent-encoded in them. Clients may
+// send their hrefs encoded differently, they are compared decoded.
+fn home_href(email: &str) -> String{
+    format!("/caldav/{}/", percent_encode(email))
+}
+
+fn collection_href(email: &str) -> String{
+    format!("/caldav/{}/{}/", percent_encode(email), COLLECTION)
+}
+
+fn resource_href(email: &str, name: &str) -> String{
+    format!("{}{}", collection_href(email), percent_encode(name))
+}
+
+fn collection_props(email: &str, records: &[TodoRecord]) -> String{
+    format!(
+        "<d:resourcetype><d:collection/><c:calendar/></d:resourcetype>\
+        <d:displayname>Todos</d:displayname>\
+        <c:supported-calendar-component-set><c:comp name=\"VTODO\"/></c:supported-calendar-component-set>\
+        <d:current-user-principal><d:href>{}</d:href></d:current-user-principal>\
+        <cs:getctag>{}</cs:getctag>\
+        <d:sync-token>{}</d:sync-token>",
+        escape_xml(&home_href(email)), escape_xml(&ctag(records)), escape_xml(&sync_token(records))
+    )
+}
+
+fn resource_props(record: &TodoRecord, calendar_data: bool) -> String{
+    let mut props = format!(
+        "<d:getetag>{}</d:getetag><d:getcontenttype>text/calendar; charset=utf-8; component=VTODO</d:getcontenttype><d:resourcetype/>",
+        escape_xml(&etag(record))
+    );
+
+    if calendar_data {
+        let calendar = ical::write_calendar(std::slice::from_ref(record), &dtstamp());
+        props.push_str(&format!("<c:calendar-data>{}</c:calendar-data>", escape_xml(&calendar)));
+    }
+
+    props
+}
+
+pub async fn options() -> HttpResponse{
+    HttpResponse::Ok()
+    .insert_header(("DAV", "1, 3, calendar-access"))
+    .insert_header((header::ALLOW, "OPTIONS, GET, PUT, DELETE, PROPFIND, REPORT"))
+    .finish()
+}
+
+pub async fn principal(req: HttpRequest, data: Data<GlobalState>) -> HttpResponse{
+    let email = match authenticate(&req, &data).await {
+        Ok(email) => email,
+        Err(res) => return res,
+    };
+
+    let props = format!(
+        "<d:current-user-principal><d:href>{0}</d:href></d:current-user-principal>\
+        <c:calendar-home-set><d:href>{0}</d:href></c:calendar-home-set>",
+        escape_xml(&home_href(&email))
+    );
+
+    multistatus(vec![response("/caldav/", &props)])
+}
+
+pub async fn home(req: HttpRequest, data: Data<GlobalState>, path: Path<String>) -> HttpResponse{
+    let email = match authenticate_owner(&req, &data, &path.into_inner()).await {
+        Ok(email) => email,
+        Err(res) => return res,
+    };
+
+    let props = format!(
+        "<d:resourcetype><d:collection/></d:resourcetype>\
+        <d:current-user-principal><d:href>{0}</d:href></d:current-user-principal>\
+        <c:calendar-home-set><d:href>{0}</d:href></c:calendar-home-set>",
+        escape_xml(&home_href(&email))
+    );
+
+    let mut responses = vec![response(&home_href(&email), &props)];
+
+    if depth(&req) > 0 {
+        let records = match user_records(&data, &email) {
+            Ok(records) => records,
+            Err(res) => return res,
+        };
+
+        responses.push(response(&collection_href(&email), &collection_props(&email, &records)));
+    }
+
+    multistatus(responses)
+}
+
+pub async fn collection(req: HttpRequest, data: Data<GlobalState>, path: Path<String>) -> HttpResponse{
+    let email = match authenticate_owner(&req, &data, &path.into_inner()).await {
+        Ok(email) => email,
+        Err(res) => return res,
+    };
+
+    let records = match user_records(&data, &email) {
+        Ok(records) => records,
+        Err(res) => return res,
+    };
+
+    let mut responses = vec![response(&collection_href(&email), &collection_props(&email, &records))];
+
+    if depth(&req) > 0 {
+        for record in &records {
+            responses.push(response(&resource_href(&email, &resource_name(record)), &resource_props(record, false)));
+        }
+    }
+
+    multistatus(responses)
+}
+
+/// `calendar-multiget` returns the listed hrefs, `calendar-query` returns every todo, the
+/// collection only ever holds VTODOs so its component filter always matches.
+pub async fn collection_report(req: HttpRequest, data: Data<GlobalState>, path: Path<String>, body: Bytes) -> HttpResponse{
+    let email = match authenticate_owner(&req, &data, &path.into_inner()).await {
+        Ok(email) => email,
+        Err(res) => return res,
+    };
+
+    let body = String::from_utf8_lossy(&body);
+
+    let records = match user_records(&data, &email) {
+        Ok(records) => records,
+        Err(res) => return res,
+    };
+
+    // each href the client listed, with the path it names
+    let hrefs: Option<Vec<(String, String)>> = if body.contains("calendar-multiget") {
+        Some(extract_hrefs(&body).into_iter().map(|href| (percent_decode(&href).unwrap_or_else(|| href.clone()), href)).collect())
+    } else if body.contains("calendar-query") {
+        None
+    } else {
+        return HttpResponse::BadRequest().body("Only calendar-query and calendar-multiget are supported");
+    };
+
+    let mut responses = vec![];
+    let mut found = vec![];
+
+    for record in &records {
+        let name = resource_name(record);
+        let path = format!("/caldav/{}/{}/{}", email, COLLECTION, name);
+
+        if hrefs.as_ref().is_some_and(|hrefs| !hrefs.iter().any(|(wanted, _)| *wanted == path)) {
+            continue;
+        }
+
+        responses.push(response(&resource_href(&email, &name), &resource_props(record, true)));
+        found.push(path);
+    }
+
+    for (path, href) in hrefs.unwrap_or_default() {
+        if !found.contains(&path) {
+            responses.push(format!(
+                "<d:response><d:href>{}</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>\n",
+                escape_xml(&href)
+            ));
+        }
+    }
+
+    multistatus(responses)
+}
+
+fn find_record(records: Vec<TodoRecord>, name: &str) -> Option<TodoRecord>{
+    records.into_iter().find(|r| resource_name(r) == name)
+}
+
+pub async fn resource_propfind(req: HttpRequest, data: Data<GlobalState>, path: Path<(String, String)>) -> HttpResponse{
+    let (user, name) = path.into_inner();
+
+    let email = match authenticate_owner(&req, &data, &user).await {
+        Ok(email) => email,
+        Err(res) => return res,
+    };
+
+    let records = match user_records(&data, &email) {
+        Ok(records) => records,
+        Err(res) => return res,
+    };
+
+    match find_record(records, &name) {
+        Some(record) => {
+            multistatus(vec![response(&resource_href(&email, &name), &resource_props(&record, false))])
+        },
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+pub async fn get_resource(req: HttpRequest, data: Data<GlobalState>, path: Path<(String, String)>) -> HttpResponse{
+    let (user, name) = path.into_inner();
+
+    let email = match authenticate_owner(&req, &data, &user).await {
+        Ok(email) => email,
+        Err(res) => return res,
+    };
+
+    let records = match user_records(&data, &email) {
+        Ok(records) => records,
+        Err(res) => return res,
+    };
+
+    match find_record(records, &name) {
+        Some(record) => HttpResponse::Ok()
+            .content_type(ical::CONTENT_TYPE)
+            .insert_header((header::ETAG, etag(&record)))
+            .body(ical::write_calendar(std::slice::from_ref(&record), &dtstamp())),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+// If-Match must name the current ETag, If-None-Match: * only allows creating.
+fn preconditions_hold(req: &HttpRequest, existing: Option<&TodoRecord>) -> bool{
+    let if_match = req.headers().get(header::IF_MATCH).and_then(|h| h.to_str().ok());
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).and_then(|h| h.to_str().ok());
+
+    if let Some(if_match) = if_match {
+        match existing {
+            Some(record) if if_match == "*" || if_match == etag(record) => {},
+            _ => return false,
+        }
+    }
+
+    if if_none_match == Some("*") && existing.is_some() {
+        return false;
+    }
+
+    true
+}
+
+/// Creates or replaces a todo from a calendar holding one VTODO. A new resource keeps the
+/// name the client chose as its UID, so it is served back under the same URL.
+pub async fn put_resource(req: HttpRequest, data: Data<GlobalState>, path: Path<(String, String)>, body: Bytes) -> HttpResponse{
+    let (user, name) = path.into_inner();
+
+    let email = match authenticate_owner(&req, &data, &user).await {
+        Ok(email) => email,
+        Err(res) => return res,
+    };
+
+    if !name.ends_with(".ics") {
+        return HttpResponse::BadRequest().body("Resource names end in .ics");
+    }
+
+    let parsed = ical::parse(&body);
+
+    let mut record = match parsed {
+        Ok((mut records, errors)) if errors.is_empty() && records.len() == 1 => records.remove(0),
+        Ok((_, errors)) if !errors.is_empty() => return HttpResponse::BadRequest().body(errors[0].message.clone()),
+        Ok(_) => return HttpResponse::BadRequest().body("Expected exactly one VTODO"),
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let mut state = state_result.unwrap();
+    let state = &mut *state;
+    let todos = &mut state.todos;
+
+    let existing = Todo::get_user_todos(email.clone(), todos)
+        .iter()
+        .map(TodoRecord::from_todo)
+        .find(|r| resource_name(r) == name);
+
+    if !preconditions_hold(&req, existing.as_ref()) {
+        return HttpResponse::PreconditionFailed().finish();
+    }
+
+    let created = existing.is_none();
+
+    match existing {
+        Some(existing) => {
+            ical::keep_unmapped(&mut record, &existing);
+            record.id = existing.id;
+            record.uid = existing.uid;
+        },
+        None => {
+            record.id = None;
+            record.uid = Some(name.trim_end_matches(".ics").to_string());
+        },
+    }
+
+    let (todo, _) = Todo::import_todo(record.into_todo(email.clone()), email, todos, &mut state.todo_ids);
+    let etag = etag(&TodoRecord::from_todo(&todo));
+
+    let mut res = if created { HttpResponse::Created() } else { HttpResponse::NoContent() };
+    res.insert_header((header::ETAG, etag)).finish()
+}
+
+pub async fn delete_resource(req: HttpRequest, data: Data<GlobalState>, path: Path<(String, String)>) -> HttpResponse{
+    let (user, name) = path.into_inner();
+
+    let email = match authenticate_owner(&req, &data, &user).await {
+        Ok(email) => email,
+        Err(res) => return res,
+    };
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let mut state = state_result.unwrap();
+    let todos = &mut state.todos;
+
+    let existing = Todo::get_user_todos(email.clone(), todos)
+        .iter()
+        .map(TodoRecord::from_todo)
+        .find(|r| resource_name(r) == name);
+
+    if existing.is_none(){
+        return HttpResponse::NotFound().finish();
+    }
+
+    let existing = existing.unwrap();
+
+    if !preconditions_hold(&req, Some(&existing)) {
+        return HttpResponse::PreconditionFailed().finish();
+    }
+
+    match Todo::delete_todos(&[existing.id.unwrap_or(0)], email, todos) {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(_) => HttpResponse::NotFound().finish(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    use data_encoding::BASE32_NOPAD;
+
+    use crate::{config::AppConfig, handlers::{access_token::{CreateTokenInput, CreatedTokenResponse}, caldav::{propfind, report}, mfa::{CodeInput, EnrollResponse}, user::{SigninInput, SignupInput, TokenResponse}}, init_app, prepare_global_state, prepare_global_state_with, totp};
+
+    const VTODO: &str = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//EN\r\nBEGIN:VTODO\r\nUID:3f1e2d\r\nDTSTAMP:20250101T000000Z\r\nSUMMARY:Buy milk\r\nSTATUS:NEEDS-ACTION\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+
+    fn basic(email: &str, password: &str) -> String{
+        format!("Basic {}", STANDARD.encode(format!("{}:{}", email, password)))
+    }
+
+    #[actix_web::test]
+    pub async fn should_sync_over_caldav(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"dav1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let auth = basic("dav1@gmail.com", "Random1234");
+        let collection = "/caldav/dav1@gmail.com/tasks/";
+        let resource = "/caldav/dav1@gmail.com/tasks/3f1e2d.ics";
+
+        let res = TestRequest::default().method(propfind()).uri("/caldav/")
+        .append_header(("Authorization", auth.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 207);
+        let body = test::read_body(res).await;
+        assert!(std::str::from_utf8(&body).unwrap().contains("<c:calendar-home-set><d:href>/caldav/dav1%40gmail.com/</d:href>"));
+
+        let res = TestRequest::default().method(propfind()).uri(collection)
+        .append_header(("Authorization", auth.clone()))
+        .append_header(("Depth", "0"))
+        .send_request(&app).await;
+
+        let body = test::read_body(res).await;
+        let body = std::str::from_utf8(&body).unwrap().to_string();
+        let empty_ctag = body.split("<cs:getctag>").nth(1).unwrap().split('<').next().unwrap().to_string();
+        assert!(body.contains("<d:sync-token>http://rust-int/ns/sync/"));
+
+        let res = TestRequest::put().uri(resource)
+        .append_header(("Authorization", auth.clone()))
+        .append_header(("If-None-Match", "*"))
+        .insert_header(("Content-Type", "text/calendar"))
+        .set_payload(VTODO)
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 201);
+        let etag = res.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+
+        // the todo is visible to the rest of the API
+        let res = TestRequest::post().uri("/user/signin")
+        .set_json(serde_json::json!({"email":"dav1@gmail.com", "password":"Random1234"}))
+        .send_request(&app).await;
+        let token: serde_json::Value = test::read_body_json(res).await;
+
+        let res = TestRequest::get().uri("/authed/todos")
+        .append_header(("Authorization", token["data"].as_str().unwrap()))
+        .send_request(&app).await;
+        let todos: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(todos[0]["title"], "Buy milk");
+
+        let res = TestRequest::default().method(propfind()).uri(collection)
+        .append_header(("Authorization", auth.clone()))
+        .append_header(("Depth", "1"))
+        .send_request(&app).await;
+
+        let body = test::read_body(res).await;
+        let body = std::str::from_utf8(&body).unwrap().to_string();
+        assert!(body.contains("<d:href>/caldav/dav1%40gmail.com/tasks/3f1e2d.ics</d:href>"));
+        assert!(body.contains(&format!("<d:getetag>{}</d:getetag>", etag.replace('"', "&quot;"))));
+        assert!(!body.contains(&format!("<cs:getctag>{}</cs:getctag>", empty_ctag)));
+
+        let query = r#"<?xml version="1.0"?><c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><d:prop><d:getetag/><c:calendar-data/></d:prop><c:filter><c:comp-filter name="VCALENDAR"><c:comp-filter name="VTODO"/></c:comp-filter></c:filter></c:calendar-query>"#;
+
+        let res = TestRequest::default().method(report()).uri(collection)
+        .append_header(("Authorization", auth.clone()))
+        .set_payload(query)
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 207);
+        let body = test::read_body(res).await;
+        assert!(std::str::from_utf8(&body).unwrap().contains("SUMMARY:Buy milk"));
+
+        let multiget = format!(r#"<C:calendar-multiget xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav"><D:prop><D:getetag/></D:prop><D:href>{}</D:href><D:href>/caldav/dav1@gmail.com/tasks/missing.ics</D:href></C:calendar-multiget>"#, resource);
+
+        let res = TestRequest::default().method(report()).uri(collection)
+        .append_header(("Authorization", auth.clone()))
+        .set_payload(multiget)
+        .send_request(&app).await;
+
+        let body = test::read_body(res).await;
+        let body = std::str::from_utf8(&body).unwrap().to_string();
+        assert!(body.contains("SUMMARY:Buy milk"));
+        assert!(body.contains("404 Not Found"));
+
+        // a stale ETag cannot overwrite
+        let res = TestRequest::put().uri(resource)
+        .append_header(("Authorization", auth.clone()))
+        .append_header(("If-Match", "\"stale\""))
+        .set_payload(VTODO.replace("NEEDS-ACTION", "COMPLETED"))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 412);
+
+        let res = TestRequest::put().uri(resource)
+        .append_header(("Authorization", auth.clone()))
+        .append_header(("If-Match", etag.clone()))
+        .set_payload(VTODO.replace("NEEDS-ACTION", "COMPLETED"))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 204);
+        assert_ne!(res.headers().get("ETag").unwrap().to_str().unwrap(), etag);
+
+        let res = TestRequest::get().uri(resource)
+        .append_header(("Authorization", auth.clone()))
+        .send_request(&app).await;
+
+        let body = test::read_body(res).await;
+        let body = std::str::from_utf8(&body).unwrap().to_string();
+        assert!(body.contains("UID:3f1e2d\r\n"));
+        assert!(body.contains("STATUS:COMPLETED\r\n"));
+
+        // what the VTODO can not carry survives a client writing the todo back
+        {
+            let todos = &mut state.overall_state.lock().unwrap().todos;
+            todos[0].extras.insert(String::from("estimate"), String::from("2h"));
+            todos[0].extras.insert(String::from("due"), String::from("2025-03-14"));
+        }
+
+        let res = TestRequest::put().uri(resource)
+        .append_header(("Authorization", auth.clone()))
+        .set_payload(VTODO.replace("Buy milk", "Buy oat milk"))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 204);
+
+        {
+            let todos = &state.overall_state.lock().unwrap().todos;
+            assert_eq!(todos[0].title, "Buy oat milk");
+            assert_eq!(todos[0].extras.get("estimate").map(String::as_str), Some("2h"));
+            assert!(!todos[0].extras.contains_key("due"));
+        }
+
+        let res = TestRequest::delete().uri(resource)
+        .append_header(("Authorization", auth.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 204);
+
+        let res = TestRequest::get().uri(resource)
+        .append_header(("Authorization", auth.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 404);
+
+        // a name the client chose that is not safe in a URI is listed encoded, and found again
+        let res = TestRequest::put().uri("/caldav/dav1@gmail.com/tasks/a%20b.ics")
+        .append_header(("Authorization", auth.clone()))
+        .set_payload(VTODO)
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 201);
+
+        let res = TestRequest::default().method(propfind()).uri(collection)
+        .append_header(("Authorization", auth.clone()))
+        .send_request(&app).await;
+
+        let body = test::read_body(res).await;
+        assert!(std::str::from_utf8(&body).unwrap().contains("<d:href>/caldav/dav1%40gmail.com/tasks/a%20b.ics</d:href>"));
+
+        let multiget = r#"<C:calendar-multiget xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav"><D:prop><D:getetag/></D:prop><D:href>/caldav/dav1%40gmail.com/tasks/a%20b.ics</D:href></C:calendar-multiget>"#;
+
+        let res = TestRequest::default().method(report()).uri(collection)
+        .append_header(("Authorization", auth))
+        .set_payload(multiget)
+        .send_request(&app).await;
+
+        let body = test::read_body(res).await;
+        let body = std::str::from_utf8(&body).unwrap().to_string();
+        assert!(body.contains("SUMMARY:Buy milk"));
+        assert!(!body.contains("404 Not Found"));
+    }
+
+    #[actix_web::test]
+    pub async fn should_reject_bad_caldav_credentials(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        for email in ["dav2@gmail.com", "dav3@gmail.com"] {
+            let input = SignupInput{
+                email:email.to_string(),
+                name:"VK".to_string(),
+                password:"Random1234".to_string(),
+            };
+
+            TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+        }
+
+        let res = TestRequest::default().method(propfind()).uri("/caldav/dav2@gmail.com/tasks/")
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 401);
+        assert!(res.headers().contains_key("WWW-Authenticate"));
+
+        let res = TestRequest::default().method(propfind()).uri("/caldav/dav2@gmail.com/tasks/")
+        .append_header(("Authorization", basic("dav2@gmail.com", "wrong")))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 401);
+
+        // another user's collection is off limits
+        let res = TestRequest::default().method(propfind()).uri("/caldav/dav2@gmail.com/tasks/")
+        .append_header(("Authorization", basic("dav3@gmail.com", "Random1234")))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 403);
+    }
+
+    #[actix_web::test]
+    pub async fn should_require_token_with_two_factor(){
+        let state = prepare_global_state();
+        let app = test::init_service(init_app!(state)).await;
+
+        for email in ["dav4@gmail.com", "dav5@gmail.com"] {
+            let input = SignupInput{email:email.to_string(), name:"VK".to_string(), password:"Random1234".to_string()};
+            TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+        }
+
+        let req = TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"dav4@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+        let jwt = test::call_and_read_body_json::<_, _, TokenResponse>(&app, req).await.data;
+
+        let req = TestRequest::post().uri("/authed/mfa/enroll").insert_header(("Authorization", jwt.clone())).to_request();
+        let enrollment: EnrollResponse = test::call_and_read_body_json(&app, req).await;
+
+        let secret = BASE32_NOPAD.decode(enrollment.secret.as_bytes()).unwrap();
+        let code = format!("{:06}", totp::hotp(&secret, totp::step_at(chrono::Utc::now().timestamp()) as u64));
+
+        let req = TestRequest::post().uri("/authed/mfa/confirm").insert_header(("Authorization", jwt.clone()))
+            .set_json(CodeInput{code}).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 200);
+
+        let collection = "/caldav/dav4@gmail.com/tasks/";
+
+        let res = TestRequest::default().method(propfind()).uri(collection)
+        .append_header(("Authorization", basic("dav4@gmail.com", "Random1234")))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 403);
+
+        let req = TestRequest::post().uri("/authed/tokens").insert_header(("Authorization", jwt))
+            .set_json(CreateTokenInput{name:"tasks app".to_string(), scopes:vec!["todos:read".to_string()], expires_in:None}).to_request();
+        let pat = test::call_and_read_body_json::<_, _, CreatedTokenResponse>(&app, req).await.token;
+
+        let res = TestRequest::default().method(propfind()).uri(collection)
+        .append_header(("Authorization", basic("dav4@gmail.com", &pat)))
+        .append_header(("Depth", "0"))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 207);
+
+        let res = TestRequest::put().uri("/caldav/dav4@gmail.com/tasks/3f1e2d.ics")
+        .append_header(("Authorization", basic("dav4@gmail.com", &pat)))
+        .set_payload(VTODO)
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 403);
+
+        // the token only works for its owner
+        let res = TestRequest::default().method(propfind()).uri("/caldav/dav5@gmail.com/tasks/")
+        .append_header(("Authorization", basic("dav5@gmail.com", &pat)))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    pub async fn should_share_lockout_with_signin(){
+        let mut config = AppConfig::dev();
+        config.lockout.free_attempts = 10;
+        config.lockout.threshold = 3;
+
+        let state = prepare_global_state_with(config);
+        let app = test::init_service(init_app!(state)).await;
+
+        let input = SignupInput{email:"dav6@gmail.com".to_string(), name:"VK".to_string(), password:"Random1234".to_string()};
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let propfind_as = |password: &str| TestRequest::default().method(propfind()).uri("/caldav/dav6@gmail.com/tasks/")
+            .append_header(("Authorization", basic("dav6@gmail.com", password)))
+            .append_header(("Depth", "0"))
+            .to_request();
+
+        for _ in 0..3 {
+            assert_eq!(test::call_service(&app, propfind_as("Wrong1234")).await.status().as_u16(), 401);
+        }
+
+        // locked, even with the right password and on the other way in
+        assert_eq!(test::call_service(&app, propfind_as("Random1234")).await.status().as_u16(), 429);
+
+        let req = TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"dav6@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 429);
+    }
+}
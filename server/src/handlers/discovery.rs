@@ -0,0 +1,162 @@
+use actix_web::{get, http::header, web::Data, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::{scopes::GRANTABLE, utils::Jwk, GlobalState};
+
+#[derive(Serialize, Debug)]
+pub struct JwkSet{
+    pub keys: Vec<Jwk>,
+}
+
+/// The subset of OpenID Connect discovery metadata other services need to verify our tokens,
+/// and apps need to reach the OAuth2 endpoints.
+#[derive(Serialize, Debug)]
+pub struct DiscoveryDocument{
+    pub issuer: String,
+    pub jwks_uri: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub response_types_supported: Vec<&'static str>,
+    pub grant_types_supported: Vec<&'static str>,
+    pub code_challenge_methods_supported: Vec<&'static str>,
+    pub token_endpoint_auth_methods_supported: Vec<&'static str>,
+    pub scopes_supported: Vec<&'static str>,
+    pub id_token_signing_alg_values_supported: Vec<&'static str>,
+    pub subject_types_supported: Vec<&'static str>,
+    pub claims_supported: Vec<&'static str>,
+}
+
+// keys change only on a restart, caches may hold them for a while
+const CACHE_CONTROL: &str = "public, max-age=300";
+
+/// Public keys of the RS256 and EdDSA keys that verify tokens. HMAC keys are never listed.
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(data:Data<GlobalState>) -> impl Responder {
+
+    match data.config.jwt.keys.jwks(Utc::now().timestamp()) {
+        Ok(keys) => HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, CACHE_CONTROL))
+            .json(JwkSet{keys}),
+        Err(e) => {
+            println!("could not publish jwks: {}", e);
+            HttpResponse::InternalServerError().json(String::from("Internal Server Error"))
+        },
+    }
+}
+
+#[get("/.well-known/openid-configuration")]
+pub async fn openid_configuration(req:HttpRequest, data:Data<GlobalState>) -> impl Responder {
+
+    let jwt = &data.config.jwt;
+
+    // an issuer that is a URL is where we are served from, otherwise use the request's host
+    let base = if jwt.issuer.starts_with("https://") || jwt.issuer.starts_with("http://") {
+        jwt.issuer.trim_end_matches('/').to_string()
+    } else {
+        let conn = req.connection_info();
+        format!("{}://{}", conn.scheme(), conn.host())
+    };
+
+    let algorithms = jwt.keys.public_algorithms(Utc::now().timestamp());
+
+    HttpResponse::Ok()
+    .insert_header((header::CACHE_CONTROL, CACHE_CONTROL))
+    .json(DiscoveryDocument{
+        issuer: jwt.issuer.clone(),
+        jwks_uri: format!("{}/.well-known/jwks.json", base),
+        authorization_endpoint: format!("{}/authed/oauth/authorize", base),
+        token_endpoint: format!("{}/oauth/token", base),
+        response_types_supported: vec!["code"],
+        grant_types_supported: vec!["authorization_code", "client_credentials"],
+        code_challenge_methods_supported: vec!["S256"],
+        token_endpoint_auth_methods_supported: vec!["client_secret_basic", "client_secret_post", "none"],
+        scopes_supported: GRANTABLE.to_vec(),
+        id_token_signing_alg_values_supported: algorithms,
+        subject_types_supported: vec!["public"],
+        claims_supported: vec!["sub", "iss", "aud", "exp", "iat"],
+    })
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+
+    use crate::{config::AppConfig, handlers::user::{AppResponse, SigninInput, SignupInput}, init_app, prepare_global_state_with, utils::{KeyAlg, KeyRing, SigningKey}};
+
+    fn key_file(name:&str) -> Option<String>{
+        Some(format!("{}/fixtures/keys/{}", env!("CARGO_MANIFEST_DIR"), name))
+    }
+
+    #[actix_web::test]
+    pub async fn should_publish_keys_that_verify_tokens(){
+        let mut config = AppConfig::dev();
+        config.jwt.issuer = String::from("https://todos.example.com");
+
+        let ed = SigningKey::from_pem_files("ed-1", KeyAlg::EdDSA, key_file("ed25519_private.pem"), key_file("ed25519_public.pem"), 0).unwrap();
+        let rsa = SigningKey::from_pem_files("rsa-1", KeyAlg::RS256, key_file("rsa_private.pem"), key_file("rsa_public.pem"), 0).unwrap();
+
+        let mut keys = KeyRing::with_key(ed);
+        keys.rotate_to(2_000, 100, SigningKey::hmac("hmac-1", "4f9c2a7e1b8d6f3a0c5e9b2d7a4f1c8e", 2_000));
+        keys.rotate_to(2_050, i64::MAX / 2, rsa);
+        assert_eq!(keys.keys.len(), 3);
+        config.jwt.keys = keys;
+
+        let state = prepare_global_state_with(config);
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let res = TestRequest::get().uri("/.well-known/jwks.json").send_request(&app).await;
+        assert!(res.status().is_success());
+
+        let res: serde_json::Value = test::read_body_json(res).await;
+        let keys = res["keys"].as_array().unwrap();
+
+        // the HMAC key still verifies but is secret, the Ed25519 key is past its deadline
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0]["kid"], "rsa-1");
+        assert_eq!(keys[0]["kty"], "RSA");
+        assert_eq!(keys[0]["alg"], "RS256");
+        assert_eq!(keys[0]["e"], "AQAB");
+        assert!(keys[0].get("secret").is_none());
+
+        let res = TestRequest::get().uri("/.well-known/openid-configuration").send_request(&app).await;
+        let res: serde_json::Value = test::read_body_json(res).await;
+
+        assert_eq!(res["issuer"], "https://todos.example.com");
+        assert_eq!(res["jwks_uri"], "https://todos.example.com/.well-known/jwks.json");
+        assert_eq!(res["id_token_signing_alg_values_supported"], serde_json::json!(["RS256"]));
+        assert_eq!(res["authorization_endpoint"], "https://todos.example.com/authed/oauth/authorize");
+        assert_eq!(res["token_endpoint"], "https://todos.example.com/oauth/token");
+        assert_eq!(res["response_types_supported"], serde_json::json!(["code"]));
+
+        // signin now hands out RS256 tokens that the API accepts
+        let input = SignupInput{
+            email:"jwks1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"jwks1@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = test::read_body_json(res).await;
+
+        let header = jsonwebtoken::decode_header(&res.data).unwrap();
+        assert_eq!(header.alg, jsonwebtoken::Algorithm::RS256);
+        assert_eq!(header.kid.as_deref(), Some("rsa-1"));
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", res.data))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+    }
+}
@@ -0,0 +1,265 @@
+use actix_web::{delete, get, http::header, post, web::{Data, Path}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use store::{feed::FeedToken, formats::{ical, TodoRecord}, todo::Todo, user::User};
+
+use crate::{handlers::todo::Message, scopes, utils::{generate_secret_token, hash_token}, GlobalState};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FeedUrl{
+    pub url: String,
+}
+
+fn etag_matches(req: &HttpRequest, etag: &str) -> bool{
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).and_then(|h| h.to_str().ok());
+
+    match if_none_match {
+        Some(value) => value.split(',').map(str::trim).any(|tag| {
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        }),
+        None => false,
+    }
+}
+
+/// The public, unauthenticated calendar of a user's open todos. The token in the path is the credential.
+/// A disabled account's feed is not served, and works again once the account is enabled.
+#[get("/feeds/{token}.ics")]
+pub async fn get_feed(req:HttpRequest, data:Data<GlobalState>, path:Path<String>) -> impl Responder {
+
+    let token_hash = hash_token(&path.into_inner());
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    let feed = FeedToken::get_feed(&state.feeds, &token_hash);
+
+    if feed.is_none(){
+        return HttpResponse::NotFound().finish();
+    }
+
+    let feed = feed.unwrap();
+
+    if User::get_user(&state.users, &feed.user_email).is_none_or(|u| u.disabled){
+        return HttpResponse::NotFound().finish();
+    }
+
+    let todos = Todo::get_user_todos(feed.user_email, &mut state.todos);
+
+    drop(state);
+
+    let records: Vec<TodoRecord> = todos.iter()
+        .filter(|t| !t.done)
+        .map(TodoRecord::from_todo)
+        .collect();
+
+    // DTSTAMP is pinned to the feed's creation so an unchanged list gives the same body and ETag
+    let dtstamp = DateTime::<Utc>::from_timestamp(feed.created_at, 0).unwrap_or_default();
+    let body = ical::write_calendar(&records, &dtstamp.format("%Y%m%dT%H%M%SZ").to_string());
+
+    let etag = format!("\"{}\"", &hash_token(&body)[..32]);
+
+    if etag_matches(&req, &etag){
+        return HttpResponse::NotModified()
+        .insert_header((header::ETAG, etag))
+        .finish();
+    }
+
+    HttpResponse::Ok()
+    .content_type(ical::CONTENT_TYPE)
+    .insert_header((header::ETAG, etag))
+    .insert_header((header::CACHE_CONTROL, "private, no-cache"))
+    .body(body)
+}
+
+/// Creates the caller's feed URL, or replaces it so the previous URL stops working.
+#[post("/feed", wrap = "scopes::require(scopes::FEED_WRITE)")]
+pub async fn rotate_feed(req:HttpRequest, data:Data<GlobalState>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    let token = generate_secret_token();
+
+    FeedToken::rotate(&mut state.feeds, FeedToken{
+        token_hash: hash_token(&token),
+        user_email: email,
+        created_at: Utc::now().timestamp(),
+    });
+
+    let conn = req.connection_info();
+    let url = format!("{}://{}/feeds/{}.ics", conn.scheme(), conn.host(), token);
+
+    HttpResponse::Ok().json(FeedUrl{url})
+}
+
+#[delete("/feed", wrap = "scopes::require(scopes::FEED_WRITE)")]
+pub async fn revoke_feed(req:HttpRequest, data:Data<GlobalState>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    match FeedToken::revoke(&mut state.feeds, &email) {
+        Ok(val) => HttpResponse::Ok().json(Message{message:val}),
+        Err(e) => HttpResponse::NotFound().json(Message{message:e}),
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+
+    use crate::{handlers::{feed::FeedUrl, user::{AppResponse, SigninInput, SignupInput}}, init_app, prepare_global_state};
+
+    #[actix_web::test]
+    pub async fn should_serve_feed(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"feed1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"feed1@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = actix_web::test::read_body_json(res).await;
+        let token = res.data;
+
+        for (title, done) in [("Go to Gym", false), ("Go to Movie", true)] {
+            TestRequest::post()
+            .uri("/authed/todo").set_json(serde_json::json!({"title":title, "done":done}))
+            .append_header(("Authorization", token.clone()))
+            .send_request(&app).await;
+        }
+
+        let res = TestRequest::post()
+        .uri("/authed/feed")
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        let res: FeedUrl = actix_web::test::read_body_json(res).await;
+        let path = res.url.split_once("/feeds/").map(|(_, p)| format!("/feeds/{}", p)).unwrap();
+
+        // No Authorization header, the URL is the credential
+        let res = TestRequest::get().uri(&path).send_request(&app).await;
+
+        assert!(res.status().is_success());
+        let etag = res.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+        let body = test::read_body(res).await;
+        let body = std::str::from_utf8(&body).unwrap();
+
+        assert!(body.contains("SUMMARY:Go to Gym"));
+        assert!(!body.contains("SUMMARY:Go to Movie"));
+
+        let res = TestRequest::get().uri(&path)
+        .insert_header(("If-None-Match", etag.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 304);
+
+        // A change to the list changes the ETag
+        TestRequest::post()
+        .uri("/authed/todo").set_json(serde_json::json!({"title":"Read", "done":false}))
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        let res = TestRequest::get().uri(&path)
+        .insert_header(("If-None-Match", etag))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 200);
+    }
+
+    #[actix_web::test]
+    pub async fn should_rotate_and_revoke_feed(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"feed2@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"feed2@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = actix_web::test::read_body_json(res).await;
+        let token = res.data;
+
+        let mut paths = vec![];
+
+        for _ in 0..2 {
+            let res = TestRequest::post()
+            .uri("/authed/feed")
+            .append_header(("Authorization", token.clone()))
+            .send_request(&app).await;
+
+            let res: FeedUrl = actix_web::test::read_body_json(res).await;
+            paths.push(res.url.split_once("/feeds/").map(|(_, p)| format!("/feeds/{}", p)).unwrap());
+        }
+
+        let res = TestRequest::get().uri(&paths[0]).send_request(&app).await;
+        assert_eq!(res.status().as_u16(), 404);
+
+        let res = TestRequest::get().uri(&paths[1]).send_request(&app).await;
+        assert_eq!(res.status().as_u16(), 200);
+
+        let res = TestRequest::delete()
+        .uri("/authed/feed")
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        let res = TestRequest::get().uri(&paths[1]).send_request(&app).await;
+        assert_eq!(res.status().as_u16(), 404);
+    }
+
+}
@@ -0,0 +1,367 @@
+use actix_web::{post, web::{Data, Json}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use store::{lockout::FailedAttempts, mfa::{MfaChallenge, TotpEnrollment}, user::User};
+
+use crate::{handlers::{todo::Message, user::{count_failure, signin_keys, start_session, throttled}}, scopes, totp, utils::{generate_secret_token, hash_token}, CombinedState, GlobalState};
+
+/// How long the `mfa_token` from signin waits for a code.
+pub const MFA_CHALLENGE_TTL: i64 = 60 * 5;
+// wrong codes before an `mfa_token` stops working, signin has to start over
+const MAX_CODE_FAILURES: u32 = 5;
+const RECOVERY_CODES: usize = 10;
+
+/// What signin answers instead of tokens when the account has two-factor authentication.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MfaRequiredResponse{
+    pub data: String,
+    pub mfa_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EnrollResponse{
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// The recovery codes in clear text, shown this one time only.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RecoveryCodesResponse{
+    pub data: String,
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CodeInput {
+    /// a TOTP code or a recovery code
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MfaVerifyInput {
+    pub mfa_token: String,
+    pub code: String,
+}
+
+/// Stores a challenge for a signin that still needs a code and returns its token.
+pub fn issue_challenge(state: &mut CombinedState, email: &str, now: i64) -> String{
+    let token = generate_secret_token();
+
+    MfaChallenge::issue(&mut state.mfa_challenges, MfaChallenge{
+        token_hash: hash_token(&token),
+        user_email: email.to_string(),
+        expires_at: now + MFA_CHALLENGE_TTL,
+        failed_codes: 0,
+    }, now);
+
+    token
+}
+
+// Recovery codes are typed by hand, so dashes, spaces and case do not matter
+fn normalize_recovery_code(code: &str) -> String{
+    code.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_ascii_lowercase()
+}
+
+/// Accepts a TOTP code that was not used before or uses up a recovery code.
+fn check_code(state: &mut CombinedState, email: &String, code: &str, now: i64) -> Result<(), String>{
+    let enrollment = TotpEnrollment::get_enrollment(&state.totp_enrollments, email);
+
+    if enrollment.is_none(){
+        return Err(String::from("Two-factor authentication is not enabled"));
+    }
+
+    let enrollment = enrollment.unwrap();
+
+    if let Some(step) = totp::verify(&enrollment.secret, code, now) {
+        return TotpEnrollment::use_step(&mut state.totp_enrollments, email, step).map(|_| ());
+    }
+
+    TotpEnrollment::redeem_recovery_code(&mut state.totp_enrollments, email, &hash_token(&normalize_recovery_code(code)))
+        .map(|_| ())
+        .map_err(|_| String::from("Invalid code"))
+}
+
+/// Starts enrollment with a new secret, for the authenticator app to scan from `otpauth_uri`.
+#[post("/mfa/enroll", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn enroll(req:HttpRequest, data:Data<GlobalState>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    let secret = totp::generate_secret();
+
+    let res = TotpEnrollment::enroll(&mut state.totp_enrollments, TotpEnrollment{
+        user_email: email.clone(),
+        secret: secret.clone(),
+        confirmed: false,
+        created_at: Utc::now().timestamp(),
+        last_used_step: -1,
+        recovery_codes: vec![],
+    });
+
+    match res {
+        Ok(_) => HttpResponse::Ok().json(EnrollResponse{
+            otpauth_uri: totp::otpauth_uri(&data.config.jwt.issuer, &email, &secret),
+            secret,
+        }),
+        Err(e) => HttpResponse::BadRequest().json(Message{message:e}),
+    }
+}
+
+/// Turns two-factor authentication on once a code from the app checks out.
+#[post("/mfa/confirm", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn confirm(req:HttpRequest, data:Data<GlobalState>, input:Json<CodeInput>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    let enrollment = TotpEnrollment::get_enrollment(&state.totp_enrollments, &email);
+
+    if enrollment.as_ref().is_none_or(|e| e.confirmed){
+        return HttpResponse::BadRequest().json(Message{message:String::from("Start enrollment first")});
+    }
+
+    let step = totp::verify(&enrollment.unwrap().secret, &input.code, Utc::now().timestamp());
+
+    if step.is_none(){
+        return HttpResponse::BadRequest().json(Message{message:String::from("Invalid code")});
+    }
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = &generate_secret_token()[..10];
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect();
+
+    let hashes = recovery_codes.iter().map(|c| hash_token(&normalize_recovery_code(c))).collect();
+
+    match TotpEnrollment::confirm(&mut state.totp_enrollments, &email, step.unwrap(), hashes) {
+        Ok(val) => HttpResponse::Ok().json(RecoveryCodesResponse{data:val, recovery_codes}),
+        Err(e) => HttpResponse::BadRequest().json(Message{message:e}),
+    }
+}
+
+/// Turns two-factor authentication off, which takes a code like signing in does.
+#[post("/mfa/disable", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn disable(req:HttpRequest, data:Data<GlobalState>, input:Json<CodeInput>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    if let Err(e) = check_code(&mut state, &email, &input.code, Utc::now().timestamp()) {
+        return HttpResponse::BadRequest().json(Message{message:e});
+    }
+
+    TotpEnrollment::remove(&mut state.totp_enrollments, &email);
+
+    HttpResponse::Ok().json(Message{message:String::from("Two-factor authentication disabled")})
+}
+
+/// Finishes a signin that answered with an `mfa_token`.
+#[post("/mfa/verify")]
+pub async fn verify(req:HttpRequest, data:Data<GlobalState>, input:Json<MfaVerifyInput>) -> impl Responder {
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    let token_hash = hash_token(&input.mfa_token);
+    let now = Utc::now().timestamp();
+
+    let email = MfaChallenge::get_email(&state.mfa_challenges, &token_hash, now);
+
+    if email.is_none(){
+        return HttpResponse::BadRequest().json(Message{message:String::from("Invalid or expired challenge")});
+    }
+
+    let email = email.unwrap();
+
+    // wrong codes count like wrong passwords, or fresh challenges would allow guessing without end
+    let keys = signin_keys(&req, &email);
+
+    if let Some(res) = throttled(&state, &keys, now) {
+        return res;
+    }
+
+    if let Err(e) = check_code(&mut state, &email, &input.code, now) {
+        MfaChallenge::fail(&mut state.mfa_challenges, &token_hash, MAX_CODE_FAILURES);
+        count_failure(&mut state, &data.config.lockout, &keys);
+        return HttpResponse::BadRequest().json(Message{message:e});
+    }
+
+    let _ = MfaChallenge::redeem(&mut state.mfa_challenges, &token_hash, now);
+    FailedAttempts::reset(&mut state.failed_attempts, &keys.0);
+
+    if User::get_user(&state.users, &email).is_none_or(|u| u.disabled) {
+        return HttpResponse::Forbidden().json(Message{message:String::from("Account disabled")});
+    }
+
+    match start_session(&req, &data, &mut state, &email) {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(_) => HttpResponse::InternalServerError().json(String::from("Internal Server Error")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+    use data_encoding::BASE32_NOPAD;
+
+    use crate::{config::AppConfig, handlers::{mfa::{CodeInput, EnrollResponse, MfaRequiredResponse, MfaVerifyInput, RecoveryCodesResponse}, user::{SigninInput, SignupInput, TokenResponse}}, init_app, prepare_global_state, prepare_global_state_with, totp};
+
+    fn code_at(secret:&str, step:i64) -> String{
+        format!("{:06}", totp::hotp(&BASE32_NOPAD.decode(secret.as_bytes()).unwrap(), step as u64))
+    }
+
+    #[actix_web::test]
+    pub async fn should_require_second_factor(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"vk@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let signin = || TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()});
+
+        let res: TokenResponse = test::call_and_read_body_json(&app, signin().to_request()).await;
+        let bearer = res.data;
+
+        let req = TestRequest::post().uri("/authed/mfa/enroll").insert_header(("Authorization", bearer.clone())).to_request();
+        let enrollment: EnrollResponse = test::call_and_read_body_json(&app, req).await;
+
+        assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/rust-int:vk%40gmail.com?secret="));
+
+        let step = totp::step_at(chrono::Utc::now().timestamp());
+
+        let req = TestRequest::post().uri("/authed/mfa/confirm").insert_header(("Authorization", bearer.clone()))
+            .set_json(CodeInput{code:"000000".to_string()}).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status().as_u16(), 400);
+
+        let req = TestRequest::post().uri("/authed/mfa/confirm").insert_header(("Authorization", bearer.clone()))
+            .set_json(CodeInput{code:code_at(&enrollment.secret, step)}).to_request();
+        let res: RecoveryCodesResponse = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(res.recovery_codes.len(), 10);
+        let recovery_code = res.recovery_codes[0].clone();
+
+        // the password alone only gets a challenge now
+        let res: MfaRequiredResponse = test::call_and_read_body_json(&app, signin().to_request()).await;
+        let mfa_token = res.mfa_token;
+
+        let req = TestRequest::get().uri("/authed/sessions").insert_header(("Authorization", mfa_token.clone())).to_request();
+        assert!(test::try_call_service(&app, req).await.is_err());
+
+        let verify = |mfa_token:&str, code:String| TestRequest::post().uri("/user/mfa/verify")
+            .set_json(MfaVerifyInput{mfa_token:mfa_token.to_string(), code}).to_request();
+
+        // a code is used once, the one that confirmed enrollment does not sign in
+        let res = test::call_service(&app, verify(&mfa_token, code_at(&enrollment.secret, step))).await;
+        assert_eq!(res.status().as_u16(), 400);
+
+        let res: TokenResponse = test::call_and_read_body_json(&app, verify(&mfa_token, code_at(&enrollment.secret, step + 1))).await;
+        assert!(!res.refresh_token.is_empty());
+
+        let res = test::call_service(&app, verify(&mfa_token, code_at(&enrollment.secret, step + 1))).await;
+        assert_eq!(res.status().as_u16(), 400);
+
+        // recovery codes work once, however they are typed
+        let res: MfaRequiredResponse = test::call_and_read_body_json(&app, signin().to_request()).await;
+        let res = test::call_service(&app, verify(&res.mfa_token, recovery_code.to_uppercase())).await;
+        assert!(res.status().is_success());
+
+        let res: MfaRequiredResponse = test::call_and_read_body_json(&app, signin().to_request()).await;
+        let res = test::call_service(&app, verify(&res.mfa_token, recovery_code.clone())).await;
+        assert_eq!(res.status().as_u16(), 400);
+    }
+
+    #[actix_web::test]
+    pub async fn should_lock_out_code_guessing_across_signins(){
+        let mut config = AppConfig::dev();
+        // no backoff, so the lockout is reached without waiting
+        config.lockout.free_attempts = 10;
+        config.lockout.threshold = 3;
+
+        let state = prepare_global_state_with(config);
+        let app = test::init_service(init_app!(state)).await;
+
+        let input = SignupInput{email:"vk@gmail.com".to_string(), name:"VK".to_string(), password:"Random1234".to_string()};
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let signin = || TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+
+        let bearer = test::call_and_read_body_json::<_, _, TokenResponse>(&app, signin()).await.data;
+
+        let req = TestRequest::post().uri("/authed/mfa/enroll").insert_header(("Authorization", bearer.clone())).to_request();
+        let enrollment: EnrollResponse = test::call_and_read_body_json(&app, req).await;
+
+        let step = totp::step_at(chrono::Utc::now().timestamp());
+        let req = TestRequest::post().uri("/authed/mfa/confirm").insert_header(("Authorization", bearer))
+            .set_json(CodeInput{code:code_at(&enrollment.secret, step)}).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 200);
+
+        // the password is known, each signin hands out a fresh challenge to guess at
+        for _ in 0..3 {
+            let res: MfaRequiredResponse = test::call_and_read_body_json(&app, signin()).await;
+            let req = TestRequest::post().uri("/user/mfa/verify")
+                .set_json(MfaVerifyInput{mfa_token:res.mfa_token, code:"000000".to_string()}).to_request();
+            assert_eq!(test::call_service(&app, req).await.status().as_u16(), 400);
+        }
+
+        assert_eq!(test::call_service(&app, signin()).await.status().as_u16(), 429);
+    }
+}
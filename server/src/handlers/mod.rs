@@ -1,2 +1,15 @@
 pub mod user;
-pub mod todo;
\ No newline at end of file
+pub mod todo;
+pub mod batch;
+pub mod transfer;
+pub mod feed;
+pub mod caldav;
+pub mod discovery;
+pub mod session;
+pub mod password;
+pub mod verification;
+pub mod mfa;
+pub mod access_token;
+pub mod oauth;
+pub mod oidc;
+pub mod admin;
@@ -0,0 +1,806 @@
+//! An OAuth2 authorization server (RFC 6749) for apps acting on behalf of users. It supports
+//! the authorization code grant with PKCE (RFC 7636, S256 only) and the client credentials grant.
+//!
+//! There is no browser session, so the consent screen of the app's frontend asks
+//! `/authed/oauth/authorize` with the signed in user's token. The resulting tokens are opaque
+//! `oat_` tokens, checked by the `/authed` middleware like personal access tokens.
+
+use actix_web::{delete, get, http::header, post, web::{Data, Form, Json, Path, Query}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use store::{access_token::AccessToken, oauth::{AuthorizationCode, Consent, OAuthClient}};
+
+use crate::{handlers::{todo::Message, user::ValidationResponse}, policy::FieldError, scopes::{self, GRANTABLE}, utils::{generate_secret_token, hash_token, percent_encode}, CombinedState, GlobalState};
+
+/// Sets tokens issued to OAuth apps apart in the `Authorization` header.
+pub const OAUTH_TOKEN_PREFIX: &str = "oat_";
+const CLIENT_SECRET_PREFIX: &str = "ocs_";
+const ACCESS_TOKEN_TTL: i64 = 60 * 60;
+const CODE_TTL: i64 = 60 * 5;
+
+#[derive(Deserialize, Serialize)]
+pub struct RegisterClientInput {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    /// a server side app that can keep a secret, public apps like CLIs rely on PKCE alone
+    pub confidential: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClientView{
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub confidential: bool,
+    pub created_at: i64,
+}
+
+/// `client_secret` is shown this one time only.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RegisteredClientResponse{
+    pub client_secret: Option<String>,
+    #[serde(flatten)]
+    pub client: ClientView,
+}
+
+impl From<OAuthClient> for ClientView {
+    fn from(client: OAuthClient) -> ClientView{
+        ClientView{
+            client_id: client.client_id,
+            name: client.name,
+            redirect_uris: client.redirect_uris,
+            scopes: client.scopes,
+            confidential: client.secret_hash.is_some(),
+            created_at: client.created_at,
+        }
+    }
+}
+
+/// The query of an authorization request as the app sent it.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AuthorizeRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: Option<String>,
+    /// space separated, all the app registered for when left out
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AuthorizeDecision {
+    #[serde(flatten)]
+    pub request: AuthorizeRequest,
+    pub approve: bool,
+}
+
+/// What the consent screen shows.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConsentPrompt{
+    pub client_id: String,
+    pub client_name: String,
+    pub scopes: Vec<String>,
+    /// the user allowed all of `scopes` before
+    pub consented: bool,
+}
+
+/// Where to send the browser, with a `code` or an `error` in the query.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthorizeResponse{
+    pub redirect_to: String,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OAuthTokenResponse{
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub scope: String,
+}
+
+/// An error in the shape of RFC 6749 section 5.2.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OAuthError{
+    pub error: String,
+    pub error_description: String,
+}
+
+fn oauth_error(error:&str, description:&str) -> OAuthError{
+    OAuthError{error: error.to_string(), error_description: description.to_string()}
+}
+
+fn validate_client(input: &RegisterClientInput) -> Vec<FieldError>{
+    let mut errors = vec![];
+
+    if input.name.trim().is_empty() {
+        errors.push(FieldError::new("name", "empty", String::from("Name the app")));
+    }
+
+    if input.redirect_uris.is_empty() {
+        errors.push(FieldError::new("redirect_uris", "empty", String::from("Add at least one redirect URI")));
+    }
+
+    for uri in input.redirect_uris.iter().filter(|u| !(u.starts_with("https://") || u.starts_with("http://")) || u.contains('#')) {
+        errors.push(FieldError::new("redirect_uris", "invalid", format!("'{}' is not an http(s) URI without a fragment", uri)));
+    }
+
+    if input.scopes.is_empty() {
+        errors.push(FieldError::new("scopes", "empty", String::from("Pick at least one scope")));
+    }
+
+    for scope in input.scopes.iter().filter(|s| !GRANTABLE.contains(&s.as_str())) {
+        errors.push(FieldError::new("scopes", "unknown", format!("'{}' is not one of {}", scope, GRANTABLE.join(", "))));
+    }
+
+    errors
+}
+
+// Space separated scopes, all of the client's when none are asked for
+fn requested_scopes(client: &OAuthClient, scope: &Option<String>) -> Result<Vec<String>, OAuthError>{
+    let mut scopes: Vec<String> = match scope {
+        Some(scope) if !scope.trim().is_empty() => scope.split_whitespace().map(String::from).collect(),
+        _ => client.scopes.clone(),
+    };
+
+    scopes.sort();
+    scopes.dedup();
+
+    if let Some(scope) = scopes.iter().find(|s| !client.scopes.contains(s)) {
+        return Err(oauth_error("invalid_scope", &format!("The app is not registered for '{}'", scope)));
+    }
+
+    Ok(scopes)
+}
+
+fn with_query(uri: &str, params: &[(&str, &str)]) -> String{
+    let query: Vec<String> = params.iter().map(|(k, v)| format!("{}={}", k, percent_encode(v))).collect();
+    let separator = if uri.contains('?') { '&' } else { '?' };
+
+    format!("{}{}{}", uri, separator, query.join("&"))
+}
+
+/// A checked authorization request, or why not.
+enum Authorization{
+    Valid{client: OAuthClient, redirect_uri: String, scopes: Vec<String>, code_challenge: String},
+    /// the client or redirect URI are wrong, so there is nowhere safe to redirect to
+    Rejected(String),
+    /// anything else goes back to the app
+    Redirect{redirect_uri: String, error: OAuthError},
+}
+
+fn check_authorization(state: &CombinedState, request: &AuthorizeRequest) -> Authorization{
+    let client = OAuthClient::get_client(&state.oauth_clients, &request.client_id);
+
+    if client.is_none(){
+        return Authorization::Rejected(String::from("Unknown client"));
+    }
+
+    let client = client.unwrap();
+
+    let redirect_uri = match &request.redirect_uri {
+        Some(uri) if client.redirect_uris.contains(uri) => uri.clone(),
+        None if client.redirect_uris.len() == 1 => client.redirect_uris[0].clone(),
+        _ => return Authorization::Rejected(String::from("The redirect URI is not registered for this client")),
+    };
+
+    if request.response_type != "code" {
+        return Authorization::Redirect{redirect_uri, error: oauth_error("unsupported_response_type", "Only the code response type is supported")};
+    }
+
+    let scopes = requested_scopes(&client, &request.scope);
+
+    if let Err(error) = scopes {
+        return Authorization::Redirect{redirect_uri, error};
+    }
+
+    let code_challenge = request.code_challenge.clone().filter(|c| c.len() == 43);
+
+    if code_challenge.is_none() || request.code_challenge_method.as_deref() != Some("S256") {
+        return Authorization::Redirect{redirect_uri, error: oauth_error("invalid_request", "A S256 code_challenge is required")};
+    }
+
+    Authorization::Valid{client, redirect_uri, scopes: scopes.unwrap(), code_challenge: code_challenge.unwrap()}
+}
+
+/// The client named by the request if its credentials, from Basic auth or the form, check out.
+fn authenticate_client(state: &CombinedState, req: &HttpRequest, input: &TokenRequest) -> Result<OAuthClient, OAuthError>{
+    let basic = req.headers().get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Basic "))
+        .and_then(|b| STANDARD.decode(b).ok())
+        .and_then(|b| String::from_utf8(b).ok());
+
+    let (client_id, secret) = match &basic {
+        Some(credentials) => match credentials.split_once(':') {
+            Some((id, secret)) => (Some(id.to_string()), Some(secret.to_string())),
+            None => return Err(oauth_error("invalid_client", "Malformed Basic credentials")),
+        },
+        None => (input.client_id.clone(), input.client_secret.clone()),
+    };
+
+    let client = client_id.and_then(|id| OAuthClient::get_client(&state.oauth_clients, &id));
+
+    if client.is_none(){
+        return Err(oauth_error("invalid_client", "Unknown client"));
+    }
+
+    let client = client.unwrap();
+
+    let authenticated = match (&client.secret_hash, &secret) {
+        (Some(hash), Some(secret)) => *hash == hash_token(secret),
+        (None, None) => true,
+        _ => false,
+    };
+
+    if !authenticated {
+        return Err(oauth_error("invalid_client", "Client authentication failed"));
+    }
+
+    Ok(client)
+}
+
+fn issue_access_token(state: &mut CombinedState, client: &OAuthClient, email: &str, scopes: Vec<String>, now: i64) -> OAuthTokenResponse{
+    let token = format!("{}{}", OAUTH_TOKEN_PREFIX, generate_secret_token());
+    let scope = scopes.join(" ");
+
+    AccessToken::add_token(&mut state.access_tokens, AccessToken{
+        id: generate_secret_token()[..16].to_string(),
+        user_email: email.to_string(),
+        name: client.name.clone(),
+        token_hash: hash_token(&token),
+        scopes,
+        created_at: now,
+        expires_at: Some(now + ACCESS_TOKEN_TTL),
+        last_used: None,
+        client_id: Some(client.client_id.clone()),
+    });
+
+    OAuthTokenResponse{access_token: token, token_type: String::from("Bearer"), expires_in: ACCESS_TOKEN_TTL, scope}
+}
+
+#[post("/oauth/clients", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn register_client(req:HttpRequest, data:Data<GlobalState>, input:Json<RegisterClientInput>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let errors = validate_client(&input);
+
+    if !errors.is_empty(){
+        return HttpResponse::BadRequest().json(ValidationResponse{data:String::from("Check the highlighted fields"), errors});
+    }
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    let secret = input.confidential.then(|| format!("{}{}", CLIENT_SECRET_PREFIX, generate_secret_token()));
+
+    let mut scopes = input.scopes.clone();
+    scopes.sort();
+    scopes.dedup();
+
+    let client = OAuthClient{
+        client_id: generate_secret_token()[..24].to_string(),
+        name: input.name.trim().to_string(),
+        owner_email: email,
+        secret_hash: secret.as_deref().map(hash_token),
+        redirect_uris: input.redirect_uris.clone(),
+        scopes,
+        created_at: Utc::now().timestamp(),
+    };
+
+    OAuthClient::add_client(&mut state.oauth_clients, client.clone());
+
+    HttpResponse::Created().json(RegisteredClientResponse{client_secret: secret, client: ClientView::from(client)})
+}
+
+#[get("/oauth/clients", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn get_clients(req:HttpRequest, data:Data<GlobalState>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let state = state_result.unwrap();
+
+    let clients: Vec<ClientView> = OAuthClient::get_user_clients(&state.oauth_clients, &email)
+        .into_iter()
+        .map(ClientView::from)
+        .collect();
+
+    HttpResponse::Ok().json(clients)
+}
+
+/// Removes the app along with every consent, code and token it was given.
+#[delete("/oauth/clients/{id}", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn remove_client(req:HttpRequest, data:Data<GlobalState>, path:Path<String>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+    let client_id = path.into_inner();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    match OAuthClient::remove(&mut state.oauth_clients, &client_id, &email) {
+        Ok(val) => {
+            Consent::revoke_client(&mut state.oauth_consents, &client_id);
+            AuthorizationCode::revoke_client(&mut state.authorization_codes, &client_id);
+            AccessToken::revoke_client(&mut state.access_tokens, &client_id, None);
+            HttpResponse::Ok().json(Message{message:val})
+        },
+        Err(e) => HttpResponse::NotFound().json(Message{message:e}),
+    }
+}
+
+/// Checks an authorization request and tells the consent screen what to show.
+#[get("/oauth/authorize", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn authorize_prompt(req:HttpRequest, data:Data<GlobalState>, query:Query<AuthorizeRequest>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let state = state_result.unwrap();
+
+    match check_authorization(&state, &query) {
+        Authorization::Valid{client, scopes, ..} => HttpResponse::Ok().json(ConsentPrompt{
+            consented: Consent::covers(&state.oauth_consents, &email, &client.client_id, &scopes),
+            client_id: client.client_id,
+            client_name: client.name,
+            scopes,
+        }),
+        Authorization::Rejected(message) => HttpResponse::BadRequest().json(Message{message}),
+        Authorization::Redirect{error, ..} => HttpResponse::BadRequest().json(error),
+    }
+}
+
+/// Records the user's decision and returns where to redirect the browser.
+#[post("/oauth/authorize", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn authorize(req:HttpRequest, data:Data<GlobalState>, input:Json<AuthorizeDecision>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    let request = &input.request;
+    let app_state = request.state.clone().unwrap_or_default();
+
+    let redirect = |redirect_uri:&str, mut params:Vec<(&str, String)>| {
+        if request.state.is_some() {
+            params.push(("state", app_state.clone()));
+        }
+
+        let params: Vec<(&str, &str)> = params.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        HttpResponse::Ok().json(AuthorizeResponse{redirect_to: with_query(redirect_uri, &params)})
+    };
+
+    match check_authorization(&state, request) {
+        Authorization::Rejected(message) => HttpResponse::BadRequest().json(Message{message}),
+        Authorization::Redirect{redirect_uri, error} => redirect(&redirect_uri, vec![("error", error.error), ("error_description", error.error_description)]),
+        Authorization::Valid{redirect_uri, ..} if !input.approve => redirect(
+            &redirect_uri,
+            vec![("error", String::from("access_denied")), ("error_description", String::from("The user denied the request"))],
+        ),
+        Authorization::Valid{client, redirect_uri, scopes, code_challenge} => {
+            let now = Utc::now().timestamp();
+            let code = generate_secret_token();
+
+            Consent::grant(&mut state.oauth_consents, Consent{
+                user_email: email.clone(),
+                client_id: client.client_id.clone(),
+                scopes: scopes.clone(),
+                granted_at: now,
+            });
+
+            AuthorizationCode::issue(&mut state.authorization_codes, AuthorizationCode{
+                code_hash: hash_token(&code),
+                client_id: client.client_id,
+                user_email: email,
+                redirect_uri: request.redirect_uri.clone(),
+                scopes,
+                code_challenge,
+                expires_at: now + CODE_TTL,
+            }, now);
+
+            redirect(&redirect_uri, vec![("code", code)])
+        },
+    }
+}
+
+/// The token endpoint, form encoded as RFC 6749 has it.
+#[post("/oauth/token")]
+pub async fn token_endpoint(req:HttpRequest, data:Data<GlobalState>, input:Form<TokenRequest>) -> impl Responder {
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    let client = authenticate_client(&state, &req, &input);
+
+    if let Err(error) = client {
+        return HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"oauth\""))
+            .json(error);
+    }
+
+    let client = client.unwrap();
+    let now = Utc::now().timestamp();
+
+    let res = match input.grant_type.as_str() {
+        "authorization_code" => exchange_code(&mut state, &client, &input, now),
+        "client_credentials" if client.secret_hash.is_some() => requested_scopes(&client, &input.scope)
+            .map(|scopes| issue_access_token(&mut state, &client, &client.owner_email, scopes, now)),
+        "client_credentials" => Err(oauth_error("unauthorized_client", "Public clients can not use client credentials")),
+        _ => Err(oauth_error("unsupported_grant_type", "Use authorization_code or client_credentials")),
+    };
+
+    match res {
+        Ok(tokens) => HttpResponse::Ok().insert_header((header::CACHE_CONTROL, "no-store")).json(tokens),
+        Err(error) => HttpResponse::BadRequest().insert_header((header::CACHE_CONTROL, "no-store")).json(error),
+    }
+}
+
+fn exchange_code(state: &mut CombinedState, client: &OAuthClient, input: &TokenRequest, now: i64) -> Result<OAuthTokenResponse, OAuthError>{
+    let code = input.code.as_deref().unwrap_or_default();
+
+    let record = AuthorizationCode::redeem(&mut state.authorization_codes, &hash_token(code), &client.client_id, now)
+        .map_err(|e| oauth_error("invalid_grant", &e))?;
+
+    // RFC 6749 section 4.1.3, required and identical when the authorization request had one
+    if input.redirect_uri != record.redirect_uri {
+        return Err(oauth_error("invalid_grant", "The redirect URI does not match the authorization request"));
+    }
+
+    let verifier = input.code_verifier.as_deref().unwrap_or_default();
+
+    if !(43..=128).contains(&verifier.len()) || URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())) != record.code_challenge {
+        return Err(oauth_error("invalid_grant", "The code verifier does not match the code challenge"));
+    }
+
+    Ok(issue_access_token(state, client, &record.user_email, record.scopes, now))
+}
+
+#[get("/oauth/consents", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn get_consents(req:HttpRequest, data:Data<GlobalState>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let state = state_result.unwrap();
+
+    HttpResponse::Ok().json(Consent::get_user_consents(&state.oauth_consents, &email))
+}
+
+/// Withdraws consent from an app, its tokens for the user stop working.
+#[delete("/oauth/consents/{client_id}", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn revoke_consent(req:HttpRequest, data:Data<GlobalState>, path:Path<String>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+    let client_id = path.into_inner();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    match Consent::revoke(&mut state.oauth_consents, &email, &client_id) {
+        Ok(val) => {
+            AccessToken::revoke_client(&mut state.access_tokens, &client_id, Some(&email));
+            HttpResponse::Ok().json(Message{message:val})
+        },
+        Err(e) => HttpResponse::NotFound().json(Message{message:e}),
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+    use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+    use sha2::{Digest, Sha256};
+
+    use crate::{handlers::{oauth::{AuthorizeDecision, AuthorizeRequest, AuthorizeResponse, ConsentPrompt, OAuthError, OAuthTokenResponse, RegisterClientInput, RegisteredClientResponse, TokenRequest}, user::{SigninInput, SignupInput, TokenResponse}}, init_app, prepare_global_state, utils::percent_encode};
+
+    fn status(res: Result<actix_web::dev::ServiceResponse, actix_web::Error>) -> u16{
+        match res {
+            Ok(res) => res.status().as_u16(),
+            Err(e) => e.as_response_error().status_code().as_u16(),
+        }
+    }
+
+    fn query_param(uri:&str, name:&str) -> Option<String>{
+        uri.split_once('?')?.1.split('&')
+            .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+            .map(String::from)
+    }
+
+    #[actix_web::test]
+    pub async fn should_run_authorization_code_flow_with_pkce(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"vk@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let req = TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+        let jwt = test::call_and_read_body_json::<_, _, TokenResponse>(&app, req).await.data;
+
+        let req = TestRequest::post().uri("/authed/oauth/clients").insert_header(("Authorization", jwt.clone()))
+            .set_json(RegisterClientInput{
+                name:"todo cli".to_string(),
+                redirect_uris:vec!["http://127.0.0.1:9000/callback".to_string()],
+                scopes:vec!["todos:read".to_string(), "todos:write".to_string()],
+                confidential:false,
+            }).to_request();
+        let client: RegisteredClientResponse = test::call_and_read_body_json(&app, req).await;
+
+        assert!(client.client_secret.is_none());
+        let client_id = client.client.client_id;
+
+        let verifier = "a".repeat(20) + &"b".repeat(30);
+        let request = AuthorizeRequest{
+            response_type:"code".to_string(),
+            client_id:client_id.clone(),
+            redirect_uri:Some("http://127.0.0.1:9000/callback".to_string()),
+            scope:Some("todos:read".to_string()),
+            state:Some("xyz".to_string()),
+            code_challenge:Some(URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))),
+            code_challenge_method:Some("S256".to_string()),
+        };
+
+        let query = format!(
+            "response_type=code&client_id={}&redirect_uri={}&scope=todos%3Aread&state=xyz&code_challenge={}&code_challenge_method=S256",
+            client_id, percent_encode("http://127.0.0.1:9000/callback"), request.code_challenge.clone().unwrap(),
+        );
+        let req = TestRequest::get().uri(&format!("/authed/oauth/authorize?{}", query)).insert_header(("Authorization", jwt.clone())).to_request();
+        let prompt: ConsentPrompt = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(prompt.client_name, "todo cli");
+        assert!(!prompt.consented);
+
+        // an unregistered redirect URI is refused outright
+        let mut wrong = AuthorizeRequest{redirect_uri:Some("https://evil.example/".to_string()), ..request.clone()};
+        let req = TestRequest::post().uri("/authed/oauth/authorize").insert_header(("Authorization", jwt.clone()))
+            .set_json(AuthorizeDecision{request:wrong, approve:true}).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 400);
+
+        wrong = AuthorizeRequest{code_challenge:None, ..request.clone()};
+        let req = TestRequest::post().uri("/authed/oauth/authorize").insert_header(("Authorization", jwt.clone()))
+            .set_json(AuthorizeDecision{request:wrong, approve:true}).to_request();
+        let res: AuthorizeResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(query_param(&res.redirect_to, "error").unwrap(), "invalid_request");
+
+        let req = TestRequest::post().uri("/authed/oauth/authorize").insert_header(("Authorization", jwt.clone()))
+            .set_json(AuthorizeDecision{request:request.clone(), approve:true}).to_request();
+        let res: AuthorizeResponse = test::call_and_read_body_json(&app, req).await;
+
+        assert!(res.redirect_to.starts_with("http://127.0.0.1:9000/callback?code="));
+        assert_eq!(query_param(&res.redirect_to, "state").unwrap(), "xyz");
+
+        let code = query_param(&res.redirect_to, "code").unwrap();
+
+        let exchange = |verifier:&str| TestRequest::post().uri("/oauth/token").set_form(TokenRequest{
+            grant_type:"authorization_code".to_string(),
+            code:Some(code.clone()),
+            redirect_uri:Some("http://127.0.0.1:9000/callback".to_string()),
+            code_verifier:Some(verifier.to_string()),
+            client_id:Some(client_id.clone()),
+            ..Default::default()
+        }).to_request();
+
+        // a wrong verifier uses the code up all the same
+        let res: OAuthError = test::call_and_read_body_json(&app, exchange(&"c".repeat(50))).await;
+        assert_eq!(res.error, "invalid_grant");
+
+        let req = TestRequest::post().uri("/authed/oauth/authorize").insert_header(("Authorization", jwt.clone()))
+            .set_json(AuthorizeDecision{request:request.clone(), approve:true}).to_request();
+        let res: AuthorizeResponse = test::call_and_read_body_json(&app, req).await;
+        let code = query_param(&res.redirect_to, "code").unwrap();
+
+        let token_request = |code:&str, client_id:&str, redirect_uri:Option<&str>| TestRequest::post().uri("/oauth/token").set_form(TokenRequest{
+            grant_type:"authorization_code".to_string(),
+            code:Some(code.to_string()),
+            redirect_uri:redirect_uri.map(String::from),
+            code_verifier:Some(verifier.clone()),
+            client_id:Some(client_id.to_string()),
+            ..Default::default()
+        }).to_request();
+
+        // another app presenting the code does not use it up
+        let req = TestRequest::post().uri("/authed/oauth/clients").insert_header(("Authorization", jwt.clone()))
+            .set_json(RegisterClientInput{
+                name:"other app".to_string(),
+                redirect_uris:vec!["http://127.0.0.1:9000/callback".to_string()],
+                scopes:vec!["todos:read".to_string()],
+                confidential:false,
+            }).to_request();
+        let other: RegisteredClientResponse = test::call_and_read_body_json(&app, req).await;
+
+        let res: OAuthError = test::call_and_read_body_json(&app, token_request(&code, &other.client.client_id, Some("http://127.0.0.1:9000/callback"))).await;
+        assert_eq!(res.error, "invalid_grant");
+
+        let tokens: OAuthTokenResponse = test::call_and_read_body_json(&app, token_request(&code, &client_id, Some("http://127.0.0.1:9000/callback"))).await;
+
+        assert!(tokens.access_token.starts_with("oat_"));
+        assert_eq!(tokens.scope, "todos:read");
+
+        // the authorization request named a redirect URI, so the token request has to repeat it
+        let req = TestRequest::post().uri("/authed/oauth/authorize").insert_header(("Authorization", jwt.clone()))
+            .set_json(AuthorizeDecision{request:request.clone(), approve:true}).to_request();
+        let res: AuthorizeResponse = test::call_and_read_body_json(&app, req).await;
+        let code = query_param(&res.redirect_to, "code").unwrap();
+
+        let res: OAuthError = test::call_and_read_body_json(&app, token_request(&code, &client_id, None)).await;
+        assert_eq!(res.error, "invalid_grant");
+
+        let bearer = format!("Bearer {}", tokens.access_token);
+
+        let req = TestRequest::get().uri("/authed/todos").insert_header(("Authorization", bearer.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::post().uri("/authed/todos/bulk").insert_header(("Authorization", bearer.clone())).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 403);
+
+        let req = TestRequest::get().uri(&format!("/authed/oauth/authorize?{}", query)).insert_header(("Authorization", jwt.clone())).to_request();
+        let prompt: ConsentPrompt = test::call_and_read_body_json(&app, req).await;
+        assert!(prompt.consented);
+
+        // withdrawing consent ends the app's access
+        let req = TestRequest::delete().uri(&format!("/authed/oauth/consents/{}", client_id)).insert_header(("Authorization", jwt)).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+
+        let req = TestRequest::get().uri("/authed/todos").insert_header(("Authorization", bearer)).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 401);
+    }
+
+    #[actix_web::test]
+    pub async fn should_issue_client_credentials_tokens(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"vk@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let req = TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+        let jwt = test::call_and_read_body_json::<_, _, TokenResponse>(&app, req).await.data;
+
+        let req = TestRequest::post().uri("/authed/oauth/clients").insert_header(("Authorization", jwt))
+            .set_json(RegisterClientInput{
+                name:"reporting".to_string(),
+                redirect_uris:vec!["https://reports.example/callback".to_string()],
+                scopes:vec!["todos:read".to_string()],
+                confidential:true,
+            }).to_request();
+        let client: RegisteredClientResponse = test::call_and_read_body_json(&app, req).await;
+
+        let client_id = client.client.client_id;
+        let secret = client.client_secret.unwrap();
+
+        let credentials = |secret:&str| format!("Basic {}", STANDARD.encode(format!("{}:{}", client_id, secret)));
+        let grant = |scope:Option<&str>| TokenRequest{grant_type:"client_credentials".to_string(), scope:scope.map(String::from), ..Default::default()};
+
+        let req = TestRequest::post().uri("/oauth/token").insert_header(("Authorization", credentials("wrong"))).set_form(grant(None)).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status().as_u16(), 401);
+
+        let req = TestRequest::post().uri("/oauth/token").insert_header(("Authorization", credentials(&secret))).set_form(grant(Some("todos:write"))).to_request();
+        let res: OAuthError = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res.error, "invalid_scope");
+
+        let req = TestRequest::post().uri("/oauth/token").insert_header(("Authorization", credentials(&secret))).set_form(grant(None)).to_request();
+        let tokens: OAuthTokenResponse = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(tokens.token_type, "Bearer");
+
+        let req = TestRequest::get().uri("/authed/todos").insert_header(("Authorization", format!("Bearer {}", tokens.access_token))).to_request();
+        assert_eq!(status(test::try_call_service(&app, req).await), 200);
+    }
+}
@@ -0,0 +1,444 @@
+use actix_web::{get, http::header, web::{self, Data, Path, Query}, HttpRequest, HttpResponse, Responder};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use store::{mfa::TotpEnrollment, oidc::{OidcIdentity, OidcLogin}, user::User};
+
+use crate::{handlers::{mfa::{issue_challenge, MfaRequiredResponse, MFA_CHALLENGE_TTL}, todo::Message, user::start_session}, oidc, utils::{generate_secret_token, hash_token}, GlobalState};
+
+// how long the user may take at the provider
+const LOGIN_TTL: i64 = 60 * 10;
+
+#[derive(Deserialize, Serialize)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Redirects to the provider's sign in page.
+#[get("/oidc/{provider}/login")]
+pub async fn oidc_login(data:Data<GlobalState>, path:Path<String>) -> impl Responder {
+
+    let provider = data.config.oidc.iter().find(|p| p.name == *path).cloned();
+
+    if provider.is_none(){
+        return HttpResponse::NotFound().json(Message{message:String::from("Unknown provider")});
+    }
+
+    let provider = provider.unwrap();
+
+    let metadata = {
+        let (provider, discovery) = (provider.clone(), data.oidc_discovery.clone());
+        web::block(move || discovery.get(&provider)).await
+    };
+
+    let metadata = match metadata {
+        Ok(Ok(metadata)) => metadata,
+        Ok(Err(e)) => {
+            println!("OIDC discovery for {} failed: {}", provider.name, e);
+            return HttpResponse::BadGateway().json(Message{message:String::from("The provider is not reachable")});
+        },
+        Err(_) => return HttpResponse::InternalServerError().json(String::from("Internal Server Error")),
+    };
+
+    let state = generate_secret_token();
+    let nonce = generate_secret_token();
+    let code_verifier = generate_secret_token();
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    {
+        let state_result = data.overall_state.lock();
+
+        if state_result.is_err(){
+            return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+        }
+
+        let mut overall_state = state_result.unwrap();
+        let now = Utc::now().timestamp();
+
+        OidcLogin::issue(&mut overall_state.oidc_logins, OidcLogin{
+            state_hash: hash_token(&state),
+            provider: provider.name.clone(),
+            nonce: nonce.clone(),
+            code_verifier,
+            expires_at: now + LOGIN_TTL,
+        }, now);
+    }
+
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, oidc::authorization_url(&provider, &metadata, &state, &nonce, &code_challenge)))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .finish()
+}
+
+/// Where the provider sends the browser back. Signs in the user linked to the ID token's issuer
+/// and subject. On the first sign in the provider account is linked by its verified email,
+/// creating the local account if there is none.
+#[get("/oidc/{provider}/callback")]
+pub async fn oidc_callback(req:HttpRequest, data:Data<GlobalState>, path:Path<String>, query:Query<CallbackQuery>) -> impl Responder {
+
+    if let Some(error) = &query.error {
+        let description = query.error_description.clone().unwrap_or_default();
+        return HttpResponse::BadRequest().json(Message{message:format!("The provider refused the sign in: {} {}", error, description).trim_end().to_string()});
+    }
+
+    if query.code.is_none() || query.state.is_none(){
+        return HttpResponse::BadRequest().json(Message{message:String::from("code and state are required")});
+    }
+
+    let provider = data.config.oidc.iter().find(|p| p.name == *path).cloned();
+
+    if provider.is_none(){
+        return HttpResponse::NotFound().json(Message{message:String::from("Unknown provider")});
+    }
+
+    let provider = provider.unwrap();
+
+    let login = {
+        let state_result = data.overall_state.lock();
+
+        if state_result.is_err(){
+            return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+        }
+
+        let mut overall_state = state_result.unwrap();
+
+        OidcLogin::redeem(&mut overall_state.oidc_logins, &hash_token(query.state.as_deref().unwrap()), &provider.name, Utc::now().timestamp())
+    };
+
+    if let Err(e) = login {
+        return HttpResponse::BadRequest().json(Message{message:e});
+    }
+
+    let login = login.unwrap();
+    let code = query.code.clone().unwrap();
+
+    let discovery = data.oidc_discovery.clone();
+
+    let claims = web::block(move || {
+        let metadata = discovery.get(&provider)?;
+        let id_token = oidc::exchange_code(&provider, &metadata, &code, &login.code_verifier)?;
+        oidc::validate_id_token(&provider, &metadata, &id_token, &login.nonce)
+    }).await;
+
+    let claims = match claims {
+        Ok(Ok(claims)) => claims,
+        Ok(Err(e)) => {
+            println!("OIDC sign in with {} failed: {}", path, e);
+            return HttpResponse::Unauthorized().json(Message{message:String::from("The sign in could not be verified")});
+        },
+        Err(_) => return HttpResponse::InternalServerError().json(String::from("Internal Server Error")),
+    };
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+    let now = Utc::now().timestamp();
+
+    let email = match OidcIdentity::find(&state.oidc_identities, &claims.iss, &claims.sub) {
+        Some(identity) => identity.user_email,
+        None => {
+            // an unverified address could belong to anyone, it must not reach an existing account
+            if claims.email.is_none() || claims.email_verified != Some(true) {
+                return HttpResponse::Forbidden().json(Message{message:String::from("The provider did not confirm an email address")});
+            }
+
+            let email = claims.email.clone().unwrap();
+
+            match User::get_user(&state.users, &email) {
+                Some(user) if user.disabled => {
+                    return HttpResponse::Forbidden().json(Message{message:String::from("Account disabled")});
+                },
+                // whoever signed up with the address never proved it is theirs, linking would
+                // hand the provider account their password, sessions and tokens
+                Some(user) if !user.verified => {
+                    return HttpResponse::Conflict().json(Message{message:String::from("An unverified account uses this email, verify it before signing in with the provider")});
+                },
+                Some(_) => {},
+                None => {
+                    let name = claims.name.clone().unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+
+                    // no password, it can be set with a password reset
+                    let _ = User::add_user(&mut state.users, &User{
+                        email: email.clone(),
+                        name,
+                        password: String::new(),
+                        verified: true,
+                        roles: data.config.initial_roles(&email),
+                        disabled: false,
+                    });
+                },
+            }
+
+            let _ = OidcIdentity::link(&mut state.oidc_identities, OidcIdentity{
+                issuer: claims.iss.clone(),
+                subject: claims.sub.clone(),
+                user_email: email.clone(),
+                linked_at: now,
+            });
+
+            email
+        },
+    };
+
+    if User::get_user(&state.users, &email).is_none_or(|u| u.disabled) {
+        return HttpResponse::Forbidden().json(Message{message:String::from("Account disabled")});
+    }
+
+    if TotpEnrollment::is_enabled(&state.totp_enrollments, &email) {
+        let mfa_token = issue_challenge(&mut state, &email, now);
+        return HttpResponse::Ok().json(MfaRequiredResponse{
+            data: String::from("Enter a code from your authenticator app or a recovery code"),
+            mfa_token,
+            expires_in: MFA_CHALLENGE_TTL,
+        });
+    }
+
+    match start_session(&req, &data, &mut state, &email) {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(_) => HttpResponse::InternalServerError().json(String::from("Internal Server Error")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener, sync::{Arc, Mutex}, thread};
+
+    use actix_web::test::{self, TestRequest};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use sha2::{Digest, Sha256};
+
+    use store::user::User;
+
+    use crate::{config::{AppConfig, OidcProvider}, handlers::user::{SignupInput, TokenResponse}, init_app, prepare_global_state_with, utils::{KeyAlg, SigningKey}};
+
+    const KEYS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/keys");
+
+    /// What the stand-in provider puts into its next ID token.
+    #[derive(Default)]
+    struct NextLogin{
+        nonce: String,
+        code_challenge: String,
+        subject: String,
+        email: String,
+        email_verified: bool,
+        /// how often the discovery document was fetched
+        discoveries: u32,
+    }
+
+    fn query_param(uri:&str, name:&str) -> String{
+        uri.split_once('?').unwrap().1.split('&')
+            .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+            .unwrap()
+            .to_string()
+    }
+
+    // Plays an OpenID provider over plain HTTP: discovery, JWKS and a token endpoint that signs
+    // ID tokens with the RSA fixture key
+    fn stand_in_provider(next: Arc<Mutex<NextLogin>>) -> String{
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let base = issuer.clone();
+
+        let key = SigningKey::from_pem_files("idp-1", KeyAlg::RS256, Some(format!("{}/rsa_private.pem", KEYS)), Some(format!("{}/rsa_public.pem", KEYS)), 0).unwrap();
+        let jwks = serde_json::json!({"keys": [key.jwk().unwrap().unwrap()]}).to_string();
+        let encoding_key = EncodingKey::from_rsa_pem(&std::fs::read(format!("{}/rsa_private.pem", KEYS)).unwrap()).unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+
+                let mut body = vec![0u8; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let body = String::from_utf8(body).unwrap();
+
+                let (status, response) = if request_line.starts_with("GET /.well-known/openid-configuration") {
+                    next.lock().unwrap().discoveries += 1;
+                    ("200 OK", serde_json::json!({
+                        "issuer": base,
+                        "authorization_endpoint": format!("{}/authorize", base),
+                        "token_endpoint": format!("{}/token", base),
+                        "jwks_uri": format!("{}/jwks", base),
+                    }).to_string())
+                } else if request_line.starts_with("GET /jwks") {
+                    ("200 OK", jwks.clone())
+                } else if request_line.starts_with("POST /token") {
+                    let next = next.lock().unwrap();
+                    let verifier = body.split('&').find_map(|p| p.strip_prefix("code_verifier=")).unwrap_or_default();
+
+                    if !body.contains("code=mock-code") || URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())) != next.code_challenge {
+                        ("400 Bad Request", String::from(r#"{"error": "invalid_grant"}"#))
+                    } else {
+                        let now = chrono::Utc::now().timestamp();
+                        let claims = serde_json::json!({
+                            "iss": base, "aud": "rust-int", "sub": next.subject, "iat": now, "exp": now + 300,
+                            "email": next.email, "email_verified": next.email_verified, "name": "SSO User", "nonce": next.nonce,
+                        });
+                        let header = Header{kid: Some(String::from("idp-1")), ..Header::new(Algorithm::RS256)};
+                        let id_token = encode(&header, &claims, &encoding_key).unwrap();
+
+                        ("200 OK", serde_json::json!({"access_token": "at", "token_type": "Bearer", "id_token": id_token}).to_string())
+                    }
+                } else {
+                    ("404 Not Found", String::from("{}"))
+                };
+
+                let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, response.len(), response);
+            }
+        });
+
+        issuer
+    }
+
+    fn corp_config(issuer: &str) -> AppConfig{
+        let mut config = AppConfig::dev();
+        config.oidc = vec![OidcProvider{
+            name: String::from("corp"),
+            issuer: issuer.to_string(),
+            client_id: String::from("rust-int"),
+            client_secret: String::from("client-secret"),
+            redirect_uri: String::from("http://localhost:8080/user/oidc/corp/callback"),
+            scopes: vec![String::from("openid"), String::from("email")],
+        }];
+        config
+    }
+
+    #[actix_web::test]
+    pub async fn should_sign_in_with_oidc_provider(){
+        let next = Arc::new(Mutex::new(NextLogin{subject: String::from("248289761001"), email: String::from("sso@corp.example"), ..NextLogin::default()}));
+        let issuer = stand_in_provider(next.clone());
+
+        let state = prepare_global_state_with(corp_config(&issuer));
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let login = || async {
+            let res = TestRequest::get().uri("/user/oidc/corp/login").send_request(&app).await;
+            assert_eq!(res.status().as_u16(), 302);
+
+            let location = res.headers().get("Location").unwrap().to_str().unwrap().to_string();
+            assert!(location.starts_with(&format!("{}/authorize?response_type=code&client_id=rust-int", issuer)));
+
+            let mut next = next.lock().unwrap();
+            next.nonce = query_param(&location, "nonce");
+            next.code_challenge = query_param(&location, "code_challenge");
+            next.email_verified = true;
+
+            query_param(&location, "state")
+        };
+
+        let callback = |state:&str| TestRequest::get().uri(&format!("/user/oidc/corp/callback?code=mock-code&state={}", state)).to_request();
+
+        let login_state = login().await;
+        let res: TokenResponse = test::call_and_read_body_json(&app, callback(&login_state)).await;
+        assert!(!res.refresh_token.is_empty());
+
+        {
+            let combined = state.overall_state.lock().unwrap();
+            let user = combined.users.iter().find(|u| u.email == "sso@corp.example").unwrap();
+            assert!(user.verified);
+            assert_eq!(user.name, "SSO User");
+        }
+
+        // the state works once
+        let res = test::call_service(&app, callback(&login_state)).await;
+        assert_eq!(res.status().as_u16(), 400);
+
+        // an ID token from another sign in is refused
+        let login_state = login().await;
+        next.lock().unwrap().nonce = String::from("someone-elses-nonce");
+        let res = test::call_service(&app, callback(&login_state)).await;
+        assert_eq!(res.status().as_u16(), 401);
+
+        // a provider account that is not linked yet needs a verified email
+        let login_state = login().await;
+        {
+            let mut next = next.lock().unwrap();
+            next.subject = String::from("248289761002");
+            next.email_verified = false;
+        }
+        let res = test::call_service(&app, callback(&login_state)).await;
+        assert_eq!(res.status().as_u16(), 403);
+
+        // every login so far used the first discovery document
+        assert_eq!(next.lock().unwrap().discoveries, 1);
+
+        let res = TestRequest::get().uri("/user/oidc/other/login").send_request(&app).await;
+        assert_eq!(res.status().as_u16(), 404);
+    }
+
+    #[actix_web::test]
+    pub async fn should_not_link_unverified_accounts(){
+        let next = Arc::new(Mutex::new(NextLogin{subject: String::from("victim-sub"), email: String::from("victim@corp.example"), email_verified: true, ..NextLogin::default()}));
+        let issuer = stand_in_provider(next.clone());
+
+        let state = prepare_global_state_with(corp_config(&issuer));
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let login = || async {
+            let res = TestRequest::get().uri("/user/oidc/corp/login").send_request(&app).await;
+            let location = res.headers().get("Location").unwrap().to_str().unwrap().to_string();
+
+            let mut next = next.lock().unwrap();
+            next.nonce = query_param(&location, "nonce");
+            next.code_challenge = query_param(&location, "code_challenge");
+
+            TestRequest::get().uri(&format!("/user/oidc/corp/callback?code=mock-code&state={}", query_param(&location, "state"))).to_request()
+        };
+
+        // someone signs up with the address first and never verifies it
+        let input = SignupInput{email:"victim@corp.example".to_string(), name:"Squatter".to_string(), password:"Random1234".to_string()};
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let res = test::call_service(&app, login().await).await;
+        assert_eq!(res.status().as_u16(), 409);
+
+        {
+            let combined = state.overall_state.lock().unwrap();
+            assert!(combined.oidc_identities.is_empty());
+            assert!(!User::get_user(&combined.users, &"victim@corp.example".to_string()).unwrap().verified);
+        }
+
+        User::set_verified(&mut state.overall_state.lock().unwrap().users, &"victim@corp.example".to_string()).unwrap();
+
+        let res = test::call_service(&app, login().await).await;
+        assert_eq!(res.status().as_u16(), 200);
+
+        // once linked the subject decides, a changed email at the provider reaches the same account
+        next.lock().unwrap().email = String::from("renamed@corp.example");
+
+        let res = test::call_service(&app, login().await).await;
+        assert_eq!(res.status().as_u16(), 200);
+
+        let combined = state.overall_state.lock().unwrap();
+        assert!(User::get_user(&combined.users, &"renamed@corp.example".to_string()).is_none());
+        assert_eq!(combined.oidc_identities.len(), 1);
+        assert_eq!(combined.oidc_identities[0].user_email, "victim@corp.example");
+    }
+}
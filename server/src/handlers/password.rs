@@ -0,0 +1,402 @@
+use actix_web::{post, web::{self, Data, Json}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use store::{access_token::AccessToken, refresh::RefreshToken, reset::ResetToken, revocation::TokenCutoff, session::Session, user::User};
+
+use crate::{handlers::{todo::Message, user::ValidationResponse}, hashing, mailer::Email, policy::FieldError, scopes, utils::{generate_secret_token, hash_token, Claims}, GlobalState};
+
+// long enough to find the mail, short enough that an old inbox is not a way in
+const RESET_TTL: i64 = 60 * 60;
+
+#[derive(Deserialize, Serialize)]
+pub struct ForgotPasswordInput {
+    pub email: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ResetPasswordInput {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ChangePasswordInput {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Mails a reset token if the account exists. The reply is the same either way so it can not be
+/// used to find out who has an account.
+#[post("/forgot-password")]
+pub async fn forgot_password(data:Data<GlobalState>, input:Json<ForgotPasswordInput>) -> impl Responder {
+
+    let reply = Message{message:String::from("If the account exists, a reset token is on its way")};
+
+    let token = generate_secret_token();
+    let now = Utc::now().timestamp();
+
+    {
+        let state_result = data.overall_state.lock();
+
+        if state_result.is_err(){
+            return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+        }
+
+        let mut state = state_result.unwrap();
+
+        if User::get_user(&state.users, &input.email).is_none(){
+            return HttpResponse::Ok().json(reply);
+        }
+
+        ResetToken::issue(&mut state.reset_tokens, ResetToken{
+            token_hash: hash_token(&token),
+            user_email: input.email.clone(),
+            expires_at: now + RESET_TTL,
+        }, now);
+    }
+
+    // there is no reset page to link to, the mail names the token and where to send it
+    let email = Email{
+        to: input.email.clone(),
+        subject: String::from("Reset your password"),
+        body: format!(
+            "Someone asked to reset the password of your account. The reset token is {}\n\nSend it with the new password, as {{\"token\": ..., \"password\": ...}}, to POST {}/user/reset-password.\n\nIt works once and expires in an hour. If it was not you, ignore this mail.",
+            token, data.config.mail.public_url,
+        ),
+    };
+
+    // not awaited, an unknown email must not be told apart by answering sooner
+    let mailer = data.mailer.clone();
+    actix_web::rt::spawn(async move {
+        match web::block(move || mailer.send(&email)).await {
+            Ok(Ok(())) => {},
+            Ok(Err(e)) => println!("could not send reset mail: {}", e),
+            Err(e) => println!("could not send reset mail: {}", e),
+        }
+    });
+
+    HttpResponse::Ok().json(reply)
+}
+
+/// Sets a new password with a token from `forgot_password` and signs out every session.
+#[post("/reset-password")]
+pub async fn reset_password(data:Data<GlobalState>, input:Json<ResetPasswordInput>) -> impl Responder {
+
+    let now = Utc::now().timestamp();
+    let token_hash = hash_token(&input.token);
+
+    let user = {
+        let state_result = data.overall_state.lock();
+
+        if state_result.is_err(){
+            return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+        }
+
+        let state = state_result.unwrap();
+
+        ResetToken::get_email(&state.reset_tokens, &token_hash, now).and_then(|email| User::get_user(&state.users, &email))
+    };
+
+    if user.is_none(){
+        return HttpResponse::BadRequest().json(Message{message:String::from("Invalid, used or expired reset token")});
+    }
+
+    let user = user.unwrap();
+
+    // checked before the token is used up, so it can be tried again with a better password
+    let errors = data.config.password.check("password", &input.password, &user.email, &user.name);
+
+    if !errors.is_empty(){
+        return HttpResponse::BadRequest().json(ValidationResponse{data:String::from("Choose a stronger password"), errors});
+    }
+
+    let hashed_password_res = hashing::hash_password(&data, &input.password).await;
+
+    if hashed_password_res.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    // a second request with the same token may have won while this one was hashing
+    if let Err(e) = ResetToken::redeem(&mut state.reset_tokens, &token_hash, now) {
+        return HttpResponse::BadRequest().json(Message{message:e});
+    }
+
+    let email = user.email;
+
+    if let Err(e) = User::set_password(&mut state.users, &email, hashed_password_res.unwrap()) {
+        return HttpResponse::BadRequest().json(Message{message:e});
+    }
+
+    // whoever knew the old password is signed out too, and loses the tokens they may have created
+    TokenCutoff::set(&mut state.token_cutoffs, TokenCutoff{user_email: email.clone(), not_before_ms: Utc::now().timestamp_millis()});
+    RefreshToken::revoke_user(&mut state.refresh_tokens, &email);
+    Session::revoke_user(&mut state.sessions, &email);
+    AccessToken::revoke_user(&mut state.access_tokens, &email);
+
+    HttpResponse::Ok().json(Message{message:String::from("Password updated, sign in again")})
+}
+
+/// Changes the signed in user's password. Every other session is signed out and every access
+/// token revoked, this session stays.
+#[post("/password", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn change_password(req:HttpRequest, data:Data<GlobalState>, input:Json<ChangePasswordInput>) -> impl Responder {
+
+    let claims_ext = req.extensions().get::<Claims>().cloned();
+
+    if claims_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let claims = claims_ext.unwrap();
+
+    let user = {
+        let state_result = data.overall_state.lock();
+
+        if state_result.is_err(){
+            return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+        }
+
+        User::get_user(&state_result.unwrap().users, &claims.sub)
+    };
+
+    if user.is_none(){
+        return HttpResponse::NotFound().json(Message{message:String::from("User not found")});
+    }
+
+    let user = user.unwrap();
+
+    if !hashing::verify_password(&data, &user.password, &input.current_password).await {
+        let errors = vec![FieldError::new("current_password", "incorrect", String::from("The current password is not right"))];
+        return HttpResponse::BadRequest().json(ValidationResponse{data:String::from("Check the highlighted fields"), errors});
+    }
+
+    let mut errors = data.config.password.check("new_password", &input.new_password, &user.email, &user.name);
+
+    if input.new_password == input.current_password {
+        errors.push(FieldError::new("new_password", "unchanged", String::from("Choose a password different from the current one")));
+    }
+
+    if !errors.is_empty(){
+        return HttpResponse::BadRequest().json(ValidationResponse{data:String::from("Choose a stronger password"), errors});
+    }
+
+    let hashed_password_res = hashing::hash_password(&data, &input.new_password).await;
+
+    if hashed_password_res.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    // the current password was checked against this hash, a change made meanwhile wins
+    if User::get_user(&state.users, &user.email).is_none_or(|u| u.password != user.password) {
+        return HttpResponse::Conflict().json(Message{message:String::from("The password was changed meanwhile, try again")});
+    }
+
+    if let Err(e) = User::set_password(&mut state.users, &user.email, hashed_password_res.unwrap()) {
+        return HttpResponse::BadRequest().json(Message{message:e});
+    }
+
+    let others: Vec<String> = Session::get_user_sessions(&state.sessions, &user.email).into_iter()
+        .map(|s| s.id)
+        .filter(|id| claims.sid.as_ref() != Some(id))
+        .collect();
+
+    for id in others {
+        Session::remove(&mut state.sessions, &id);
+        RefreshToken::revoke_family(&mut state.refresh_tokens, &id);
+    }
+
+    // personal access and OAuth tokens could have been made with the old password
+    AccessToken::revoke_user(&mut state.access_tokens, &user.email);
+
+    HttpResponse::Ok().json(Message{message:String::from("Password updated")})
+}
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+
+    use crate::{config::{AppConfig, MailTransport}, handlers::{password::{ChangePasswordInput, ForgotPasswordInput, ResetPasswordInput}, user::{SigninInput, SignupInput, TokenResponse, ValidationResponse}}, init_app, prepare_global_state, prepare_global_state_with};
+
+    #[actix_web::test]
+    pub async fn should_reset_password_once(){
+        let outbox = std::env::temp_dir().join(format!("rust-int-outbox-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&outbox);
+
+        let mut config = AppConfig::dev();
+        config.mail.transport = MailTransport::Outbox(outbox.clone());
+
+        let state = prepare_global_state_with(config);
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        // unknown accounts get the same answer and no mail
+        let res = TestRequest::post().uri("/user/forgot-password")
+        .set_json(ForgotPasswordInput{email:"nobody@gmail.com".to_string()})
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+        assert!(!outbox.exists());
+
+        let input = SignupInput{
+            email:"reset1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        // a forged Host must not end up in the mail
+        let res = TestRequest::post().uri("/user/forgot-password")
+        .insert_header(("Host", "evil.example"))
+        .set_json(ForgotPasswordInput{email:"reset1@gmail.com".to_string()})
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        // the mail goes out after the answer
+        let mut mails: Vec<String> = vec![];
+
+        for _ in 0..50 {
+            mails = std::fs::read_dir(&outbox).into_iter().flatten()
+                .map(|m| std::fs::read_to_string(m.unwrap().path()).unwrap())
+                .filter(|m| m.contains("Subject: Reset your password"))
+                .collect();
+
+            if !mails.is_empty() {
+                break;
+            }
+
+            actix_web::rt::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        assert_eq!(mails.len(), 1);
+
+        let mail = &mails[0];
+        assert!(mail.contains("To: reset1@gmail.com"));
+        assert!(mail.contains("POST http://localhost:8080/user/reset-password"));
+        assert!(!mail.contains("evil.example"));
+
+        let token = mail.split("reset token is ").nth(1).unwrap().split_whitespace().next().unwrap().to_string();
+
+        // a weak password is refused without using up the token
+        let res = TestRequest::post().uri("/user/reset-password")
+        .set_json(ResetPasswordInput{token: token.clone(), password:"password1".to_string()})
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 400);
+        let res: ValidationResponse = test::read_body_json(res).await;
+        assert_eq!(res.errors.iter().map(|e| e.code.as_str()).collect::<Vec<_>>(), vec!["too_simple", "breached"]);
+
+        let res = TestRequest::post().uri("/user/reset-password")
+        .set_json(ResetPasswordInput{token: token.clone(), password:"NewPassword99".to_string()})
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        // single use
+        let res = TestRequest::post().uri("/user/reset-password")
+        .set_json(ResetPasswordInput{token, password:"Another123".to_string()})
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 400);
+
+        for (password, ok) in [("Random1234", false), ("NewPassword99", true)] {
+            let input = SigninInput{
+                email:"reset1@gmail.com".to_string(),
+                password:password.to_string(),
+            };
+
+            let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+            assert_eq!(res.status().is_success(), ok);
+        }
+
+        std::fs::remove_dir_all(outbox).unwrap();
+    }
+
+    #[actix_web::test]
+    pub async fn should_change_password(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"change1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let mut logins = vec![];
+
+        for _ in 0..2 {
+            let input = SigninInput{
+                email:"change1@gmail.com".to_string(),
+                password:"Random1234".to_string(),
+            };
+
+            let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+            let res: TokenResponse = test::read_body_json(res).await;
+            logins.push(res.data);
+        }
+
+        let res = TestRequest::post()
+        .uri("/authed/password")
+        .append_header(("Authorization", logins[0].clone()))
+        .set_json(ChangePasswordInput{current_password:"wrong".to_string(), new_password:"Change1@gmail.com".to_string()})
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 400);
+        let res: ValidationResponse = test::read_body_json(res).await;
+        assert_eq!(res.errors.iter().map(|e| (e.field.as_str(), e.code.as_str())).collect::<Vec<_>>(), vec![("current_password", "incorrect")]);
+
+        let res = TestRequest::post()
+        .uri("/authed/password")
+        .append_header(("Authorization", logins[0].clone()))
+        .set_json(ChangePasswordInput{current_password:"Random1234".to_string(), new_password:"Change1@gmail.com".to_string()})
+        .send_request(&app).await;
+
+        let res: ValidationResponse = test::read_body_json(res).await;
+        assert_eq!(res.errors.iter().map(|e| (e.field.as_str(), e.code.as_str())).collect::<Vec<_>>(), vec![("new_password", "matches_account")]);
+
+        let res = TestRequest::post()
+        .uri("/authed/password")
+        .append_header(("Authorization", logins[0].clone()))
+        .set_json(ChangePasswordInput{current_password:"Random1234".to_string(), new_password:"Better-Pass-42".to_string()})
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        // this session keeps going, the other one is signed out
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", logins[0].clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", logins[1].clone()))
+        .to_request();
+
+        let res = test::try_call_service(&app, req).await;
+        assert_eq!(res.err().map(|e| e.as_response_error().status_code().as_u16()), Some(401));
+    }
+}
@@ -0,0 +1,183 @@
+use actix_web::{delete, get, web::{Data, Path}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use store::{refresh::RefreshToken, session::Session};
+
+use crate::{handlers::todo::Message, scopes, utils::Claims, GlobalState};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionView{
+    pub id: String,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    /// the session the request was made from
+    pub current: bool,
+}
+
+#[get("/sessions", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn get_sessions(req:HttpRequest, data:Data<GlobalState>) -> impl Responder {
+
+    let claims_ext = req.extensions().get::<Claims>().cloned();
+
+    if claims_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let claims = claims_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let state = state_result.unwrap();
+
+    let mut sessions: Vec<SessionView> = Session::get_user_sessions(&state.sessions, &claims.sub)
+        .into_iter()
+        .map(|s| SessionView{
+            current: claims.sid.as_ref() == Some(&s.id),
+            id: s.id,
+            created_at: s.created_at,
+            last_seen: s.last_seen,
+            user_agent: s.user_agent,
+            ip: s.ip,
+        })
+        .collect();
+
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen));
+
+    HttpResponse::Ok().json(sessions)
+}
+
+/// Signs a session out: its access tokens stop working and its refresh token is revoked.
+#[delete("/sessions/{id}", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn revoke_session(req:HttpRequest, data:Data<GlobalState>, path:Path<String>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+    let id = path.into_inner();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    match Session::revoke(&mut state.sessions, &id, &email) {
+        Ok(val) => {
+            RefreshToken::revoke_family(&mut state.refresh_tokens, &id);
+            HttpResponse::Ok().json(Message{message:val})
+        },
+        Err(e) => HttpResponse::NotFound().json(Message{message:e}),
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+
+    use crate::{handlers::{session::SessionView, user::{RefreshInput, SigninInput, SignupInput, TokenResponse}}, init_app, prepare_global_state};
+
+    #[actix_web::test]
+    pub async fn should_list_and_revoke_sessions(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"session1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let mut logins = vec![];
+
+        for agent in ["Firefox", "Phone"] {
+            let input = SigninInput{
+                email:"session1@gmail.com".to_string(),
+                password:"Random1234".to_string(),
+            };
+
+            let res = TestRequest::post().uri("/user/signin")
+            .insert_header(("User-Agent", agent))
+            .peer_addr("10.0.0.7:4000".parse().unwrap())
+            .set_json(input)
+            .send_request(&app).await;
+
+            let res: TokenResponse = actix_web::test::read_body_json(res).await;
+            logins.push(res);
+        }
+
+        let res = TestRequest::get()
+        .uri("/authed/sessions")
+        .append_header(("Authorization", logins[0].data.clone()))
+        .send_request(&app).await;
+
+        let sessions: Vec<SessionView> = actix_web::test::read_body_json(res).await;
+        assert_eq!(sessions.len(), 2);
+
+        let current = sessions.iter().find(|s| s.current).unwrap();
+        assert_eq!(current.user_agent.as_deref(), Some("Firefox"));
+        assert_eq!(current.ip.as_deref(), Some("10.0.0.7"));
+
+        let phone = sessions.iter().find(|s| !s.current).unwrap();
+
+        let res = TestRequest::delete()
+        .uri(&format!("/authed/sessions/{}", phone.id))
+        .append_header(("Authorization", logins[0].data.clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        // the phone's access and refresh tokens are both dead
+        let req = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", logins[1].data.clone()))
+        .to_request();
+
+        let res = test::try_call_service(&app, req).await;
+        assert_eq!(res.err().map(|e| e.as_response_error().status_code().as_u16()), Some(401));
+
+        let res = TestRequest::post().uri("/user/refresh")
+        .set_json(RefreshInput{refresh_token: logins[1].refresh_token.clone()})
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 401);
+
+        // a refreshed token stays in its session
+        let res = TestRequest::post().uri("/user/refresh")
+        .set_json(RefreshInput{refresh_token: logins[0].refresh_token.clone()})
+        .send_request(&app).await;
+
+        let refreshed: TokenResponse = actix_web::test::read_body_json(res).await;
+
+        let res = TestRequest::get()
+        .uri("/authed/sessions")
+        .append_header(("Authorization", refreshed.data))
+        .send_request(&app).await;
+
+        let sessions: Vec<SessionView> = actix_web::test::read_body_json(res).await;
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].current);
+
+        // sessions of other users can not be revoked
+        let res = TestRequest::delete()
+        .uri("/authed/sessions/not-a-session")
+        .append_header(("Authorization", logins[0].data.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 404);
+    }
+}
@@ -1,7 +1,9 @@
-use actix_web::{get, post, put, web::{Data, Json, Path}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use actix_web::{get, patch, post, put, web::{Bytes, Data, Json, Path}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use store::todo::Todo;
 
-use crate::{GlobalState};
+use crate::{filter::parse_filter, patch::{parse_patch, PatchError}, scopes, GlobalState};
 
 #[derive(Deserialize, Serialize)]
 pub struct CreateTodo{
@@ -14,7 +16,33 @@ pub struct Message{
     pub message:String
 }
 
-#[post("/todo")]
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BulkAction{
+    Complete,
+    Reopen,
+    Delete,
+    /// `pattern` is a regex, `replacement` may refer to its groups as `$1`, `$name`
+    Retitle{pattern: String, replacement: String},
+    Move{project: Option<String>},
+}
+
+/// Exactly one of `ids` or `filter` selects the todos the action applies to.
+#[derive(Deserialize, Serialize)]
+pub struct BulkInput{
+    pub action: BulkAction,
+    pub ids: Option<Vec<u32>>,
+    pub filter: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BulkSummary{
+    pub matched: usize,
+    pub updated: Vec<Todo>,
+    pub deleted: Vec<u32>,
+}
+
+#[post("/todo", wrap = "scopes::require(scopes::TODOS_WRITE)")]
 pub async fn create_todo(req:HttpRequest, data:Data<GlobalState>, input:Json<CreateTodo>) -> impl Responder{
 
     let email_ext = req.extensions().get::<String>().cloned();
@@ -32,15 +60,15 @@ pub async fn create_todo(req:HttpRequest, data:Data<GlobalState>, input:Json<Cre
     }
 
     let mut state = state_result.unwrap();
-    let todos = &mut state.todos;
+    let state = &mut *state;
 
-    let res = store::todo::Todo::add_todo(input.title.clone(), input.done, email, todos);
+    let res = store::todo::Todo::add_todo(input.title.clone(), input.done, email, &mut state.todos, &mut state.todo_ids);
 
     HttpResponse::Ok().json(res)
 }
 
 
-#[put("/todo/{id}")]
+#[put("/todo/{id}", wrap = "scopes::require(scopes::TODOS_WRITE)")]
 pub async fn update_todo(req:HttpRequest, data:Data<GlobalState>, input:Json<CreateTodo>, path:Path<u32>) -> impl Responder {
 
     let email_ext = req.extensions().get::<String>().cloned();
@@ -71,7 +99,133 @@ pub async fn update_todo(req:HttpRequest, data:Data<GlobalState>, input:Json<Cre
 
 }
 
-#[get("/todos")]
+#[patch("/todo/{id}", wrap = "scopes::require(scopes::TODOS_WRITE)")]
+pub async fn patch_todo(req:HttpRequest, data:Data<GlobalState>, body:Bytes, path:Path<u32>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+    
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+    let todos = &mut state.todos;
+
+    let id = path.into_inner();
+
+    let existing_todo = store::todo::Todo::get_todo(id, todos);
+
+    if existing_todo.is_none(){
+        return HttpResponse::BadRequest().json(Message{message:String::from("Enter Valid todo id")});
+    }
+
+    let existing_todo = existing_todo.unwrap();
+
+    if existing_todo.user_email != email {
+        return HttpResponse::BadRequest().json(Message{message:String::from("UNAUTHORISED")});
+    }
+
+    let patch = match parse_patch(req.content_type(), &body, &existing_todo) {
+        Ok(patch) => patch,
+        Err(PatchError::UnsupportedMediaType) => {
+            let message = format!("Use {} or {}", crate::patch::MERGE_PATCH, crate::patch::JSON_PATCH);
+            return HttpResponse::UnsupportedMediaType().json(Message{message});
+        },
+        Err(PatchError::TestFailed(message)) => return HttpResponse::Conflict().json(Message{message}),
+        Err(PatchError::Invalid(message)) => return HttpResponse::BadRequest().json(Message{message}),
+    };
+
+    let res = store::todo::Todo::patch_todo(id, email, patch.title, patch.done, todos);
+
+    match res {
+        Ok(todo) => HttpResponse::Ok().json(todo),
+        Err(e) => HttpResponse::BadRequest().json(Message{message:e})
+    }
+
+}
+
+#[post("/todos/bulk", wrap = "scopes::require(scopes::TODOS_WRITE)")]
+pub async fn bulk_todos(req:HttpRequest, data:Data<GlobalState>, input:Json<BulkInput>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+    
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+    let input = input.into_inner();
+
+    // compile the pattern before taking the lock, a bad one fails the whole request anyway
+    let retitle = match &input.action {
+        BulkAction::Retitle{pattern, replacement} => match Regex::new(pattern) {
+            Ok(re) => Some((re, replacement.clone())),
+            Err(e) => return HttpResponse::BadRequest().json(Message{message:format!("Invalid pattern: {}", e)}),
+        },
+        _ => None,
+    };
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+    let todos = &mut state.todos;
+
+    let ids = match (input.ids, input.filter) {
+        (Some(ids), None) => ids,
+        (None, Some(filter)) => match parse_filter(&filter) {
+            // a filter only ever sees the caller's own todos
+            Ok(filter) => todos.iter()
+                .filter(|t| t.user_email == email && filter.matches(t))
+                .map(|t| t.id)
+                .collect(),
+            Err(e) => return HttpResponse::BadRequest().json(Message{message:e}),
+        },
+        _ => return HttpResponse::BadRequest().json(Message{message:String::from("Provide either ids or filter")}),
+    };
+
+    let before: Vec<Todo> = todos.iter().filter(|t| ids.contains(&t.id)).cloned().collect();
+
+    let res = match input.action {
+        BulkAction::Delete => Todo::delete_todos(&ids, email, todos).map(|deleted| (vec![], deleted)),
+        action => {
+            let res = Todo::bulk_update(&ids, email, todos, |todo| match &action {
+                BulkAction::Complete => todo.done = true,
+                BulkAction::Reopen => todo.done = false,
+                BulkAction::Move{project} => todo.project = project.clone(),
+                BulkAction::Retitle{..} => {
+                    let (re, replacement) = retitle.as_ref().unwrap();
+                    todo.title = re.replace_all(&todo.title, replacement.as_str()).into_owned();
+                },
+                BulkAction::Delete => {},
+            });
+
+            res.map(|updated| {
+                let changed = updated.into_iter().filter(|t| !before.contains(t)).collect();
+                (changed, vec![])
+            })
+        },
+    };
+
+    match res {
+        Ok((updated, deleted)) => HttpResponse::Ok().json(BulkSummary{matched: before.len(), updated, deleted}),
+        Err(e) => HttpResponse::BadRequest().json(Message{message:e})
+    }
+
+}
+
+#[get("/todos", wrap = "scopes::require(scopes::TODOS_READ)")]
 pub async fn get_todos(req:HttpRequest, data:Data<GlobalState>) -> impl Responder{
 
     let email_ext = req.extensions().get::<String>().cloned();
@@ -100,9 +254,9 @@ pub async fn get_todos(req:HttpRequest, data:Data<GlobalState>) -> impl Responde
 #[cfg(test)]
 mod tests{
     use actix_web::{test::{self, TestRequest}};
-    use store::{todo::Todo, user::User};
+    use store::todo::Todo;
 
-    use crate::{handlers::{todo::{CreateTodo, Message}, user::{AppResponse, SigninInput}}, init_app, prepare_global_state};
+    use crate::{handlers::{todo::{BulkSummary, CreateTodo, Message}, user::{AppResponse, SigninInput, SignupInput}}, init_app, prepare_global_state};
 
     #[actix_web::test]
     pub async fn should_create_todo(){
@@ -110,7 +264,7 @@ mod tests{
         let app = init_app!(state);
         let app = test::init_service(app).await;
 
-        let input = User{
+        let input = SignupInput{
             email:"vk4@gmail.com".to_string(),
             name:"VK".to_string(),
             password:"Random1234".to_string(),
@@ -144,7 +298,7 @@ mod tests{
 
         let res : Todo = actix_web::test::read_body_json(res).await;
         assert_eq!(res.title, "Go to Gym");
-        assert_eq!(res.done, false);
+        assert!(!res.done);
 
     }
 
@@ -166,7 +320,7 @@ mod tests{
         let res = test::try_call_service(&app, req).await;
         println!("{:?}", res);
         match res {
-            Ok(_) => assert!(false),
+            Ok(_) => panic!("request without a token should fail"),
             Err(e) => assert_eq!(e.to_string(), String::from("Token Not found")),
         }
     }
@@ -177,7 +331,7 @@ mod tests{
         let app = init_app!(state);
         let app = test::init_service(app).await;
 
-        let input = User{
+        let input = SignupInput{
             email:"vk5@gmail.com".to_string(),
             name:"VK".to_string(),
             password:"Random1234".to_string(),
@@ -211,7 +365,7 @@ mod tests{
 
         let data : Todo = actix_web::test::read_body_json(res).await;
         assert_eq!(data.title, "Go to Gym");
-        assert_eq!(data.done, false);
+        assert!(!data.done);
 
         // Update the existing todo
         let todo =  CreateTodo{
@@ -237,7 +391,7 @@ mod tests{
         let app = init_app!(state);
         let app = test::init_service(app).await;
 
-        let input = User{
+        let input = SignupInput{
             email:"vk6@gmail.com".to_string(),
             name:"VK".to_string(),
             password:"Random1234".to_string(),
@@ -287,10 +441,276 @@ mod tests{
         assert_eq!(res.len(), 2);
         
         assert_eq!(res[0].title, String::from("Go to Gym"));
-        assert_eq!(res[0].done, false);
+        assert!(!res[0].done);
         assert_eq!(res[1].title, String::from("Go to Movie"));
-        assert_eq!(res[1].done, false);
+        assert!(!res[1].done);
+
+    }
+
+    #[actix_web::test]
+    pub async fn should_patch_todo(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"vk7@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"vk7@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = actix_web::test::read_body_json(res).await;
+        let token = res.data;
+
+        let todo = CreateTodo{
+            title:"Go to Gym".to_string(),
+            done:false,
+        };
+
+        let res = TestRequest::post()
+        .uri("/authed/todo").set_json(todo)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        let data : Todo = actix_web::test::read_body_json(res).await;
+        let uri = format!("/authed/todo/{}", data.id);
+
+        // Merge patch only touches the fields it names
+        let res = TestRequest::patch()
+        .uri(&uri)
+        .insert_header(("Content-Type", "application/merge-patch+json"))
+        .set_payload(r#"{"done":true}"#)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+        let res: Todo = actix_web::test::read_body_json(res).await;
+        assert!(res.done);
+        assert_eq!(res.title, "Go to Gym");
+
+        // JSON patch applies its operations in order
+        let res = TestRequest::patch()
+        .uri(&uri)
+        .insert_header(("Content-Type", "application/json-patch+json"))
+        .set_payload(r#"[{"op":"test","path":"/done","value":true},{"op":"replace","path":"/title","value":"Go to Pool"}]"#)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+        let res: Todo = actix_web::test::read_body_json(res).await;
+        assert!(res.done);
+        assert_eq!(res.title, "Go to Pool");
+
+        // A failing operation rejects the whole patch
+        let res = TestRequest::patch()
+        .uri(&uri)
+        .insert_header(("Content-Type", "application/json-patch+json"))
+        .set_payload(r#"[{"op":"replace","path":"/title","value":"Changed"},{"op":"test","path":"/done","value":false}]"#)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 409);
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", token))
+        .send_request(&app).await;
+
+        let res: Vec<Todo> = actix_web::test::read_body_json(res).await;
+        assert_eq!(res[0].title, "Go to Pool");
+    }
+
+    #[actix_web::test]
+    pub async fn should_not_patch_protected_fields(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"vk8@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"vk8@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = actix_web::test::read_body_json(res).await;
+        let token = res.data;
+
+        let todo = CreateTodo{
+            title:"Go to Gym".to_string(),
+            done:false,
+        };
+
+        let res = TestRequest::post()
+        .uri("/authed/todo").set_json(todo)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        let data : Todo = actix_web::test::read_body_json(res).await;
+        let uri = format!("/authed/todo/{}", data.id);
+
+        let res = TestRequest::patch()
+        .uri(&uri)
+        .insert_header(("Content-Type", "application/merge-patch+json"))
+        .set_payload(r#"{"user_email":"someone@else.com"}"#)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 400);
+        let res: Message = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.message, "Field 'user_email' is not patchable");
+
+        let res = TestRequest::patch()
+        .uri(&uri)
+        .insert_header(("Content-Type", "application/json-patch+json"))
+        .set_payload(r#"[{"op":"remove","path":"/title"}]"#)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 400);
 
+        let res = TestRequest::patch()
+        .uri(&uri)
+        .insert_header(("Content-Type", "application/json"))
+        .set_payload(r#"{"done":true}"#)
+        .append_header(("Authorization", token))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 415);
     }
 
-}
\ No newline at end of file
+    #[actix_web::test]
+    pub async fn should_bulk_update_todos(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let mut tokens = vec![];
+
+        for email in ["vk9@gmail.com", "vk10@gmail.com"] {
+            let input = SignupInput{
+                email:email.to_string(),
+                name:"VK".to_string(),
+                password:"Random1234".to_string(),
+            };
+
+            TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+            let input = SigninInput{
+                email:email.to_string(),
+                password:"Random1234".to_string(),
+            };
+
+            let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+            let res: AppResponse = actix_web::test::read_body_json(res).await;
+            tokens.push(res.data);
+        }
+
+        for (title, token) in [("Go to Gym", &tokens[0]), ("Go to Movie", &tokens[0]), ("Read", &tokens[0]), ("Go to Gym", &tokens[1])] {
+            let todo = CreateTodo{
+                title:title.to_string(),
+                done:false,
+            };
+
+            TestRequest::post()
+            .uri("/authed/todo").set_json(todo)
+            .append_header(("Authorization", token.clone()))
+            .send_request(&app).await;
+        }
+
+        // A filter only matches the caller's todos
+        let res = TestRequest::post()
+        .uri("/authed/todos/bulk")
+        .set_json(serde_json::json!({"action":{"type":"complete"}, "filter":"title:\"go to\" done:false"}))
+        .append_header(("Authorization", tokens[0].clone()))
+        .send_request(&app).await;
+
+        let res: BulkSummary = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.matched, 2);
+        assert_eq!(res.updated.iter().map(|t| t.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(res.updated.iter().all(|t| t.done));
+
+        let res = TestRequest::post()
+        .uri("/authed/todos/bulk")
+        .set_json(serde_json::json!({"action":{"type":"retitle", "pattern":"^Go to (.*)$", "replacement":"Went to $1"}, "ids":[1, 2, 3]}))
+        .append_header(("Authorization", tokens[0].clone()))
+        .send_request(&app).await;
+
+        let res: BulkSummary = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.matched, 3);
+        assert_eq!(res.updated.len(), 2);
+        assert_eq!(res.updated[0].title, "Went to Gym");
+        assert_eq!(res.updated[1].title, "Went to Movie");
+
+        let res = TestRequest::post()
+        .uri("/authed/todos/bulk")
+        .set_json(serde_json::json!({"action":{"type":"move", "project":"errands"}, "filter":"done:true"}))
+        .append_header(("Authorization", tokens[0].clone()))
+        .send_request(&app).await;
+
+        let res: BulkSummary = actix_web::test::read_body_json(res).await;
+        assert!(res.updated.iter().all(|t| t.project.as_deref() == Some("errands")));
+
+        // Deleting someone else's todo fails the whole request
+        let res = TestRequest::post()
+        .uri("/authed/todos/bulk")
+        .set_json(serde_json::json!({"action":{"type":"delete"}, "ids":[3, 4]}))
+        .append_header(("Authorization", tokens[0].clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 400);
+        let res: Message = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.message, "UNAUTHORISED: 4");
+
+        let res = TestRequest::post()
+        .uri("/authed/todos/bulk")
+        .set_json(serde_json::json!({"action":{"type":"delete"}, "filter":"project:errands"}))
+        .append_header(("Authorization", tokens[0].clone()))
+        .send_request(&app).await;
+
+        let res: BulkSummary = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.deleted, vec![1, 2]);
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", tokens[0].clone()))
+        .send_request(&app).await;
+
+        let res: Vec<Todo> = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].title, "Read");
+
+        // The id of a deleted todo is not handed out again, even when it was the highest
+        let res = TestRequest::post()
+        .uri("/authed/todos/bulk")
+        .set_json(serde_json::json!({"action":{"type":"delete"}, "ids":[4]}))
+        .append_header(("Authorization", tokens[1].clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        let res = TestRequest::post()
+        .uri("/authed/todo").set_json(CreateTodo{title:"Go to Gym".to_string(), done:false})
+        .append_header(("Authorization", tokens[1].clone()))
+        .send_request(&app).await;
+
+        let res: Todo = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.id, 5);
+    }
+
+}
@@ -0,0 +1,453 @@
+use actix_web::{get, post, web::{Bytes, Data, Query}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use store::{formats::{self, RowError, TodoRecord}, todo::Todo};
+
+use crate::{handlers::todo::Message, scopes, GlobalState};
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Format{
+    Csv,
+    Json,
+    Todotxt,
+    Ics,
+}
+
+impl Format {
+    fn content_type(&self) -> &'static str{
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Json => "application/json",
+            Format::Todotxt => formats::todotxt::CONTENT_TYPE,
+            Format::Ics => formats::ical::CONTENT_TYPE,
+        }
+    }
+
+    fn from_content_type(content_type: &str) -> Option<Format>{
+        match content_type {
+            "text/csv" => Some(Format::Csv),
+            "application/json" => Some(Format::Json),
+            "text/plain" => Some(Format::Todotxt),
+            "text/calendar" => Some(Format::Ics),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode{
+    /// Records with the id of an existing todo overwrite it, the rest are added
+    #[default]
+    Merge,
+    /// All of the caller's todos are deleted before the records are added
+    Replace,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ExportQuery{
+    pub format: Option<Format>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ImportQuery{
+    pub format: Option<Format>,
+    #[serde(default)]
+    pub mode: ImportMode,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportReport{
+    pub created: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub errors: Vec<RowError>,
+}
+
+/// Turns the caller's todos into the body, one chunk per row. A row is only serialized when the
+/// response gets to it, so a large export is never held in memory as a whole.
+fn export_stream(format: Format, todos: Vec<Todo>) -> impl Stream<Item = Result<Bytes, actix_web::Error>>{
+    let (start, end): (&'static [u8], &'static [u8]) = match format {
+        Format::Csv => (formats::csv::HEADER.as_bytes(), b""),
+        Format::Json => (b"[", b"]"),
+        Format::Todotxt => (b"", b""),
+        Format::Ics => (formats::ical::CALENDAR_START.as_bytes(), formats::ical::CALENDAR_END.as_bytes()),
+    };
+
+    let dtstamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+    let rows = todos.into_iter().enumerate().map(move |(index, todo)| {
+        let record = TodoRecord::from_todo(&todo);
+
+        let row = match format {
+            Format::Csv => formats::csv::write_record(&record).map(Bytes::from),
+            Format::Json => serde_json::to_vec(&record).map_err(|e| e.to_string()).map(|row| {
+                match index {
+                    0 => Bytes::from(row),
+                    _ => Bytes::from([&b","[..], &row].concat()),
+                }
+            }),
+            Format::Todotxt => Ok(Bytes::from(format!("{}\n", formats::todotxt::write_line(&record)))),
+            Format::Ics => Ok(Bytes::from(formats::ical::write_vtodo(&record, &dtstamp))),
+        };
+
+        row.map_err(actix_web::error::ErrorInternalServerError)
+    });
+
+    let edge = |bytes: &'static [u8]| stream::iter((!bytes.is_empty()).then(|| Ok(Bytes::from_static(bytes))));
+
+    edge(start).chain(stream::iter(rows)).chain(edge(end))
+}
+
+#[get("/export", wrap = "scopes::require(scopes::TODOS_READ)")]
+pub async fn export_todos(req:HttpRequest, data:Data<GlobalState>, query:Query<ExportQuery>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+    let todos = Todo::get_user_todos(email, &mut state.todos);
+
+    // the snapshot is taken, the lock is not needed while the body is written
+    drop(state);
+
+    let format = query.format.unwrap_or(Format::Json);
+
+    HttpResponse::Ok()
+    .content_type(format.content_type())
+    .streaming(export_stream(format, todos))
+}
+
+#[post("/import", wrap = "scopes::require(scopes::TODOS_WRITE)")]
+pub async fn import_todos(req:HttpRequest, data:Data<GlobalState>, query:Query<ImportQuery>, body:Bytes) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+
+    let format = query.format.or_else(|| Format::from_content_type(req.content_type()));
+
+    if format.is_none(){
+        return HttpResponse::UnsupportedMediaType().json(Message{message:String::from("Send text/csv, application/json, text/plain (todo.txt) or text/calendar, or pass ?format=")});
+    }
+
+    let parsed = match format.unwrap() {
+        Format::Csv => formats::csv::parse(&body),
+        Format::Json => formats::from_json(&body),
+        Format::Todotxt => formats::todotxt::parse(&body),
+        Format::Ics => formats::ical::parse(&body),
+    };
+
+    let (records, errors) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return HttpResponse::BadRequest().json(Message{message:e}),
+    };
+
+    // an import with bad rows changes nothing, the caller fixes the file and sends it again
+    if !errors.is_empty(){
+        return HttpResponse::BadRequest().json(ImportReport{created: 0, updated: 0, deleted: 0, errors});
+    }
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+    let state = &mut *state;
+    let todos = &mut state.todos;
+
+    let mut report = ImportReport{created: 0, updated: 0, deleted: 0, errors};
+
+    if query.mode == ImportMode::Replace {
+        report.deleted = Todo::delete_user_todos(&email, todos).len();
+    }
+
+    for record in records {
+        let (_, updated) = Todo::import_todo(record.into_todo(email.clone()), email.clone(), todos, &mut state.todo_ids);
+
+        if updated {
+            report.updated += 1;
+        } else {
+            report.created += 1;
+        }
+    }
+
+    HttpResponse::Ok().json(report)
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+    use store::todo::Todo;
+
+    use crate::{handlers::{transfer::ImportReport, user::{AppResponse, SigninInput, SignupInput}}, init_app, prepare_global_state};
+
+    #[actix_web::test]
+    pub async fn should_export_and_import_csv(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"transfer1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"transfer1@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = actix_web::test::read_body_json(res).await;
+        let token = res.data;
+
+        let res = TestRequest::post()
+        .uri("/authed/import")
+        .insert_header(("Content-Type", "text/csv"))
+        .set_payload("title,done,project\nGo to Gym,false,health\n\"Call mom, dad\",true,\n")
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+        let res: ImportReport = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.created, 2);
+
+        let res = TestRequest::get()
+        .uri("/authed/export?format=csv")
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.headers().get("Content-Type").unwrap(), "text/csv; charset=utf-8");
+        let body = test::read_body(res).await;
+        assert_eq!(body, "id,title,done,project,priority,created_on,completed_on,contexts,extras,uid\n1,Go to Gym,false,health,,,,,,\n2,\"Call mom, dad\",true,,,,,,,\n");
+
+        // Merging the export back with an edited row updates that todo in place
+        let res = TestRequest::post()
+        .uri("/authed/import?format=csv&mode=merge")
+        .set_payload("id,title,done,project\n1,Go to Gym,true,health\n")
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        let res: ImportReport = actix_web::test::read_body_json(res).await;
+        assert_eq!((res.created, res.updated), (0, 1));
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", token))
+        .send_request(&app).await;
+
+        let res: Vec<Todo> = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.len(), 2);
+        assert!(res[0].done);
+    }
+
+    #[actix_web::test]
+    pub async fn should_replace_with_json_import(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"transfer2@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"transfer2@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = actix_web::test::read_body_json(res).await;
+        let token = res.data;
+
+        TestRequest::post()
+        .uri("/authed/todo").set_json(serde_json::json!({"title":"Old", "done":false}))
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        // One bad row rejects the whole file
+        let res = TestRequest::post()
+        .uri("/authed/import?mode=replace")
+        .set_json(serde_json::json!([{"title":"Read"}, {"title":""}, {"done":true}]))
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 400);
+        let res: ImportReport = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.errors.iter().map(|e| e.row).collect::<Vec<_>>(), vec![2, 3]);
+
+        let res = TestRequest::post()
+        .uri("/authed/import?mode=replace")
+        .set_json(serde_json::json!([{"title":"Read"}, {"title":"Write", "done":true, "project":"blog"}]))
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        let res: ImportReport = actix_web::test::read_body_json(res).await;
+        assert_eq!((res.created, res.deleted), (2, 1));
+
+        let res = TestRequest::get()
+        .uri("/authed/export?format=json")
+        .append_header(("Authorization", token))
+        .send_request(&app).await;
+
+        // the replaced todo's id is not reused
+        let res: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(res, serde_json::json!([
+            {"id":2, "title":"Read", "done":false, "project":null, "priority":null, "created_on":null, "completed_on":null, "contexts":[], "extras":{}, "uid":null},
+            {"id":3, "title":"Write", "done":true, "project":"blog", "priority":null, "created_on":null, "completed_on":null, "contexts":[], "extras":{}, "uid":null},
+        ]));
+    }
+
+    #[actix_web::test]
+    pub async fn should_round_trip_todotxt(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"transfer3@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"transfer3@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = actix_web::test::read_body_json(res).await;
+        let token = res.data;
+
+        let file = "(A) 2025-01-01 Pay rent +house @online due:2025-01-05\nx 2025-01-03 2025-01-01 Call the plumber +house @phone pri:B\nRead a book\n";
+
+        let res = TestRequest::post()
+        .uri("/authed/import")
+        .insert_header(("Content-Type", "text/plain"))
+        .set_payload(file)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        let res: ImportReport = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.created, 3);
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        let res: Vec<Todo> = actix_web::test::read_body_json(res).await;
+        assert_eq!(res[0].title, "Pay rent");
+        assert_eq!(res[0].priority, Some('A'));
+        assert_eq!(res[0].project.as_deref(), Some("house"));
+        assert!(res[1].done);
+        assert_eq!(res[1].completed_on.as_deref(), Some("2025-01-03"));
+
+        let res = TestRequest::get()
+        .uri("/authed/export?format=todotxt")
+        .append_header(("Authorization", token))
+        .send_request(&app).await;
+
+        let body = test::read_body(res).await;
+        assert_eq!(body, file);
+    }
+
+    #[actix_web::test]
+    pub async fn should_export_and_import_ics(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"transfer4@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"transfer4@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: AppResponse = actix_web::test::read_body_json(res).await;
+        let token = res.data;
+
+        let calendar = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//Test//EN\r\nBEGIN:VTODO\r\nUID:abc-123\r\nDTSTAMP:20250101T000000Z\r\nSUMMARY:Buy milk\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+
+        let res = TestRequest::post()
+        .uri("/authed/import")
+        .insert_header(("Content-Type", "text/calendar"))
+        .set_payload(calendar)
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        let res: ImportReport = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.created, 1);
+
+        TestRequest::post()
+        .uri("/authed/todo").set_json(serde_json::json!({"title":"Go to Gym", "done":true}))
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        // Importing the same UID again updates the todo instead of adding another
+        let res = TestRequest::post()
+        .uri("/authed/import")
+        .insert_header(("Content-Type", "text/calendar"))
+        .set_payload(calendar.replace("Buy milk", "Buy oat milk"))
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        let res: ImportReport = actix_web::test::read_body_json(res).await;
+        assert_eq!((res.created, res.updated), (0, 1));
+
+        let res = TestRequest::get()
+        .uri("/authed/export?format=ics")
+        .append_header(("Authorization", token))
+        .send_request(&app).await;
+
+        assert_eq!(res.headers().get("Content-Type").unwrap(), "text/calendar; charset=utf-8");
+        let body = test::read_body(res).await;
+        let body = std::str::from_utf8(&body).unwrap();
+
+        assert!(body.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(body.contains("UID:abc-123\r\n"));
+        assert!(body.contains("SUMMARY:Buy oat milk\r\n"));
+        assert!(body.contains("UID:todo-2@rust-int\r\n"));
+        assert!(body.contains("STATUS:COMPLETED\r\n"));
+        assert!(body.ends_with("END:VCALENDAR\r\n"));
+    }
+
+}
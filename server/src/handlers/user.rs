@@ -1,8 +1,16 @@
-use actix_web::{post, web::{Data, Json}, HttpResponse, Responder};
+use actix_web::{http::header, post, web::{Data, Json}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use store::user::{User};
+use store::{access_token::AccessToken, lockout::{FailedAttempts, LockoutEvent}, mfa::TotpEnrollment, refresh::RefreshToken, revocation::{RevokedToken, TokenCutoff}, session::Session, user::User};
 
-use crate::{utils::{generate_jwt_token, get_hashed_password, verify_password}, GlobalState};
+use crate::{config::LockoutConfig, handlers::{mfa::{issue_challenge, MfaRequiredResponse, MFA_CHALLENGE_TTL}, verification::{issue_verification, send_verification}}, hashing, policy::FieldError, scopes, utils::{generate_jwt_token, generate_secret_token, hash_token, needs_rehash, Claims}, CombinedState, GlobalState};
+
+#[derive(Deserialize, Serialize)]
+pub struct SignupInput {
+    pub email: String,
+    pub name: String,
+    pub password: String,
+}
 
 #[derive(Deserialize, Serialize)]
 pub struct SigninInput {
@@ -15,19 +23,85 @@ pub struct AppResponse{
     pub data:String
 }
 
+/// A 400 for input that failed validation, `errors` has one entry per problem.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ValidationResponse{
+    pub data: String,
+    pub errors: Vec<FieldError>,
+}
+
+/// `data` is the access token, as it always was, `expires_in` its lifetime in seconds.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenResponse{
+    pub data: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RefreshInput {
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct LogoutInput {
+    /// also revoke this refresh token, and the tokens refreshed from the same signin
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct LogoutAllInput {
+    /// unix seconds, defaults to now
+    pub before: Option<i64>,
+}
+
+// Just enough to catch typos and junk, the verification mail is the real check
+fn is_valid_email(email:&str) -> bool{
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.split('.').count() >= 2
+                && domain.split('.').all(|part| !part.is_empty())
+                && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        },
+        None => false,
+    }
+}
+
+// A refresh token to be stored, the family, its lifetime and the user are filled in by the
+// caller or by `rotate`
+fn new_refresh_token(data:&GlobalState) -> (String, RefreshToken){
+    let token = generate_secret_token();
+    let now = Utc::now().timestamp();
+
+    let record = RefreshToken{
+        token_hash: hash_token(&token),
+        family_id: String::new(),
+        user_email: String::new(),
+        created_at: now,
+        expires_at: now + data.config.jwt.refresh_ttl,
+        family_expires_at: now + data.config.jwt.refresh_family_ttl,
+        replaced: false,
+    };
+
+    (token, record)
+}
+
 #[post("/signup")]
-async fn signup(data:Data<GlobalState>, input: Json<User>) -> impl Responder {
+async fn signup(data:Data<GlobalState>, input: Json<SignupInput>) -> impl Responder {
 
-    let state_result = data.overall_state.lock();
+    let mut errors = data.config.password.check("password", &input.password, &input.email, &input.name);
 
-    if state_result.is_err(){
-        return HttpResponse::InternalServerError().json(AppResponse{data:String::from("Internal Error")});
+    if !is_valid_email(&input.email){
+        errors.insert(0, FieldError::new("email", "invalid", String::from("Enter a valid email")));
     }
 
-    let mut state = state_result.unwrap();
-    let users = &mut state.users;
+    if !errors.is_empty(){
+        return HttpResponse::BadRequest().json(ValidationResponse{data:String::from("Check the highlighted fields"), errors});
+    }
 
-    let hashed_password_res = get_hashed_password(&input.password);
+    let hashed_password_res = hashing::hash_password(&data, &input.password).await;
 
     if hashed_password_res.is_err(){
         println!("error while hashing password");
@@ -38,19 +112,149 @@ async fn signup(data:Data<GlobalState>, input: Json<User>) -> impl Responder {
         email: input.email.clone(), 
         name: input.name.clone(), 
         password:hashed_password_res.unwrap(), 
+        verified: false,
+        // ADMIN_EMAILS only count once the address is proven, see `verify_email`
+        roles: vec![],
+        disabled: false,
+    };
+
+    let (val, token) = {
+        let state_result = data.overall_state.lock();
+
+        if state_result.is_err(){
+            return HttpResponse::InternalServerError().json(AppResponse{data:String::from("Internal Error")});
+        }
+
+        let mut state = state_result.unwrap();
+
+        let res = store::user::User::add_user(&mut state.users, &user);
+
+        if let Err(e) = res {
+            return HttpResponse::BadRequest().json(AppResponse{data:e});
+        }
+
+        (res.unwrap(), issue_verification(&mut state, &user.email, Utc::now().timestamp()))
     };
 
-    let res = store::user::User::add_user(users, &user);
-    
-    match res {
-        Ok(val) => HttpResponse::Ok().json(AppResponse{data:val}),
-        Err(e) => HttpResponse::BadRequest().json(AppResponse{data:e}),
+    // not awaited, a slow mail server must not hold up the signup, send_verification logs failures
+    let (data, email) = (data.clone(), user.email.clone());
+    actix_web::rt::spawn(async move {
+        send_verification(&data, &email, &token).await;
+    });
+
+    HttpResponse::Ok().json(AppResponse{data:val})
+
+}
+
+// peer_addr and not the forwarded headers, a client could pick a fresh IP for every attempt
+pub(crate) fn signin_keys(req:&HttpRequest, email:&str) -> (String, String){
+    let ip = req.peer_addr().map(|a| a.ip().to_string()).unwrap_or_default();
+    (account_key(email), format!("ip:{}", ip))
+}
+
+/// The lockout key counting failures against the account itself.
+pub(crate) fn account_key(email:&str) -> String{
+    format!("account:{}", email)
+}
+
+/// Whether signins for `keys` may go ahead right now, for checks made outside the state lock.
+pub(crate) fn not_throttled(data:&GlobalState, keys:&(String, String)) -> bool{
+    data.overall_state.lock().is_ok_and(|state| throttled(&state, keys, Utc::now().timestamp()).is_none())
+}
+
+/// A 429 while the account or the client IP are blocked after failed signins.
+pub(crate) fn throttled(state:&CombinedState, keys:&(String, String), now:i64) -> Option<HttpResponse>{
+    let blocked_until = [&keys.0, &keys.1].iter()
+        .filter_map(|key| FailedAttempts::blocked_until(&state.failed_attempts, key, now))
+        .max()?;
+
+    Some(HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, (blocked_until - now).to_string()))
+        .json(AppResponse{data:String::from("Too many failed signins, try again later")}))
+}
+
+/// Counts a failed signin for both keys, blocking them as `LockoutConfig` says.
+pub(crate) fn count_failure(state:&mut CombinedState, lockout:&LockoutConfig, keys:&(String, String)){
+    let now = Utc::now().timestamp();
+
+    for (key, free_attempts, threshold) in [
+        (&keys.0, lockout.free_attempts, lockout.threshold),
+        (&keys.1, lockout.ip_threshold, lockout.ip_threshold),
+    ] {
+        let failures = FailedAttempts::record_failure(&mut state.failed_attempts, key, now, lockout.lockout_seconds);
+        let (delay, locked) = lockout.delay(failures, free_attempts, threshold);
+
+        if delay > 0 {
+            FailedAttempts::block(&mut state.failed_attempts, key, now + delay);
+        }
+
+        if locked {
+            println!("Locked out {} after {} failed signins", key, failures);
+            LockoutEvent::record(&mut state.lockout_events, LockoutEvent{key: key.clone(), failures, locked_at: now, locked_until: now + delay});
+        }
     }
+}
+
+/// Creates a session for `email` with its access and refresh token.
+pub(crate) fn start_session(req:&HttpRequest, data:&GlobalState, state:&mut CombinedState, email:&str) -> Result<TokenResponse, String>{
+    let session_id = generate_secret_token()[..32].to_string();
+    let roles = User::get_user(&state.users, &email.to_string()).map(|u| u.roles).unwrap_or_default();
 
+    let token = generate_jwt_token(&data.config.jwt, email.to_string(), Some(session_id.clone()), roles)?;
+
+    let (refresh_token, mut record) = new_refresh_token(data);
+    record.family_id = session_id.clone();
+    record.user_email = email.to_string();
+
+    let now = record.created_at;
+
+    RefreshToken::issue(&mut state.refresh_tokens, record);
+
+    Session::add_session(&mut state.sessions, Session{
+        id: session_id,
+        user_email: email.to_string(),
+        created_at: now,
+        last_seen: now,
+        user_agent: req.headers().get(header::USER_AGENT).and_then(|h| h.to_str().ok()).map(String::from),
+        ip: req.connection_info().realip_remote_addr().map(String::from),
+    });
+
+    Ok(TokenResponse{data:token, refresh_token, expires_in:data.config.jwt.ttl})
 }
 
 #[post("/signin")]
-async fn signin(data: Data<GlobalState>, input:Json<SigninInput>) -> impl Responder {
+async fn signin(req:HttpRequest, data: Data<GlobalState>, input:Json<SigninInput>) -> impl Responder {
+
+    let keys = signin_keys(&req, &input.email);
+
+    let res = {
+        let state_result = data.overall_state.lock();
+
+        if state_result.is_err(){
+            return HttpResponse::InternalServerError().json(AppResponse{data:String::from("Internal Error")});
+        }
+
+        let state = state_result.unwrap();
+
+        if let Some(res) = throttled(&state, &keys, Utc::now().timestamp()) {
+            return res;
+        }
+
+        store::user::User::get_user(&state.users, &input.email)
+    };
+
+    // the wait for a permit can be long under load, a lockout starting meanwhile makes the hash
+    // pointless. Skipping it counts as a wrong password, the check below answers with the 429
+    let valid = match &res {
+        Some(user) => hashing::verify_password_if(&data, &user.password, &input.password, || not_throttled(&data, &keys)).await.unwrap_or(false),
+        None => false,
+    };
+
+    // the password is only at hand now, so this is when an outdated hash gets replaced
+    let rehashed = match &res {
+        Some(user) if valid && needs_rehash(&data.config.hashing, &user.password) => hashing::hash_password(&data, &input.password).await.ok(),
+        _ => None,
+    };
 
     let state_result = data.overall_state.lock();
 
@@ -59,41 +263,209 @@ async fn signin(data: Data<GlobalState>, input:Json<SigninInput>) -> impl Respon
     }
 
     let mut state = state_result.unwrap();
-    let users = &mut state.users;
 
-    let res = store::user::User::get_user(users, &input.email);
+    // guesses sent at once all got past the first check, those finishing after a lockout learn nothing
+    if let Some(res) = throttled(&state, &keys, Utc::now().timestamp()) {
+        return res;
+    }
 
-    if res.is_none() {
-        return HttpResponse::BadRequest().json(AppResponse{data:String::from("Signup first")});
+    // an unknown email counts as a failure too, so guessing accounts costs the same as guessing passwords
+    let failure = match &res {
+        None => Some("Signup first"),
+        Some(_) if !valid => Some("Enter valid Password"),
+        Some(_) => None,
+    };
+
+    if let Some(message) = failure {
+        count_failure(&mut state, &data.config.lockout, &keys);
+        return HttpResponse::BadRequest().json(AppResponse{data:String::from(message)});
     }
 
     let user = res.unwrap();
 
-    let verify_res = verify_password(&user.password, &input.password);
+    // unless the password was changed while this one was hashed
+    if let Some(hashed) = rehashed {
+        if User::get_user(&state.users, &input.email).is_some_and(|u| u.password == user.password) {
+            let _ = User::set_password(&mut state.users, &input.email, hashed);
+        }
+    }
+
+    if user.disabled {
+        return HttpResponse::Forbidden().json(AppResponse{data:String::from("Account disabled")});
+    }
+
+    if TotpEnrollment::is_enabled(&state.totp_enrollments, &input.email) {
+        let mfa_token = issue_challenge(&mut state, &input.email, Utc::now().timestamp());
+        return HttpResponse::Ok().json(MfaRequiredResponse{
+            data: String::from("Enter a code from your authenticator app or a recovery code"),
+            mfa_token,
+            expires_in: MFA_CHALLENGE_TTL,
+        });
+    }
+
+    // only now, with a second factor the password alone must not clear the count of wrong codes.
+    // The IP counter is left to expire, so one working account can not clear it for guesses at others
+    FailedAttempts::reset(&mut state.failed_attempts, &keys.0);
 
-    if verify_res == false{
-        return HttpResponse::BadRequest().json(AppResponse{data:String::from("Enter valid Password")});
+    match start_session(&req, &data, &mut state, &input.email) {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(_) => HttpResponse::InternalServerError().json(AppResponse{data:String::from("Internal Error")}),
     }
 
-    let token_res = generate_jwt_token(input.email.clone());
+}
+
+/// Trades a refresh token for a new access token and a new refresh token. Each refresh token
+/// works once, replaying one signs its family out.
+#[post("/refresh")]
+async fn refresh(data: Data<GlobalState>, input:Json<RefreshInput>) -> impl Responder {
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(AppResponse{data:String::from("Internal Error")});
+    }
+
+    let mut state = state_result.unwrap();
+
+    let (refresh_token, record) = new_refresh_token(&data);
+    let token_hash = hash_token(&input.refresh_token);
+
+    let session_id = state.refresh_tokens.iter()
+        .find(|t| t.token_hash == token_hash)
+        .map(|t| t.family_id.clone());
+
+    let res = RefreshToken::rotate(&mut state.refresh_tokens, &token_hash, record);
+
+    if let Err(e) = res {
+        // a replayed token revoked its family, its session goes with it
+        if let Some(session_id) = session_id {
+            if !state.refresh_tokens.iter().any(|t| t.family_id == session_id) {
+                Session::remove(&mut state.sessions, &session_id);
+            }
+        }
+
+        return HttpResponse::Unauthorized().json(AppResponse{data:e});
+    }
+
+    let record = res.unwrap();
+
+    let user = User::get_user(&state.users, &record.user_email);
+
+    if user.as_ref().is_none_or(|u| u.disabled){
+        return HttpResponse::Forbidden().json(AppResponse{data:String::from("Account disabled")});
+    }
+
+    Session::touch(&mut state.sessions, &record.family_id, record.created_at);
+
+    let token_res = generate_jwt_token(&data.config.jwt, record.user_email, Some(record.family_id), user.unwrap().roles);
 
     if token_res.is_err(){
         return HttpResponse::InternalServerError().json(AppResponse{data:String::from("Internal Error")});
     }
-    
-    let token = token_res.unwrap();
 
-    HttpResponse::Ok().json(AppResponse{data:token})
+    HttpResponse::Ok().json(TokenResponse{data:token_res.unwrap(), refresh_token, expires_in:data.config.jwt.ttl})
 
 }
 
 
+/// Revokes the access token the request was made with.
+#[post("/logout", wrap = "scopes::require(scopes::ACCOUNT)")]
+async fn logout(req:HttpRequest, data: Data<GlobalState>, input:Option<Json<LogoutInput>>) -> impl Responder {
+
+    let claims_ext = req.extensions().get::<Claims>().cloned();
+
+    if claims_ext.is_none(){
+        return HttpResponse::Unauthorized().json(AppResponse{data:String::from("UNAUTHORIZED")});
+    }
+
+    let claims = claims_ext.unwrap();
+
+    // tokens from before `jti` existed can only be logged out everywhere
+    if claims.jti.is_empty(){
+        return HttpResponse::BadRequest().json(AppResponse{data:String::from("This token can not be logged out alone, log out everywhere")});
+    }
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(AppResponse{data:String::from("Internal Error")});
+    }
+
+    let mut state = state_result.unwrap();
+
+    // a bad refresh token is reported, but the access token is logged out all the same
+    let refresh_res = match input.and_then(|i| i.into_inner().refresh_token) {
+        Some(refresh_token) => RefreshToken::revoke_token_family(&mut state.refresh_tokens, &hash_token(&refresh_token), &claims.sub).map(|_| ()),
+        None => Ok(()),
+    };
+
+    // the session ends with its last token, unless the refresh token lives on
+    if let Some(sid) = &claims.sid {
+        if !state.refresh_tokens.iter().any(|t| t.family_id == *sid) {
+            Session::remove(&mut state.sessions, sid);
+        }
+    }
+
+    RevokedToken::revoke(&mut state.revoked_tokens, RevokedToken{
+        jti: claims.jti,
+        user_email: claims.sub,
+        expires_at: claims.exp as i64,
+    }, Utc::now().timestamp());
+
+    if let Err(e) = refresh_res {
+        return HttpResponse::BadRequest().json(AppResponse{data:e});
+    }
+
+    HttpResponse::Ok().json(AppResponse{data:String::from("Logged out")})
+}
+
+/// Invalidates every access token of the user issued at or before `before`, and all their
+/// refresh, personal access and OAuth tokens.
+#[post("/logout-all", wrap = "scopes::require(scopes::ACCOUNT)")]
+async fn logout_all(req:HttpRequest, data: Data<GlobalState>, input:Option<Json<LogoutAllInput>>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return HttpResponse::Unauthorized().json(AppResponse{data:String::from("UNAUTHORIZED")});
+    }
+
+    let email = email_ext.unwrap();
+    let now_ms = Utc::now().timestamp_millis();
+    let before = input.and_then(|i| i.into_inner().before);
+
+    // a cutoff in the future would lock the user out until then
+    if before.is_some_and(|b| b > now_ms / 1000) {
+        return HttpResponse::BadRequest().json(AppResponse{data:String::from("before can not be in the future")});
+    }
+
+    // `before` is in seconds and includes the whole second, up to now
+    let not_before_ms = before.map(|b| (b * 1000 + 999).min(now_ms)).unwrap_or(now_ms);
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(AppResponse{data:String::from("Internal Error")});
+    }
+
+    let mut state = state_result.unwrap();
+
+    TokenCutoff::set(&mut state.token_cutoffs, TokenCutoff{user_email: email.clone(), not_before_ms});
+    RefreshToken::revoke_user(&mut state.refresh_tokens, &email);
+    Session::revoke_user(&mut state.sessions, &email);
+    AccessToken::revoke_user(&mut state.access_tokens, &email);
+
+    HttpResponse::Ok().json(AppResponse{data:String::from("Logged out everywhere")})
+}
+
 #[cfg(test)]
 mod tests{
     use actix_web::test::{self, TestRequest};
-    use store::user::User;
+    use chrono::Utc;
+
+    use std::sync::Arc;
 
-    use crate::{handlers::user::{AppResponse, SigninInput}, init_app, prepare_global_state};
+    use crate::{config::{AppConfig, HashConfig}, handlers::user::{AppResponse, LogoutInput, RefreshInput, SigninInput, SignupInput, TokenResponse, ValidationResponse}, init_app, prepare_global_state, prepare_global_state_with, utils::{generate_jwt_token, needs_rehash}, GlobalState};
 
 
     #[actix_web::test]
@@ -102,7 +474,7 @@ mod tests{
         let app = init_app!(state);
         let app = test::init_service(app).await;
 
-        let input = User{
+        let input = SignupInput{
             email:"vk1@gmail.com".to_string(),
             name:"VK".to_string(),
             password:"Random123".to_string(),
@@ -113,13 +485,43 @@ mod tests{
         assert_eq!(res.data, String::from("User created Successfully"));
     }   
 
+    #[actix_web::test]
+    pub async fn should_reject_weak_signup(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"vk-weak".to_string(),
+            name:"VK".to_string(),
+            password:"".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+        assert_eq!(res.status().as_u16(), 400);
+
+        let res: ValidationResponse = actix_web::test::read_body_json(res).await;
+        let errors: Vec<(&str, &str)> = res.errors.iter().map(|e| (e.field.as_str(), e.code.as_str())).collect();
+        assert_eq!(errors, vec![("email", "invalid"), ("password", "too_short"), ("password", "too_simple")]);
+
+        let input = SignupInput{
+            email:"vk-weak@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Passw0rd!".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+        let res: ValidationResponse = actix_web::test::read_body_json(res).await;
+        assert_eq!(res.errors[0].code, "breached");
+    }
+
     #[actix_web::test]
     pub async fn should_signin(){
         let state = prepare_global_state();
         let app = init_app!(state);
         let app = test::init_service(app).await;
 
-        let input = User{
+        let input = SignupInput{
             email:"vk2@gmail.com".to_string(),
             name:"VK".to_string(),
             password:"Random1234".to_string(),
@@ -146,7 +548,7 @@ mod tests{
         let app = init_app!(state);
         let app = test::init_service(app).await;
 
-        let input = User{
+        let input = SignupInput{
             email:"vk3@gmail.com".to_string(),
             name:"VK".to_string(),
             password:"Random1234".to_string(),
@@ -170,5 +572,401 @@ mod tests{
         assert_eq!(res.data, String::from("Enter valid Password"));
     }
 
+    #[actix_web::test]
+    pub async fn should_throttle_failed_signins(){
+        let mut config = AppConfig::dev();
+        config.lockout.free_attempts = 2;
+        config.lockout.threshold = 4;
+
+        let state = prepare_global_state_with(config);
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"vk4@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let signin = |password:&str| TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk4@gmail.com".to_string(), password:password.to_string()})
+            .peer_addr("10.0.0.7:40000".parse().unwrap());
+
+        for _ in 0..3 {
+            let res = signin("INVALID_PASSWORD").send_request(&app).await;
+            assert_eq!(res.status().as_u16(), 400);
+        }
+
+        // the third failure blocks the account for a second
+        let account_key = "account:vk4@gmail.com";
+
+        {
+            let mut combined = state.overall_state.lock().unwrap();
+            let attempt = combined.failed_attempts.iter().find(|a| a.key == account_key).unwrap();
+            assert_eq!(attempt.failures, 3);
+            assert_eq!(attempt.blocked_until, attempt.last_failure + 1);
+
+            combined.failed_attempts.iter_mut().for_each(|a| a.blocked_until = 0);
+        }
+
+        let res = signin("INVALID_PASSWORD").send_request(&app).await;
+        assert_eq!(res.status().as_u16(), 400);
+
+        let res = signin("Random1234").send_request(&app).await;
+        assert_eq!(res.status().as_u16(), 429);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), "900");
+
+        {
+            let mut combined = state.overall_state.lock().unwrap();
+            assert_eq!(combined.lockout_events.len(), 1);
+            assert_eq!(combined.lockout_events[0].key, account_key);
+            assert_eq!(combined.lockout_events[0].failures, 4);
+
+            combined.failed_attempts.iter_mut().for_each(|a| a.blocked_until = 0);
+        }
+
+        let res = signin("Random1234").send_request(&app).await;
+        assert!(res.status().is_success());
+
+        // success starts the account over, the IP keeps its count
+        let combined = state.overall_state.lock().unwrap();
+        assert!(!combined.failed_attempts.iter().any(|a| a.key == account_key));
+        assert_eq!(combined.failed_attempts.iter().find(|a| a.key == "ip:10.0.0.7").unwrap().failures, 4);
+    }
+
+    #[actix_web::test]
+    pub async fn should_reject_token_for_other_audience(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let mut other = AppConfig::dev().jwt;
+        other.audience = String::from("another-service");
+
+        let token = generate_jwt_token(&other, String::from("vk1@gmail.com"), None, vec![]).unwrap();
+
+        let req = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", token))
+        .to_request();
+
+        let res = test::try_call_service(&app, req).await;
+        assert!(res.is_err());
+
+        let token = generate_jwt_token(&AppConfig::dev().jwt, String::from("vk1@gmail.com"), None, vec![]).unwrap();
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", token))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    pub async fn should_rotate_refresh_tokens(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"refresh1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"refresh1@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let signin: TokenResponse = actix_web::test::read_body_json(res).await;
+        assert_eq!(signin.expires_in, AppConfig::dev().jwt.ttl);
+
+        let res = TestRequest::post().uri("/user/refresh")
+        .set_json(RefreshInput{refresh_token: signin.refresh_token.clone()})
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+        let refreshed: TokenResponse = actix_web::test::read_body_json(res).await;
+        assert_ne!(refreshed.refresh_token, signin.refresh_token);
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", refreshed.data))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        // the next token in the family keeps working
+        let res = TestRequest::post().uri("/user/refresh")
+        .set_json(RefreshInput{refresh_token: refreshed.refresh_token.clone()})
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+        let latest: TokenResponse = actix_web::test::read_body_json(res).await;
+
+        // replaying an exchanged token is refused and signs the whole family out
+        let res = TestRequest::post().uri("/user/refresh")
+        .set_json(RefreshInput{refresh_token: signin.refresh_token})
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 401);
+
+        let res = TestRequest::post().uri("/user/refresh")
+        .set_json(RefreshInput{refresh_token: latest.refresh_token})
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 401);
+
+        let res = TestRequest::post().uri("/user/refresh")
+        .set_json(RefreshInput{refresh_token: String::from("not-a-token")})
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 401);
+    }
+
+    #[actix_web::test]
+    pub async fn should_end_refresh_family_after_its_lifetime(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"refresh3@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"refresh3@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let signin: TokenResponse = actix_web::test::read_body_json(res).await;
+
+        let res = TestRequest::post().uri("/user/refresh")
+        .set_json(RefreshInput{refresh_token: signin.refresh_token})
+        .send_request(&app).await;
 
-}
\ No newline at end of file
+        let refreshed: TokenResponse = actix_web::test::read_body_json(res).await;
+
+        {
+            let mut overall_state = state.overall_state.lock().unwrap();
+            let family_expires_at = overall_state.refresh_tokens.iter().map(|t| t.family_expires_at).collect::<Vec<_>>();
+
+            // refreshing carried the signin's deadline over and kept the new token within it
+            assert_eq!(family_expires_at.len(), 2);
+            assert_eq!(family_expires_at[0], family_expires_at[1]);
+            assert!(overall_state.refresh_tokens.iter().all(|t| t.expires_at <= t.family_expires_at));
+
+            // as if the family's lifetime had passed while its latest token is still fresh
+            for token in overall_state.refresh_tokens.iter_mut() {
+                token.family_expires_at = Utc::now().timestamp();
+            }
+        }
+
+        let res = TestRequest::post().uri("/user/refresh")
+        .set_json(RefreshInput{refresh_token: refreshed.refresh_token})
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 401);
+
+        let overall_state = state.overall_state.lock().unwrap();
+        assert!(overall_state.refresh_tokens.iter().all(|t| t.user_email != "refresh3@gmail.com"));
+        assert!(overall_state.sessions.iter().all(|s| s.user_email != "refresh3@gmail.com"));
+    }
+
+    #[actix_web::test]
+    pub async fn should_logout(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"logout1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let mut sessions = vec![];
+
+        for _ in 0..2 {
+            let input = SigninInput{
+                email:"logout1@gmail.com".to_string(),
+                password:"Random1234".to_string(),
+            };
+
+            let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+            let res: TokenResponse = actix_web::test::read_body_json(res).await;
+            sessions.push(res);
+        }
+
+        let res = TestRequest::post()
+        .uri("/authed/logout")
+        .append_header(("Authorization", sessions[0].data.clone()))
+        .set_json(LogoutInput{refresh_token: Some(sessions[0].refresh_token.clone())})
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", sessions[0].data.clone()))
+        .to_request();
+
+        let res = test::try_call_service(&app, req).await;
+        assert_eq!(res.err().map(|e| e.as_response_error().status_code().as_u16()), Some(401));
+
+        let res = TestRequest::post().uri("/user/refresh")
+        .set_json(RefreshInput{refresh_token: sessions[0].refresh_token.clone()})
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 401);
+
+        // the other session is untouched
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", sessions[1].data.clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        // a refresh token that is not theirs is refused, the access token is logged out anyway
+        let res = TestRequest::post()
+        .uri("/authed/logout")
+        .append_header(("Authorization", sessions[1].data.clone()))
+        .set_json(LogoutInput{refresh_token: Some(String::from("not-a-refresh-token"))})
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 400);
+
+        let req = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", sessions[1].data.clone()))
+        .to_request();
+
+        let res = test::try_call_service(&app, req).await;
+        assert_eq!(res.err().map(|e| e.as_response_error().status_code().as_u16()), Some(401));
+    }
+
+    #[actix_web::test]
+    pub async fn should_logout_everywhere(){
+        let state = prepare_global_state();
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"logout2@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let mut sessions = vec![];
+
+        for _ in 0..2 {
+            let input = SigninInput{
+                email:"logout2@gmail.com".to_string(),
+                password:"Random1234".to_string(),
+            };
+
+            let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+            let res: TokenResponse = actix_web::test::read_body_json(res).await;
+            sessions.push(res);
+        }
+
+        let res = TestRequest::post()
+        .uri("/authed/logout-all")
+        .append_header(("Authorization", sessions[0].data.clone()))
+        .set_json(serde_json::json!({"before": 4_000_000_000i64}))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 400);
+
+        let res = TestRequest::post()
+        .uri("/authed/logout-all")
+        .append_header(("Authorization", sessions[0].data.clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        for session in &sessions {
+            let req = TestRequest::get()
+            .uri("/authed/todos")
+            .append_header(("Authorization", session.data.clone()))
+            .to_request();
+
+            let res = test::try_call_service(&app, req).await;
+            assert_eq!(res.err().map(|e| e.as_response_error().status_code().as_u16()), Some(401));
+
+            let res = TestRequest::post().uri("/user/refresh")
+            .set_json(RefreshInput{refresh_token: session.refresh_token.clone()})
+            .send_request(&app).await;
+
+            assert_eq!(res.status().as_u16(), 401);
+        }
+
+        // signing in again right away, most likely within the same second, works
+        let input = SigninInput{
+            email:"logout2@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: TokenResponse = actix_web::test::read_body_json(res).await;
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", res.data))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    pub async fn should_rehash_outdated_password_on_signin(){
+        let mut config = AppConfig::dev();
+        config.hashing = HashConfig{memory_kib: 1024, iterations: 1, parallelism: 1};
+
+        let state = prepare_global_state_with(config.clone());
+        let app = test::init_service(init_app!(state)).await;
+
+        let input = SignupInput{email:"vk@gmail.com".to_string(), name:"VK".to_string(), password:"Random1234".to_string()};
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let old_hash = state.overall_state.lock().unwrap().users[0].password.clone();
+
+        // the same accounts, after the parameters were raised
+        config.hashing = HashConfig{memory_kib: 2048, iterations: 2, parallelism: 1};
+        let raised = GlobalState{config: Arc::new(config.clone()), ..state.clone()};
+        let app = test::init_service(init_app!(raised)).await;
+
+        assert!(needs_rehash(&config.hashing, &old_hash));
+
+        let signin = || TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+
+        assert_eq!(test::call_service(&app, signin()).await.status().as_u16(), 200);
+
+        let new_hash = state.overall_state.lock().unwrap().users[0].password.clone();
+
+        assert_ne!(new_hash, old_hash);
+        assert!(new_hash.contains("m=2048,t=2,p=1"));
+        assert!(!needs_rehash(&config.hashing, &new_hash));
+
+        assert_eq!(test::call_service(&app, signin()).await.status().as_u16(), 200);
+        assert_eq!(state.overall_state.lock().unwrap().users[0].password, new_hash);
+    }
+}
@@ -0,0 +1,236 @@
+use actix_web::{get, http::header, post, web::{self, Data, Query}, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use store::{user::User, verification::VerificationToken};
+
+use crate::{handlers::todo::Message, mailer::Email, scopes, utils::{generate_secret_token, hash_token}, CombinedState, GlobalState};
+
+const VERIFICATION_TTL: i64 = 60 * 60 * 24 * 2;
+// between two verification mails to the same account
+const RESEND_INTERVAL: i64 = 60;
+
+#[derive(Deserialize, Serialize)]
+pub struct VerifyQuery {
+    pub token: String,
+}
+
+/// Stores a new verification token for the user and returns it, the previous link stops working.
+pub fn issue_verification(state: &mut CombinedState, email: &str, now: i64) -> String{
+    let token = generate_secret_token();
+
+    VerificationToken::issue(&mut state.verification_tokens, VerificationToken{
+        token_hash: hash_token(&token),
+        user_email: email.to_string(),
+        sent_at: now,
+        expires_at: now + VERIFICATION_TTL,
+    });
+
+    token
+}
+
+/// Mails the verification link. Failures are only logged, the user can ask for another mail.
+pub async fn send_verification(data: &GlobalState, email: &str, token: &str){
+    let link = format!("{}/user/verify-email?token={}", data.config.mail.public_url, token);
+
+    let email = Email{
+        to: email.to_string(),
+        subject: String::from("Verify your email"),
+        body: format!("Open {}\nto verify your email. The link expires in two days.\n\nIf you did not sign up, ignore this mail.", link),
+    };
+
+    let mailer = data.mailer.clone();
+
+    match web::block(move || mailer.send(&email)).await {
+        Ok(Ok(())) => {},
+        Ok(Err(e)) => println!("could not send verification mail: {}", e),
+        Err(e) => println!("could not send verification mail: {}", e),
+    }
+}
+
+/// The link from the mail, a GET so it works when clicked.
+#[get("/verify-email")]
+pub async fn verify_email(data:Data<GlobalState>, query:Query<VerifyQuery>) -> impl Responder {
+
+    let state_result = data.overall_state.lock();
+
+    if state_result.is_err(){
+        return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+    }
+
+    let mut state = state_result.unwrap();
+
+    let email = VerificationToken::redeem(&mut state.verification_tokens, &hash_token(&query.token), Utc::now().timestamp());
+
+    if let Err(e) = email {
+        return HttpResponse::BadRequest().json(Message{message:e});
+    }
+
+    let email = email.unwrap();
+
+    match User::set_verified(&mut state.users, &email) {
+        Ok(val) => {
+            for role in data.config.initial_roles(&email) {
+                let _ = User::grant_role(&mut state.users, &email, &role);
+            }
+            HttpResponse::Ok().json(Message{message:val})
+        },
+        Err(e) => HttpResponse::BadRequest().json(Message{message:e}),
+    }
+}
+
+/// Sends the signed in user a fresh verification link, at most once a minute.
+#[post("/resend-verification", wrap = "scopes::require(scopes::ACCOUNT)")]
+pub async fn resend_verification(req:HttpRequest, data:Data<GlobalState>) -> impl Responder {
+
+    let email_ext = req.extensions().get::<String>().cloned();
+
+    if email_ext.is_none(){
+        return  HttpResponse::Unauthorized().json(String::from("UNAUTHORIZEDD"));
+    }
+
+    let email = email_ext.unwrap();
+    let now = Utc::now().timestamp();
+
+    let token = {
+        let state_result = data.overall_state.lock();
+
+        if state_result.is_err(){
+            return HttpResponse::InternalServerError().json(String::from("Internal Server Error"));
+        }
+
+        let mut state = state_result.unwrap();
+
+        if User::get_user(&state.users, &email).is_some_and(|u| u.verified){
+            return HttpResponse::BadRequest().json(Message{message:String::from("Email is verified already")});
+        }
+
+        if let Some(sent_at) = VerificationToken::last_sent(&state.verification_tokens, &email) {
+            let wait = sent_at + RESEND_INTERVAL - now;
+
+            if wait > 0 {
+                return HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, wait.to_string()))
+                .json(Message{message:format!("A link was sent recently, try again in {} seconds", wait)});
+            }
+        }
+
+        issue_verification(&mut state, &email, now)
+    };
+
+    send_verification(&data, &email, &token).await;
+
+    HttpResponse::Ok().json(Message{message:String::from("Verification mail sent")})
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::test::{self, TestRequest};
+
+    use crate::{config::{AppConfig, MailTransport}, handlers::user::{SigninInput, SignupInput, TokenResponse}, init_app, prepare_global_state_with};
+
+    // the signup mail goes out after the answer
+    async fn read_links(outbox: &std::path::Path) -> Vec<String>{
+        let mut links = vec![];
+
+        for _ in 0..50 {
+            let mut mails: Vec<_> = std::fs::read_dir(outbox).into_iter().flatten().map(|m| m.unwrap().path()).collect();
+            mails.sort();
+
+            links = mails.iter()
+                .map(|m| std::fs::read_to_string(m).unwrap())
+                .filter_map(|mail| mail.split("http://localhost:8080/user/verify-email?token=").nth(1)?.split_whitespace().next().map(str::to_string))
+                .collect();
+
+            if !links.is_empty() {
+                break;
+            }
+
+            actix_web::rt::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        links
+    }
+
+    #[actix_web::test]
+    pub async fn should_require_verified_email(){
+        let outbox = std::env::temp_dir().join(format!("rust-int-verify-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&outbox);
+
+        let mut config = AppConfig::dev();
+        config.mail.transport = MailTransport::Outbox(outbox.clone());
+        config.verification.required = true;
+
+        let state = prepare_global_state_with(config);
+        let app = init_app!(state);
+        let app = test::init_service(app).await;
+
+        let input = SignupInput{
+            email:"not an email".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+        assert_eq!(res.status().as_u16(), 400);
+
+        let input = SignupInput{
+            email:"verify1@gmail.com".to_string(),
+            name:"VK".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        TestRequest::post().uri("/user/signup").insert_header(("Host", "evil.example")).set_json(input).send_request(&app).await;
+
+        let input = SigninInput{
+            email:"verify1@gmail.com".to_string(),
+            password:"Random1234".to_string(),
+        };
+
+        let res = TestRequest::post().uri("/user/signin").set_json(input).send_request(&app).await;
+        let res: TokenResponse = test::read_body_json(res).await;
+        let token = res.data;
+
+        let req = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", token.clone()))
+        .to_request();
+
+        let res = test::try_call_service(&app, req).await;
+        assert_eq!(res.err().map(|e| e.as_response_error().status_code().as_u16()), Some(403));
+
+        // the sessions list is open to unverified accounts
+        let res = TestRequest::get()
+        .uri("/authed/sessions")
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        let res = TestRequest::post()
+        .uri("/authed/resend-verification")
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert_eq!(res.status().as_u16(), 429);
+        assert!(res.headers().get("Retry-After").is_some());
+
+        let links = read_links(&outbox).await;
+        assert_eq!(links.len(), 1);
+
+        let res = TestRequest::get().uri(&format!("/user/verify-email?token={}", links[0])).send_request(&app).await;
+        assert!(res.status().is_success());
+
+        let res = TestRequest::get().uri(&format!("/user/verify-email?token={}", links[0])).send_request(&app).await;
+        assert_eq!(res.status().as_u16(), 400);
+
+        let res = TestRequest::get()
+        .uri("/authed/todos")
+        .append_header(("Authorization", token.clone()))
+        .send_request(&app).await;
+
+        assert!(res.status().is_success());
+
+        std::fs::remove_dir_all(outbox).unwrap();
+    }
+}
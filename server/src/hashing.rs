@@ -0,0 +1,242 @@
+//! Password hashing for the handlers. An Argon2 hash takes tens of milliseconds of CPU and
+//! megabytes of memory on purpose, so it runs on the blocking thread pool instead of an actix
+//! worker, and no more than `HASH_CONCURRENCY` run at once. Do not hold the state lock across
+//! these, everyone else would wait for the hash.
+
+use std::sync::OnceLock;
+
+use actix_web::web;
+
+use crate::{utils, GlobalState};
+
+/// Hashes `password` with the configured Argon2 parameters.
+pub async fn hash_password(data: &GlobalState, password: &str) -> Result<String, String>{
+    let _permit = data.hash_permits.acquire().await.map_err(|_| String::from("Password hashing is shut down"))?;
+
+    let config = data.config.hashing.clone();
+    let password = password.to_string();
+
+    web::block(move || utils::get_hashed_password(&config, &password))
+        .await
+        .map_err(|e| format!("Password hashing failed: {}", e))?
+}
+
+/// Whether `password` matches `actual_hash`.
+pub async fn verify_password(data: &GlobalState, actual_hash: &str, password: &str) -> bool{
+    verify_password_if(data, actual_hash, password, || true).await.unwrap_or(false)
+}
+
+/// Like `verify_password`, but asks `proceed` once it is this request's turn and skips the hash,
+/// returning `None`, when the answer is no. A signin that waited out a lockout starting meanwhile
+/// then costs no hash.
+pub async fn verify_password_if(data: &GlobalState, actual_hash: &str, password: &str, proceed: impl FnOnce() -> bool) -> Option<bool>{
+    let permit = data.hash_permits.acquire().await;
+
+    if permit.is_err(){
+        return Some(false);
+    }
+
+    if !proceed() {
+        return None;
+    }
+
+    let actual_hash = actual_hash.to_string();
+    let password = password.to_string();
+
+    let valid = web::block(move || utils::verify_password(&actual_hash, &password))
+        .await
+        .unwrap_or(false);
+
+    Some(valid)
+}
+
+// Hashed on first use with the configured parameters, it matches no password anyone sends
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+/// Spends the time of a `verify_password` for an account that does not exist. Always `false`.
+pub async fn verify_dummy(data: &GlobalState, password: &str) -> bool{
+    let permit = data.hash_permits.acquire().await;
+
+    if permit.is_err(){
+        return false;
+    }
+
+    let config = data.config.hashing.clone();
+    let password = password.to_string();
+
+    let _ = web::block(move || {
+        let hash = DUMMY_HASH.get_or_init(|| utils::get_hashed_password(&config, &utils::generate_secret_token()).unwrap_or_default());
+        utils::verify_password(hash, &password)
+    }).await;
+
+    false
+}
+
+
+#[cfg(test)]
+mod tests{
+    use std::{cell::RefCell, net::TcpListener, pin::pin, thread, time::{Duration, Instant}};
+
+    use actix_web::{test::{self, TestRequest}, web, HttpServer};
+    use futures_util::future::{join, join_all, select, Either};
+    use store::user::User;
+
+    use crate::{handlers::user::{SigninInput, SignupInput}, init_app, prepare_global_state, utils::{generate_jwt_token, get_hashed_password}};
+
+    #[actix_web::test]
+    pub async fn should_serve_other_requests_while_hashing(){
+        let state = prepare_global_state();
+        let app = test::init_service(init_app!(state)).await;
+
+        let input = SignupInput{email:"vk@gmail.com".to_string(), name:"VK".to_string(), password:"Random1234".to_string()};
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let jwt = generate_jwt_token(&state.config.jwt, "vk@gmail.com".to_string(), None, vec![]).unwrap();
+        let finished = RefCell::new(vec![]);
+
+        let signins = (0..4).map(|_| async {
+            let req = TestRequest::post().uri("/user/signin")
+                .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+            assert_eq!(test::call_service(&app, req).await.status().as_u16(), 200);
+            finished.borrow_mut().push("signin");
+        });
+
+        let todos = async {
+            let req = TestRequest::get().uri("/authed/todos").insert_header(("Authorization", jwt)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status().as_u16(), 200);
+            finished.borrow_mut().push("todos");
+        };
+
+        // the signins start first, hashing on the worker would hold the todos back until they are done
+        join(join_all(signins), todos).await;
+
+        assert_eq!(finished.borrow().len(), 5);
+        assert_eq!(finished.borrow()[0], "todos");
+    }
+
+    #[actix_web::test]
+    pub async fn should_answer_reads_while_every_hash_permit_is_taken(){
+        let state = prepare_global_state();
+        let app = test::init_service(init_app!(state)).await;
+
+        let input = SignupInput{email:"vk@gmail.com".to_string(), name:"VK".to_string(), password:"Random1234".to_string()};
+        TestRequest::post().uri("/user/signup").set_json(input).send_request(&app).await;
+
+        let jwt = generate_jwt_token(&state.config.jwt, "vk@gmail.com".to_string(), None, vec![]).unwrap();
+        let permits = state.hash_permits.clone().acquire_many_owned(state.config.hash_concurrency as u32).await.unwrap();
+
+        let signin = TestRequest::post().uri("/user/signin")
+            .set_json(SigninInput{email:"vk@gmail.com".to_string(), password:"Random1234".to_string()}).to_request();
+        let signin = pin!(test::call_service(&app, signin));
+
+        let read = TestRequest::get().uri("/authed/todos").insert_header(("Authorization", jwt)).to_request();
+        let read = pin!(test::call_service(&app, read));
+
+        // the signin waits for a permit that never comes while the read goes through
+        let signin = match select(signin, read).await {
+            Either::Right((res, signin)) => {
+                assert_eq!(res.status().as_u16(), 200);
+                signin
+            },
+            Either::Left(_) => panic!("the signin got a hash permit"),
+        };
+
+        drop(permits);
+        assert_eq!(signin.await.status().as_u16(), 200);
+    }
+
+    #[actix_web::test]
+    pub async fn should_skip_hash_when_told_after_the_wait(){
+        let state = prepare_global_state();
+        let hash = get_hashed_password(&state.config.hashing, "Random1234").unwrap();
+
+        assert_eq!(super::verify_password_if(&state, &hash, "Random1234", || false).await, None);
+        assert_eq!(super::verify_password_if(&state, &hash, "Random1234", || true).await, Some(true));
+    }
+
+    /// Signs in from 8 clients while 2 others keep listing todos, against a server with 2
+    /// workers. A read stuck behind a hash on its worker would take at least as long as the hash,
+    /// so nearly every read has to be quicker than one. Takes a few seconds, run it with
+    /// `cargo test --release -p server load_test -- --ignored --nocapture`.
+    #[actix_web::test]
+    #[ignore]
+    pub async fn load_test_signins_next_to_reads(){
+        const PASSWORD: &str = "Random1234";
+        let state = prepare_global_state();
+
+        let hash_time = Instant::now();
+
+        {
+            let password = get_hashed_password(&state.config.hashing, PASSWORD).unwrap();
+            let user = User{email:"vk@gmail.com".to_string(), name:"VK".to_string(), password, verified:true, roles:vec![], disabled:false};
+            User::add_user(&mut state.overall_state.lock().unwrap().users, &user).unwrap();
+        }
+
+        let hash_time = hash_time.elapsed();
+        let jwt = generate_jwt_token(&state.config.jwt, "vk@gmail.com".to_string(), None, vec![]).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let server_state = state.clone();
+        let server = HttpServer::new(move || init_app!(server_state)).workers(2).listen(listener).unwrap().run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let duration = Duration::from_secs(5);
+
+        let (signins, mut latencies) = web::block(move || {
+            let deadline = Instant::now() + duration;
+            let body = serde_json::json!({"email": "vk@gmail.com", "password": PASSWORD}).to_string();
+
+            let signins: Vec<_> = (0..8).map(|_| {
+                let (url, body) = (format!("{}/user/signin", base), body.clone());
+                thread::spawn(move || {
+                    let mut done = 0;
+                    while Instant::now() < deadline {
+                        let res = ureq::post(&url).header("Content-Type", "application/json").send(&body);
+                        assert!(res.is_ok());
+                        done += 1;
+                    }
+                    done
+                })
+            }).collect();
+
+            let reads: Vec<_> = (0..2).map(|_| {
+                let (url, jwt) = (format!("{}/authed/todos", base), jwt.clone());
+                thread::spawn(move || {
+                    let mut latencies = vec![];
+                    while Instant::now() < deadline {
+                        let start = Instant::now();
+                        assert!(ureq::get(&url).header("Authorization", &jwt).call().is_ok());
+                        latencies.push(start.elapsed());
+                    }
+                    latencies
+                })
+            }).collect();
+
+            let signins: u32 = signins.into_iter().map(|t| t.join().unwrap()).sum();
+            let latencies: Vec<Duration> = reads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+
+            (signins, latencies)
+        }).await.unwrap();
+
+        handle.stop(true).await;
+
+        latencies.sort();
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+
+        println!(
+            "{:.1} signins/s, {:.1} reads/s, read latency p50 {:?} p99 {:?}, one hash {:?}",
+            signins as f64 / duration.as_secs_f64(),
+            latencies.len() as f64 / duration.as_secs_f64(),
+            percentile(50),
+            percentile(99),
+            hash_time,
+        );
+
+        // hashes kept running, or the reads had nothing to compete with
+        assert!(signins as f64 >= duration.as_secs_f64(), "only {} signins", signins);
+        assert!(percentile(99) < hash_time, "p99 read latency {:?} is not below one hash {:?}", percentile(99), hash_time);
+    }
+}
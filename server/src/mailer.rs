@@ -0,0 +1,247 @@
+//! Outgoing email. Handlers build an [`Email`] and hand it to the [`Mailer`] in `GlobalState`,
+//! which either drops it into a local outbox directory or relays it to an SMTP server.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Utc;
+
+use crate::{config::{MailConfig, MailTransport}, utils::generate_secret_token};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Email{
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Email {
+
+    /// The message as an RFC 5322 plain text mail with CRLF line endings.
+    pub fn to_message(&self, from:&str) -> String{
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            from, self.to, self.subject, Utc::now().to_rfc2822(),
+        );
+
+        for line in self.body.lines() {
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+
+        message
+    }
+}
+
+pub trait Mailer: Send + Sync {
+    /// Blocks until the mail is handed off, call it through `web::block`.
+    fn send(&self, email:&Email) -> Result<(), String>;
+}
+
+pub fn from_config(config:&MailConfig) -> Arc<dyn Mailer>{
+    match &config.transport {
+        MailTransport::Outbox(dir) => Arc::new(OutboxMailer{dir: dir.clone(), from: config.from.clone()}),
+        MailTransport::Smtp{host, port} => Arc::new(SmtpMailer{host: host.clone(), port: *port, from: config.from.clone()}),
+    }
+}
+
+/// Writes every mail to its own `.eml` file, for development and tests.
+pub struct OutboxMailer{
+    pub dir: PathBuf,
+    pub from: String,
+}
+
+impl Mailer for OutboxMailer {
+    fn send(&self, email:&Email) -> Result<(), String>{
+        std::fs::create_dir_all(&self.dir).map_err(|e| format!("Could not create outbox {}: {}", self.dir.display(), e))?;
+
+        // sortable by time, and unique when several mails go out within a second
+        let name = format!("{}-{}.eml", Utc::now().format("%Y%m%dT%H%M%S%.3f"), &generate_secret_token()[..8]);
+        let path = self.dir.join(name);
+
+        std::fs::write(&path, email.to_message(&self.from)).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
+
+/// Relays mail to an SMTP server without authentication or TLS, meant for a relay on the
+/// same host or network such as a local postfix.
+pub struct SmtpMailer{
+    pub host: String,
+    pub port: u16,
+    pub from: String,
+}
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Reads a possibly multi-line reply such as `250-first\r\n250 last\r\n` and checks its code
+fn expect_reply(reader:&mut impl BufRead, expected:u16) -> Result<(), String>{
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line).map_err(|e| format!("SMTP read failed: {}", e))? == 0 {
+            return Err(String::from("SMTP server closed the connection"));
+        }
+
+        let code = line.get(..3).and_then(|c| c.parse::<u16>().ok());
+
+        if code != Some(expected) {
+            return Err(format!("SMTP server replied '{}', expected {}", line.trim_end(), expected));
+        }
+
+        // a space after the code marks the last line
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+fn command(stream:&mut TcpStream, reader:&mut impl BufRead, line:&str, expected:u16) -> Result<(), String>{
+    stream.write_all(format!("{}\r\n", line).as_bytes()).map_err(|e| format!("SMTP write failed: {}", e))?;
+    expect_reply(reader, expected)
+}
+
+// Only the address part, `Name <a@b.c>` gives `a@b.c`
+fn address(mailbox:&str) -> &str{
+    match (mailbox.find('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox.trim(),
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email:&Email) -> Result<(), String>{
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+            .map_err(|e| format!("Could not connect to SMTP server {}:{}: {}", self.host, self.port, e))?;
+
+        stream.set_read_timeout(Some(SMTP_TIMEOUT)).map_err(|e| e.to_string())?;
+        stream.set_write_timeout(Some(SMTP_TIMEOUT)).map_err(|e| e.to_string())?;
+
+        let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+
+        expect_reply(&mut reader, 220)?;
+        command(&mut stream, &mut reader, "EHLO rust-int", 250)?;
+        command(&mut stream, &mut reader, &format!("MAIL FROM:<{}>", address(&self.from)), 250)?;
+        command(&mut stream, &mut reader, &format!("RCPT TO:<{}>", address(&email.to)), 250)?;
+        command(&mut stream, &mut reader, "DATA", 354)?;
+
+        // a line starting with a dot gets another one so it can not end the data early
+        let mut data = String::new();
+
+        for line in email.to_message(&self.from).lines() {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+
+        stream.write_all(data.as_bytes()).map_err(|e| format!("SMTP write failed: {}", e))?;
+        command(&mut stream, &mut reader, ".", 250)?;
+
+        // the mail is accepted at this point, a failing QUIT does not matter
+        let _ = command(&mut stream, &mut reader, "QUIT", 221);
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use std::{io::{BufRead, BufReader, Write}, net::TcpListener, thread};
+
+    use super::{Email, Mailer, SmtpMailer};
+
+    // Plays the server side of one SMTP conversation and returns what the client sent
+    fn smtp_stand_in(listener:TcpListener, reject_rcpt:bool) -> thread::JoinHandle<Vec<String>>{
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut received = vec![];
+            let mut in_data = false;
+
+            stream.write_all(b"220 stand-in ESMTP\r\n").unwrap();
+
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+
+                let line = line.trim_end_matches("\r\n").to_string();
+                received.push(line.clone());
+
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-stand-in\r\n250-SIZE 1000000\r\n250 8BITMIME\r\n"
+                } else if line.starts_with("RCPT") && reject_rcpt {
+                    b"550 no such user\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    stream.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+
+                stream.write_all(reply).unwrap();
+            }
+
+            received
+        })
+    }
+
+    #[test]
+    fn should_send_through_smtp(){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = smtp_stand_in(listener, false);
+
+        let mailer = SmtpMailer{host: "127.0.0.1".to_string(), port, from: "Todos <no-reply@example.com>".to_string()};
+
+        let email = Email{
+            to: "vk@gmail.com".to_string(),
+            subject: "Hello".to_string(),
+            body: "first line\n.starts with a dot\nlast line".to_string(),
+        };
+
+        mailer.send(&email).unwrap();
+
+        let received = server.join().unwrap();
+
+        assert_eq!(received[0], "EHLO rust-int");
+        assert_eq!(received[1], "MAIL FROM:<no-reply@example.com>");
+        assert_eq!(received[2], "RCPT TO:<vk@gmail.com>");
+        assert_eq!(received[3], "DATA");
+        assert!(received.contains(&"Subject: Hello".to_string()));
+        assert!(received.contains(&"..starts with a dot".to_string()));
+        assert_eq!(received[received.len() - 2], ".");
+        assert_eq!(received[received.len() - 1], "QUIT");
+    }
+
+    #[test]
+    fn should_report_smtp_rejections(){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let _server = smtp_stand_in(listener, true);
+
+        let mailer = SmtpMailer{host: "127.0.0.1".to_string(), port, from: "no-reply@example.com".to_string()};
+
+        let email = Email{to: "nobody@example.com".to_string(), subject: "Hi".to_string(), body: "Hi".to_string()};
+        let err = mailer.send(&email).unwrap_err();
+
+        assert!(err.contains("550"));
+    }
+}
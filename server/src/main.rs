@@ -1,13 +1,26 @@
 use std::sync::{Arc, Mutex};
 
 use actix_web::{get,HttpServer, Responder};
+use tokio::sync::Semaphore;
 
-use store::{todo::Todo, user::User};
+use store::{access_token::AccessToken, feed::FeedToken, lockout::{FailedAttempts, LockoutEvent}, mfa::{MfaChallenge, TotpEnrollment}, oauth::{AuthorizationCode, Consent, OAuthClient}, oidc::{OidcIdentity, OidcLogin}, refresh::RefreshToken, revocation::{RevokedToken, TokenCutoff}, reset::ResetToken, session::Session, todo::{Todo, TodoIds}, user::User, verification::VerificationToken};
+
+use crate::{config::AppConfig, mailer::Mailer};
 
 pub mod handlers;
 pub mod utils;
 pub mod middleware;
 pub mod errors;
+pub mod config;
+pub mod admin;
+pub mod patch;
+pub mod filter;
+pub mod mailer;
+pub mod policy;
+pub mod totp;
+pub mod scopes;
+pub mod oidc;
+pub mod hashing;
 
 #[get("/")]
 async fn hello_world() -> impl Responder {
@@ -16,12 +29,35 @@ async fn hello_world() -> impl Responder {
 
 pub struct CombinedState{
     pub users: Vec<User>,
-    pub todos: Vec<Todo>
+    pub todos: Vec<Todo>,
+    pub todo_ids: TodoIds,
+    pub feeds: Vec<FeedToken>,
+    pub refresh_tokens: Vec<RefreshToken>,
+    pub revoked_tokens: Vec<RevokedToken>,
+    pub token_cutoffs: Vec<TokenCutoff>,
+    pub sessions: Vec<Session>,
+    pub reset_tokens: Vec<ResetToken>,
+    pub verification_tokens: Vec<VerificationToken>,
+    pub failed_attempts: Vec<FailedAttempts>,
+    pub lockout_events: Vec<LockoutEvent>,
+    pub totp_enrollments: Vec<TotpEnrollment>,
+    pub mfa_challenges: Vec<MfaChallenge>,
+    pub access_tokens: Vec<AccessToken>,
+    pub oauth_clients: Vec<OAuthClient>,
+    pub authorization_codes: Vec<AuthorizationCode>,
+    pub oauth_consents: Vec<Consent>,
+    pub oidc_logins: Vec<OidcLogin>,
+    pub oidc_identities: Vec<OidcIdentity>,
 }
 
 #[derive(Clone)]
 pub struct GlobalState{
     pub overall_state : Arc<Mutex<CombinedState>>,
+    pub config: Arc<AppConfig>,
+    pub mailer: Arc<dyn Mailer>,
+    /// one permit per password hash allowed to run, see `hashing`
+    pub hash_permits: Arc<Semaphore>,
+    pub oidc_discovery: Arc<oidc::DiscoveryCache>,
 }
 
 const PORT :u16 = 8080;
@@ -31,36 +67,117 @@ macro_rules! init_app {
     ($overall_state:expr) => {
         actix_web::App::new()
         .app_data(actix_web::web::Data::new($overall_state.clone()))
-        .service(crate::hello_world)
+        .service($crate::hello_world)
+        .service($crate::handlers::feed::get_feed)
+        .service($crate::handlers::discovery::jwks)
+        .service($crate::handlers::discovery::openid_configuration)
+        .service($crate::handlers::oauth::token_endpoint)
+        .configure($crate::handlers::caldav::config)
         .service(
             actix_web::web::scope("/user")
-            .service(crate::handlers::user::signin)
-            .service(crate::handlers::user::signup)
+            .service($crate::handlers::user::signin)
+            .service($crate::handlers::user::signup)
+            .service($crate::handlers::user::refresh)
+            .service($crate::handlers::password::forgot_password)
+            .service($crate::handlers::password::reset_password)
+            .service($crate::handlers::verification::verify_email)
+            .service($crate::handlers::mfa::verify)
+            .service($crate::handlers::oidc::oidc_login)
+            .service($crate::handlers::oidc::oidc_callback)
         )
         .service(
             actix_web::web::scope("/authed")
-            .wrap(actix_web::middleware::from_fn(crate::middleware::middleware))
-            .service(crate::handlers::todo::create_todo)
-            .service(crate::handlers::todo::update_todo)
-            .service(crate::handlers::todo::get_todos)
+            .wrap(actix_web::middleware::from_fn($crate::middleware::middleware))
+            .service($crate::handlers::todo::create_todo)
+            .service($crate::handlers::todo::update_todo)
+            .service($crate::handlers::todo::patch_todo)
+            .service($crate::handlers::todo::get_todos)
+            .service($crate::handlers::todo::bulk_todos)
+            .service($crate::handlers::batch::batch)
+            .service($crate::handlers::transfer::export_todos)
+            .service($crate::handlers::transfer::import_todos)
+            .service($crate::handlers::feed::rotate_feed)
+            .service($crate::handlers::feed::revoke_feed)
+            .service($crate::handlers::user::logout)
+            .service($crate::handlers::user::logout_all)
+            .service($crate::handlers::session::get_sessions)
+            .service($crate::handlers::session::revoke_session)
+            .service($crate::handlers::verification::resend_verification)
+            .service($crate::handlers::password::change_password)
+            .service($crate::handlers::mfa::enroll)
+            .service($crate::handlers::mfa::confirm)
+            .service($crate::handlers::mfa::disable)
+            .service($crate::handlers::access_token::get_tokens)
+            .service($crate::handlers::access_token::create_token)
+            .service($crate::handlers::access_token::revoke_token)
+            .service($crate::handlers::oauth::register_client)
+            .service($crate::handlers::oauth::get_clients)
+            .service($crate::handlers::oauth::remove_client)
+            .service($crate::handlers::oauth::authorize_prompt)
+            .service($crate::handlers::oauth::authorize)
+            .service($crate::handlers::oauth::get_consents)
+            .service($crate::handlers::oauth::revoke_consent)
+        )
+        .service(
+            actix_web::web::scope("/admin")
+            // the last wrap runs first, the role check needs the claims the auth middleware adds
+            .wrap(actix_web::middleware::from_fn(|req, next| $crate::middleware::require_role(req, next, $crate::middleware::ADMIN_ROLE)))
+            .wrap(actix_web::middleware::from_fn($crate::middleware::middleware))
+            .service($crate::handlers::admin::get_users)
+            .service($crate::handlers::admin::disable_user)
+            .service($crate::handlers::admin::enable_user)
+            .service($crate::handlers::admin::delete_user)
+            .service($crate::handlers::admin::grant_role)
+            .service($crate::handlers::admin::revoke_role)
         )
 
     };
 }
 
 pub fn prepare_global_state() -> GlobalState{
-    let combined_state = CombinedState{todos:vec![], users:vec![]};
-    GlobalState{overall_state: Arc::new(Mutex::new(combined_state))}
+    prepare_global_state_with(AppConfig::dev())
+}
+
+pub fn prepare_global_state_with(config: AppConfig) -> GlobalState{
+    let combined_state = CombinedState{todos:vec![], todo_ids:TodoIds::default(), users:vec![], feeds:vec![], refresh_tokens:vec![], revoked_tokens:vec![], token_cutoffs:vec![], sessions:vec![], reset_tokens:vec![], verification_tokens:vec![], failed_attempts:vec![], lockout_events:vec![], totp_enrollments:vec![], mfa_challenges:vec![], access_tokens:vec![], oauth_clients:vec![], authorization_codes:vec![], oauth_consents:vec![], oidc_logins:vec![], oidc_identities:vec![]};
+    let mailer = mailer::from_config(&config.mail);
+    let hash_permits = Arc::new(Semaphore::new(config.hash_concurrency));
+    GlobalState{overall_state: Arc::new(Mutex::new(combined_state)), config: Arc::new(config), mailer, hash_permits, oidc_discovery: Arc::new(oidc::DiscoveryCache::default())}
 }
 
 #[actix_web::main]
 async fn main() -> Result<(), std::io::Error> {
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if let Some(res) = admin::run(&args) {
+        match res {
+            Ok(message) => println!("{}", message),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            },
+        }
+        return Ok(());
+    }
+
     let address = format!("127.0.0.1:{}", PORT);
 
     println!("Running on the port : {}", PORT);
 
-    let state = prepare_global_state();
+    let config = match AppConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Refusing to start: {}", e);
+            std::process::exit(1);
+        },
+    };
+
+    if config.dev_mode {
+        println!("Running in dev mode, do not use this configuration in production");
+    }
+
+    let state = prepare_global_state_with(config);
 
     HttpServer::new(move||{
         init_app!(state)
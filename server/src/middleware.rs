@@ -1,6 +1,12 @@
-use actix_web::{body::MessageBody, dev::{ServiceRequest, ServiceResponse}, middleware::Next, Error, HttpMessage};
+use actix_web::{body::MessageBody, dev::{ServiceRequest, ServiceResponse}, middleware::Next, web::Data, Error, HttpMessage};
+use chrono::Utc;
+use store::{access_token::AccessToken, revocation::{RevokedToken, TokenCutoff}, session::Session, user::User};
 
-use crate::{errors::AppError, utils::decode_token};
+use crate::{errors::AppError, handlers::{access_token::TOKEN_PREFIX, oauth::OAUTH_TOKEN_PREFIX}, scopes::Grant, utils::{decode_token, hash_token}, GlobalState};
+
+pub const ADMIN_ROLE: &str = "admin";
+/// Every role an admin can grant.
+pub const ROLES: [&str; 1] = [ADMIN_ROLE];
 
 pub async fn middleware(req:ServiceRequest, next:Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error>{
 
@@ -18,19 +24,145 @@ pub async fn middleware(req:ServiceRequest, next:Next<impl MessageBody>) -> Resu
         return Err(AppError::InternalError.into());
     }
 
+    // OAuth apps send `Bearer <token>`, everyone else the bare token
     let token = token_res.unwrap();
+    let token = token.strip_prefix("Bearer ").unwrap_or(token);
+
+
+    let state = req.app_data::<Data<GlobalState>>();
+
+    if state.is_none(){
+        return Err(AppError::InternalError.into());
+    }
+
+    let state = state.unwrap();
+
+    let verification = &state.config.verification;
+    let needs_verified = verification.required && !verification.allows(req.path());
+
+    if token.starts_with(TOKEN_PREFIX) || token.starts_with(OAUTH_TOKEN_PREFIX) {
+        let token_hash = hash_token(token);
+
+        let (found, unverified, disabled) = {
+            let state_result = state.overall_state.lock();
+
+            if state_result.is_err(){
+                return Err(AppError::InternalError.into());
+            }
+
+            let mut overall_state = state_result.unwrap();
+            let now = Utc::now().timestamp();
 
+            let found = AccessToken::find(&overall_state.access_tokens, &token_hash, now);
 
-    let decoded = decode_token(token);
+            if let Some(access_token) = &found {
+                AccessToken::touch(&mut overall_state.access_tokens, &access_token.id, now);
+            }
+
+            let user = found.as_ref().and_then(|t| User::get_user(&overall_state.users, &t.user_email));
+            let unverified = found.is_some() && needs_verified && !user.as_ref().is_some_and(|u| u.verified);
+            let disabled = user.is_some_and(|u| u.disabled);
+
+            (found, unverified, disabled)
+        };
+
+        if found.is_none(){
+            return Err(AppError::TokenRevoked.into());
+        }
+
+        let access_token = found.unwrap();
+
+        if disabled {
+            return Err(AppError::AccountDisabled.into());
+        }
+
+        if unverified {
+            return Err(AppError::EmailNotVerified.into());
+        }
+
+        // each route checks the scopes it needs, see `scopes::require`
+        req.extensions_mut().insert(Grant{email: access_token.user_email, scopes: Some(access_token.scopes)});
+
+        return next.call(req).await;
+    }
+
+    let decoded = decode_token(&state.config.jwt, token);
 
     if decoded.is_err(){
         return Err(AppError::InternalError.into());
     }
 
-    let email = decoded.unwrap();
+    // a signed in user holds every scope
+    let claims = decoded.unwrap();
+
+    let (revoked, unverified, disabled) = {
+        let state_result = state.overall_state.lock();
 
-    req.extensions_mut().insert(email);
+        if state_result.is_err(){
+            return Err(AppError::InternalError.into());
+        }
+
+        let mut overall_state = state_result.unwrap();
+        let now = Utc::now().timestamp();
+
+        if let Some(sid) = &claims.sid {
+            Session::touch(&mut overall_state.sessions, sid, now);
+        }
+
+        let revoked = RevokedToken::is_revoked(&overall_state.revoked_tokens, &claims.jti, now)
+            || TokenCutoff::is_cut_off(&overall_state.token_cutoffs, &claims.sub, claims.issued_at_ms())
+            || claims.sid.as_ref().is_some_and(|sid| !Session::is_active(&overall_state.sessions, sid));
+
+        let user = User::get_user(&overall_state.users, &claims.sub);
+        let unverified = needs_verified && !user.as_ref().is_some_and(|u| u.verified);
+        let disabled = user.is_some_and(|u| u.disabled);
+
+        (revoked, unverified, disabled)
+    };
+
+    // disabling also ends the sessions, this says why
+    if disabled {
+        return Err(AppError::AccountDisabled.into());
+    }
+
+    if revoked {
+        return Err(AppError::TokenRevoked.into());
+    }
+
+    if unverified {
+        return Err(AppError::EmailNotVerified.into());
+    }
+
+    // the route passes the email on to the handler, logout also needs the token's jti and expiry
+    req.extensions_mut().insert(Grant{email: claims.sub.clone(), scopes: None});
+    req.extensions_mut().insert(claims);
 
     next.call(req).await
 
-}
\ No newline at end of file
+}
+
+/// Lets through callers whose account holds `role` right now, whatever their JWT's roles claim
+/// says. Wrap a scope with it inside [`middleware`], which has to run first to put the caller in place:
+///
+/// `.wrap(from_fn(|req, next| require_role(req, next, ADMIN_ROLE))).wrap(from_fn(middleware))`
+pub async fn require_role(req:ServiceRequest, next:Next<impl MessageBody>, role: &'static str) -> Result<ServiceResponse<impl MessageBody>, Error>{
+
+    let email = req.extensions().get::<Grant>().map(|grant| grant.email.clone());
+    let state = req.app_data::<Data<GlobalState>>();
+
+    if email.is_none() || state.is_none(){
+        return Err(AppError::InternalError.into());
+    }
+
+    // a role revoked since the token was issued is gone at once
+    let has_role = match state.unwrap().overall_state.lock() {
+        Ok(state) => User::has_role(&state.users, &email.unwrap(), role),
+        Err(_) => return Err(AppError::InternalError.into()),
+    };
+
+    if !has_role {
+        return Err(AppError::MissingRole(role).into());
+    }
+
+    next.call(req).await
+}
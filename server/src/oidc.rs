@@ -0,0 +1,189 @@
+//! The relying party side of OpenID Connect: discovery, the authorization redirect, the code
+//! exchange and ID token validation against the provider's JWKS. Everything here blocks on HTTP,
+//! handlers call it through `web::block`.
+
+use std::{sync::Mutex, time::{Duration, Instant}};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{config::OidcProvider, utils::percent_encode};
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// how long a discovery document is used before it is fetched again
+const DISCOVERY_TTL: Duration = Duration::from_secs(60 * 60);
+
+// Signed with the provider's public keys, never a shared secret or none
+const ALLOWED_ALGORITHMS: [Algorithm; 7] = [
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::ES256, Algorithm::ES384, Algorithm::EdDSA,
+];
+
+/// The parts of the provider's discovery document a login needs.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ProviderMetadata{
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenEndpointResponse{
+    id_token: Option<String>,
+}
+
+/// The claims of a validated ID token.
+#[derive(Deserialize, Debug)]
+pub struct IdTokenClaims{
+    pub iss: String,
+    pub sub: String,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+    pub nonce: Option<String>,
+    /// the party the token was issued to, checked when there are several audiences
+    pub azp: Option<String>,
+    pub aud: serde_json::Value,
+}
+
+fn agent() -> ureq::Agent{
+    ureq::Agent::config_builder()
+        .timeout_global(Some(HTTP_TIMEOUT))
+        .build()
+        .into()
+}
+
+fn get_json<T: DeserializeOwned>(url: &str) -> Result<T, String>{
+    let body = agent().get(url).call()
+        .and_then(|mut res| res.body_mut().read_to_string())
+        .map_err(|e| format!("Could not fetch {}: {}", url, e))?;
+
+    serde_json::from_str(&body).map_err(|e| format!("Unexpected answer from {}: {}", url, e))
+}
+
+/// Reads `{issuer}/.well-known/openid-configuration` and checks it names the same issuer.
+pub fn discover(provider: &OidcProvider) -> Result<ProviderMetadata, String>{
+    let issuer = provider.issuer.trim_end_matches('/');
+    let metadata: ProviderMetadata = get_json(&format!("{}/.well-known/openid-configuration", issuer))?;
+
+    if metadata.issuer.trim_end_matches('/') != issuer {
+        return Err(format!("The discovery document of {} names issuer {}", issuer, metadata.issuer));
+    }
+
+    Ok(metadata)
+}
+
+/// Discovery documents by issuer, so a login does not fetch the provider's twice.
+#[derive(Default)]
+pub struct DiscoveryCache{
+    entries: Mutex<Vec<(String, ProviderMetadata, Instant)>>,
+}
+
+impl DiscoveryCache {
+
+    /// The provider's metadata, discovered again once it is older than `DISCOVERY_TTL`.
+    pub fn get(&self, provider: &OidcProvider) -> Result<ProviderMetadata, String>{
+        let cached = self.entries.lock()
+            .map_err(|_| String::from("The discovery cache is poisoned"))?
+            .iter()
+            .find(|(issuer, _, fetched_at)| *issuer == provider.issuer && fetched_at.elapsed() < DISCOVERY_TTL)
+            .map(|(_, metadata, _)| metadata.clone());
+
+        if let Some(metadata) = cached {
+            return Ok(metadata);
+        }
+
+        // not under the lock, the fetch may take up to HTTP_TIMEOUT
+        let metadata = discover(provider)?;
+
+        let mut entries = self.entries.lock().map_err(|_| String::from("The discovery cache is poisoned"))?;
+        entries.retain(|(issuer, _, _)| *issuer != provider.issuer);
+        entries.push((provider.issuer.clone(), metadata.clone(), Instant::now()));
+
+        Ok(metadata)
+    }
+}
+
+/// Where to send the browser to sign in at the provider.
+pub fn authorization_url(provider: &OidcProvider, metadata: &ProviderMetadata, state: &str, nonce: &str, code_challenge: &str) -> String{
+    let params = [
+        ("response_type", "code"),
+        ("client_id", provider.client_id.as_str()),
+        ("redirect_uri", provider.redirect_uri.as_str()),
+        ("scope", &provider.scopes.join(" ")),
+        ("state", state),
+        ("nonce", nonce),
+        ("code_challenge", code_challenge),
+        ("code_challenge_method", "S256"),
+    ];
+
+    let query: Vec<String> = params.iter().map(|(k, v)| format!("{}={}", k, percent_encode(v))).collect();
+    let separator = if metadata.authorization_endpoint.contains('?') { '&' } else { '?' };
+
+    format!("{}{}{}", metadata.authorization_endpoint, separator, query.join("&"))
+}
+
+/// Trades the code for tokens and returns the ID token.
+pub fn exchange_code(provider: &OidcProvider, metadata: &ProviderMetadata, code: &str, code_verifier: &str) -> Result<String, String>{
+    // client_secret_basic, both parts form encoded first as RFC 6749 section 2.3.1 has it
+    let credentials = STANDARD.encode(format!("{}:{}", percent_encode(&provider.client_id), percent_encode(&provider.client_secret)));
+
+    let body = agent().post(&metadata.token_endpoint)
+        .header("Authorization", &format!("Basic {}", credentials))
+        .send_form([
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("code_verifier", code_verifier),
+        ])
+        .and_then(|mut res| res.body_mut().read_to_string())
+        .map_err(|e| format!("The token request to {} failed: {}", metadata.token_endpoint, e))?;
+
+    let tokens: TokenEndpointResponse = serde_json::from_str(&body).map_err(|e| format!("Unexpected token response: {}", e))?;
+
+    tokens.id_token.ok_or_else(|| String::from("The token response has no id_token"))
+}
+
+/// Checks the ID token's signature against the provider's JWKS, its issuer, audience, expiry and nonce.
+pub fn validate_id_token(provider: &OidcProvider, metadata: &ProviderMetadata, id_token: &str, nonce: &str) -> Result<IdTokenClaims, String>{
+    let header = decode_header(id_token).map_err(|e| format!("Malformed ID token: {}", e))?;
+
+    if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+        return Err(format!("ID tokens signed with {:?} are not accepted", header.alg));
+    }
+
+    let jwks: JwkSet = get_json(&metadata.jwks_uri)?;
+
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    };
+
+    if jwk.is_none(){
+        return Err(String::from("The ID token is signed with a key the provider does not publish"));
+    }
+
+    let key = DecodingKey::from_jwk(jwk.unwrap()).map_err(|e| format!("Unusable provider key: {}", e))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.set_audience(&[&provider.client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+        .map_err(|e| format!("Invalid ID token: {}", e))?
+        .claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(String::from("The ID token does not belong to this sign in"));
+    }
+
+    if claims.aud.is_array() && claims.azp.as_deref() != Some(provider.client_id.as_str()) {
+        return Err(String::from("The ID token was issued to another party"));
+    }
+
+    Ok(claims)
+}
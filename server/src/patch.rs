@@ -0,0 +1,185 @@
+use serde_json::{Map, Value};
+use store::todo::Todo;
+
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+// Only these members of a todo can be changed by a client, everything else is owned by the server
+const PATCHABLE_FIELDS: [&str; 2] = ["title", "done"];
+
+#[derive(Debug, Default, PartialEq)]
+pub struct TodoPatch {
+    pub title: Option<String>,
+    pub done: Option<bool>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    UnsupportedMediaType,
+    Invalid(String),
+    TestFailed(String),
+}
+
+/// Builds a `TodoPatch` from a request body, picking the patch format from the content type.
+pub fn parse_patch(content_type: &str, body: &[u8], current: &Todo) -> Result<TodoPatch, PatchError> {
+    let value: Value = match serde_json::from_slice(body) {
+        Ok(v) => v,
+        Err(e) => return Err(PatchError::Invalid(format!("Invalid JSON: {}", e))),
+    };
+
+    match content_type {
+        MERGE_PATCH => merge_patch(value),
+        JSON_PATCH => json_patch(value, current),
+        _ => Err(PatchError::UnsupportedMediaType),
+    }
+}
+
+/// RFC 7396: the body is a partial todo, `null` would mean "remove the member".
+fn merge_patch(value: Value) -> Result<TodoPatch, PatchError> {
+    let members = match value {
+        Value::Object(members) => members,
+        _ => return Err(PatchError::Invalid(String::from("Merge patch must be a JSON object"))),
+    };
+
+    let mut patch = TodoPatch::default();
+
+    for (field, value) in members {
+        check_patchable(&field)?;
+
+        if value.is_null() {
+            return Err(PatchError::Invalid(format!("Field '{}' cannot be removed", field)));
+        }
+
+        set_field(&mut patch, &field, value)?;
+    }
+
+    Ok(patch)
+}
+
+/// RFC 6902: the operations are applied in order to a copy of the todo, then the patchable
+/// fields are read back. If any operation fails nothing is returned, so nothing is stored.
+fn json_patch(value: Value, current: &Todo) -> Result<TodoPatch, PatchError> {
+    let operations = match value {
+        Value::Array(operations) => operations,
+        _ => return Err(PatchError::Invalid(String::from("JSON patch must be an array of operations"))),
+    };
+
+    let mut document = match serde_json::to_value(current) {
+        Ok(Value::Object(document)) => document,
+        _ => return Err(PatchError::Invalid(String::from("Todo could not be patched"))),
+    };
+
+    for (index, operation) in operations.iter().enumerate() {
+        apply_operation(&mut document, operation)
+            .map_err(|e| match e {
+                PatchError::Invalid(msg) => PatchError::Invalid(format!("Operation {}: {}", index, msg)),
+                PatchError::TestFailed(msg) => PatchError::TestFailed(format!("Operation {}: {}", index, msg)),
+                other => other,
+            })?;
+    }
+
+    let mut patch = TodoPatch::default();
+
+    for field in PATCHABLE_FIELDS {
+        let value = document.remove(field).unwrap_or(Value::Null);
+        set_field(&mut patch, field, value)?;
+    }
+
+    if patch.title.as_deref() == Some(current.title.as_str()) {
+        patch.title = None;
+    }
+    if patch.done == Some(current.done) {
+        patch.done = None;
+    }
+
+    Ok(patch)
+}
+
+fn apply_operation(document: &mut Map<String, Value>, operation: &Value) -> Result<(), PatchError> {
+    let op = operation.get("op").and_then(Value::as_str);
+    let path = operation.get("path").and_then(Value::as_str);
+
+    if op.is_none() || path.is_none() {
+        return Err(PatchError::Invalid(String::from("'op' and 'path' are required")));
+    }
+
+    let op = op.unwrap();
+    let path = path.unwrap();
+
+    match op {
+        "add" | "replace" => {
+            let field = patchable_pointer(path)?;
+            let value = operation.get("value");
+
+            if value.is_none() {
+                return Err(PatchError::Invalid(String::from("'value' is required")));
+            }
+
+            document.insert(field.to_string(), value.unwrap().clone());
+            Ok(())
+        },
+        "copy" => {
+            let field = patchable_pointer(path)?;
+            let from = operation.get("from").and_then(Value::as_str);
+
+            if from.is_none() {
+                return Err(PatchError::Invalid(String::from("'from' is required")));
+            }
+
+            let value = Value::Object(document.clone()).pointer(from.unwrap()).cloned();
+
+            if value.is_none() {
+                return Err(PatchError::Invalid(format!("'{}' does not exist", from.unwrap())));
+            }
+
+            document.insert(field.to_string(), value.unwrap());
+            Ok(())
+        },
+        "test" => {
+            let actual = Value::Object(document.clone()).pointer(path).cloned();
+
+            if actual.as_ref() != operation.get("value") {
+                return Err(PatchError::TestFailed(format!("Test failed for '{}'", path)));
+            }
+
+            Ok(())
+        },
+        "remove" | "move" => {
+            Err(PatchError::Invalid(format!("'{}' would remove a field, which is not allowed", op)))
+        },
+        _ => Err(PatchError::Invalid(format!("Unknown op '{}'", op))),
+    }
+}
+
+fn patchable_pointer(path: &str) -> Result<&str, PatchError> {
+    let field = path.strip_prefix('/');
+
+    if field.is_none() {
+        return Err(PatchError::Invalid(format!("Invalid path '{}'", path)));
+    }
+
+    let field = field.unwrap();
+    check_patchable(field)?;
+
+    Ok(field)
+}
+
+fn check_patchable(field: &str) -> Result<(), PatchError> {
+    if PATCHABLE_FIELDS.contains(&field) {
+        return Ok(());
+    }
+
+    Err(PatchError::Invalid(format!("Field '{}' is not patchable", field)))
+}
+
+fn set_field(patch: &mut TodoPatch, field: &str, value: Value) -> Result<(), PatchError> {
+    match (field, value) {
+        ("title", Value::String(title)) => patch.title = Some(title),
+        ("done", Value::Bool(done)) => patch.done = Some(done),
+        ("title", _) => return Err(PatchError::Invalid(String::from("'title' must be a string"))),
+        ("done", _) => return Err(PatchError::Invalid(String::from("'done' must be a boolean"))),
+        (field, _) => return Err(PatchError::Invalid(format!("Field '{}' is not patchable", field))),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//! What a password has to look like before it is hashed, checked at signup, password reset and
+//! password change.
+
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+/// One problem with one input field, e.g. `{"field": "password", "code": "too_short", ...}`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct FieldError{
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field:&str, code:&str, message:String) -> FieldError{
+        FieldError{field: field.to_string(), code: code.to_string(), message}
+    }
+}
+
+/// The hashes of known breached passwords, grouped by the first five hex digits of their SHA-1
+/// like the k-anonymity range queries of Pwned Passwords, so a remote range lookup can take its
+/// place without changing callers.
+#[derive(Default)]
+pub struct BreachedList{
+    ranges: HashMap<String, HashSet<String>>,
+}
+
+/// Shipped with the binary, `BREACHED_PASSWORDS_FILE` replaces it with a bigger list.
+pub const SHIPPED_BREACHED_LIST: &str = include_str!("../data/breached-passwords.txt");
+
+impl BreachedList {
+
+    /// Reads lines of `SHA1` or `SHA1:COUNT` in hex, `#` starts a comment.
+    pub fn parse(content:&str) -> Result<BreachedList, String>{
+        let mut ranges: HashMap<String, HashSet<String>> = HashMap::new();
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let hash = line.split(':').next().unwrap_or_default().to_ascii_uppercase();
+
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("Line {} of the breached password list is not a SHA-1 hash", index + 1));
+            }
+
+            let (prefix, suffix) = hash.split_at(5);
+            ranges.entry(prefix.to_string()).or_default().insert(suffix.to_string());
+        }
+
+        Ok(BreachedList{ranges})
+    }
+
+    /// The suffixes of every hash starting with `prefix`.
+    pub fn range(&self, prefix:&str) -> Option<&HashSet<String>>{
+        self.ranges.get(prefix)
+    }
+
+    pub fn contains(&self, password:&str) -> bool{
+        let hash: String = Sha1::digest(password.as_bytes()).iter().map(|b| format!("{:02X}", b)).collect();
+        let (prefix, suffix) = hash.split_at(5);
+
+        self.range(prefix).is_some_and(|suffixes| suffixes.contains(suffix))
+    }
+
+    pub fn len(&self) -> usize{
+        self.ranges.values().map(HashSet::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.ranges.is_empty()
+    }
+}
+
+impl std::fmt::Debug for BreachedList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BreachedList({} hashes)", self.len())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy{
+    pub min_length: usize,
+    /// Argon2 hashes whatever it is given, an upper bound keeps that cheap
+    pub max_length: usize,
+    /// how many of lowercase, uppercase, digits and symbols must appear
+    pub min_classes: usize,
+    pub breached: Arc<BreachedList>,
+}
+
+impl PasswordPolicy {
+
+    /// Everything wrong with `password` for the account with `email` and `name`, reported under `field`.
+    pub fn check(&self, field:&str, password:&str, email:&str, name:&str) -> Vec<FieldError>{
+        let mut errors = vec![];
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            errors.push(FieldError::new(field, "too_short", format!("Use at least {} characters", self.min_length)));
+        }
+
+        if length > self.max_length {
+            errors.push(FieldError::new(field, "too_long", format!("Use at most {} characters", self.max_length)));
+        }
+
+        let classes = [
+            password.chars().any(|c| c.is_lowercase()),
+            password.chars().any(|c| c.is_uppercase()),
+            password.chars().any(|c| c.is_ascii_digit()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ];
+
+        if classes.iter().filter(|c| **c).count() < self.min_classes {
+            errors.push(FieldError::new(field, "too_simple", format!("Mix at least {} of lowercase, uppercase, digits and symbols", self.min_classes)));
+        }
+
+        let local_part = email.split('@').next().unwrap_or_default();
+
+        if [email, local_part, name].iter().any(|v| !v.is_empty() && password.eq_ignore_ascii_case(v)) {
+            errors.push(FieldError::new(field, "matches_account", String::from("Do not use your email or name as the password")));
+        }
+
+        if self.breached.contains(password) {
+            errors.push(FieldError::new(field, "breached", String::from("This password appeared in a data breach, choose another one")));
+        }
+
+        errors
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use super::{BreachedList, PasswordPolicy, SHIPPED_BREACHED_LIST};
+
+    fn codes(policy:&PasswordPolicy, password:&str) -> Vec<String>{
+        policy.check("password", password, "vk.red@gmail.com", "VK Red").into_iter().map(|e| e.code).collect()
+    }
+
+    #[test]
+    fn should_check_password_policy(){
+        let policy = PasswordPolicy{
+            min_length: 8,
+            max_length: 64,
+            min_classes: 3,
+            breached: Arc::new(BreachedList::parse(SHIPPED_BREACHED_LIST).unwrap()),
+        };
+
+        assert!(codes(&policy, "Random1234").is_empty());
+        assert_eq!(codes(&policy, ""), vec!["too_short", "too_simple"]);
+        assert_eq!(codes(&policy, &"Aa1".repeat(30)), vec!["too_long"]);
+        assert_eq!(codes(&policy, "alllowercase"), vec!["too_simple"]);
+        assert_eq!(codes(&policy, "VK.RED"), vec!["too_short", "too_simple", "matches_account"]);
+        assert_eq!(codes(&policy, "Password123"), vec!["breached"]);
+    }
+
+    #[test]
+    fn should_look_up_breached_hashes_by_range(){
+        // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        let list = BreachedList::parse("# comment\n5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8:9545824\n").unwrap();
+
+        assert!(list.range("5BAA6").unwrap().contains("1E4C9B93F3F0682250B6CF8331B7EE68FD8"));
+        assert!(list.contains("password"));
+        assert!(!list.contains("Password"));
+
+        assert!(BreachedList::parse("not-a-hash\n").is_err());
+    }
+}
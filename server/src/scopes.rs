@@ -0,0 +1,104 @@
+//! What a caller of the `/authed` and `/admin` scopes may do. Signed in users hold every scope, personal access
+//! tokens and OAuth tokens only the ones they were given.
+//!
+//! Each route names the scope it needs where it is declared, with [`require`]:
+//!
+//! ```text
+//! #[get("/todos", wrap = "scopes::require(scopes::TODOS_READ)")]
+//! ```
+
+use actix_web::{body::MessageBody, dev::{Service, ServiceRequest, ServiceResponse, Transform}, middleware::{from_fn, Next}, Error, HttpMessage};
+
+use crate::errors::AppError;
+
+pub const TODOS_READ: &str = "todos:read";
+pub const TODOS_WRITE: &str = "todos:write";
+pub const FEED_WRITE: &str = "feed:write";
+/// Managing the account itself, sessions, password, two-factor and tokens. Never given to an
+/// access token, so a leaked one can not be turned into more access.
+pub const ACCOUNT: &str = "account";
+/// Managing other users, on top of the admin role.
+pub const ADMIN: &str = "admin";
+
+/// The scopes an access token can be created with.
+pub const GRANTABLE: [&str; 3] = [TODOS_READ, TODOS_WRITE, FEED_WRITE];
+
+/// Who the auth middleware let in and what their token allows, `None` for a signed in user.
+#[derive(Clone, Debug)]
+pub struct Grant{
+    pub email: String,
+    pub scopes: Option<Vec<String>>,
+}
+
+/// Lets the request through to the route if the caller's token holds `scope`. Only then is the
+/// caller's email put in place for the handler, so a route registered without it refuses everyone.
+pub fn require<S, B>(scope: &'static str) -> impl Transform<S, ServiceRequest, Response = ServiceResponse<impl MessageBody>, Error = Error, InitError = ()>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    from_fn(move |req, next| check(req, next, scope))
+}
+
+async fn check(req: ServiceRequest, next: Next<impl MessageBody>, scope: &'static str) -> Result<ServiceResponse<impl MessageBody>, Error>{
+    let grant = req.extensions().get::<Grant>().cloned();
+
+    // the auth middleware wraps the whole scope and has to have run
+    if grant.is_none(){
+        return Err(AppError::InternalError.into());
+    }
+
+    let grant = grant.unwrap();
+
+    if grant.scopes.as_ref().is_some_and(|scopes| !scopes.iter().any(|s| s == scope)) {
+        return Err(AppError::InsufficientScope.into());
+    }
+
+    req.extensions_mut().insert(grant.email);
+
+    next.call(req).await
+}
+
+
+#[cfg(test)]
+mod tests{
+    use actix_web::{get, middleware::from_fn, test::{self, TestRequest}, web, App, HttpMessage, HttpRequest, HttpResponse, Responder};
+
+    use crate::{middleware::middleware, prepare_global_state, scopes, utils::generate_jwt_token};
+
+    async fn caller(req: HttpRequest) -> impl Responder{
+        match req.extensions().get::<String>() {
+            Some(email) => HttpResponse::Ok().body(email.clone()),
+            None => HttpResponse::Unauthorized().finish(),
+        }
+    }
+
+    #[get("/scoped", wrap = "scopes::require(scopes::TODOS_READ)")]
+    async fn scoped(req: HttpRequest) -> impl Responder{
+        caller(req).await
+    }
+
+    #[get("/unscoped")]
+    async fn unscoped(req: HttpRequest) -> impl Responder{
+        caller(req).await
+    }
+
+    #[actix_web::test]
+    async fn should_only_pass_caller_to_scoped_routes(){
+        let state = prepare_global_state();
+        let token = generate_jwt_token(&state.config.jwt, "scopes@gmail.com".to_string(), None, vec![]).unwrap();
+
+        let app = App::new()
+            .app_data(web::Data::new(state.clone()))
+            .service(web::scope("/authed").wrap(from_fn(middleware)).service(scoped).service(unscoped));
+        let app = test::init_service(app).await;
+
+        let req = TestRequest::get().uri("/authed/scoped").insert_header(("Authorization", token.clone())).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status().as_u16(), 200);
+        assert_eq!(test::read_body(res).await, "scopes@gmail.com");
+
+        let req = TestRequest::get().uri("/authed/unscoped").insert_header(("Authorization", token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 401);
+    }
+}
@@ -1,28 +1,454 @@
+use std::io::Write;
+
 use argon2::{
     password_hash::{
-        rand_core::OsRng, PasswordHasher, SaltString
-    }, Argon2, PasswordHash, PasswordVerifier
+        rand_core::{OsRng, RngCore}, PasswordHasher, SaltString
+    }, Argon2, Params, PasswordHash, PasswordVerifier
 };use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
 use store::{Serialize, Deserialize};
-use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use jsonwebtoken::{encode, decode, decode_header, Algorithm, Header, Validation, EncodingKey, DecodingKey};
+use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts, RsaPublicKey};
+
+use crate::config::{HashConfig, JwtConfig};
+
+/// How a key signs. HMAC keys are shared secrets, the others sign with a private key and
+/// publish the public half in the JWKS.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum KeyAlg {
+    #[default]
+    HS256,
+    RS256,
+    EdDSA,
+}
+
+impl KeyAlg {
+    pub fn parse(value:&str) -> Result<KeyAlg, String>{
+        match value {
+            "HS256" => Ok(KeyAlg::HS256),
+            "RS256" => Ok(KeyAlg::RS256),
+            "EdDSA" => Ok(KeyAlg::EdDSA),
+            other => Err(format!("Unsupported JWT algorithm '{}', use HS256, RS256 or EdDSA", other)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str{
+        match self {
+            KeyAlg::HS256 => "HS256",
+            KeyAlg::RS256 => "RS256",
+            KeyAlg::EdDSA => "EdDSA",
+        }
+    }
+
+    fn algorithm(&self) -> Algorithm{
+        match self {
+            KeyAlg::HS256 => Algorithm::HS256,
+            KeyAlg::RS256 => Algorithm::RS256,
+            KeyAlg::EdDSA => Algorithm::EdDSA,
+        }
+    }
+}
+
+/// A key tokens are signed or verified with, `kid` goes into the JWT header.
+///
+/// HS256 keys carry their `secret`. RS256 and EdDSA keys name PEM files instead: a PKCS#8
+/// private key to sign and an SPKI public key to verify and publish. A retired key only needs
+/// the public one.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SigningKey {
+    pub kid: String,
+    #[serde(default)]
+    pub alg: KeyAlg,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub secret: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key_file: Option<String>,
+    /// unix seconds
+    pub created_at: i64,
+    /// Set once the key is retired, tokens it signed are accepted until then
+    #[serde(default)]
+    pub accept_until: Option<i64>,
+    // contents of the PEM files, read by `read_key_files`
+    #[serde(skip)]
+    private_pem: Option<Vec<u8>>,
+    #[serde(skip)]
+    public_pem: Option<Vec<u8>>,
+}
+
+impl SigningKey {
+    pub fn hmac(kid:&str, secret:&str, created_at:i64) -> SigningKey{
+        SigningKey{
+            kid: kid.to_string(),
+            alg: KeyAlg::HS256,
+            secret: secret.to_string(),
+            private_key_file: None,
+            public_key_file: None,
+            created_at,
+            accept_until: None,
+            private_pem: None,
+            public_pem: None,
+        }
+    }
+
+    /// An RS256 or EdDSA key backed by PEM files, which are read right away.
+    pub fn from_pem_files(kid:&str, alg:KeyAlg, private_key_file:Option<String>, public_key_file:Option<String>, created_at:i64) -> Result<SigningKey, String>{
+        let mut key = SigningKey{
+            kid: kid.to_string(),
+            alg,
+            secret: String::new(),
+            private_key_file,
+            public_key_file,
+            created_at,
+            accept_until: None,
+            private_pem: None,
+            public_pem: None,
+        };
+
+        key.read_key_files()?;
+
+        Ok(key)
+    }
+
+    fn read_key_files(&mut self) -> Result<(), String>{
+        if self.alg == KeyAlg::HS256 {
+            if self.secret.is_empty() {
+                return Err(format!("HS256 key {} has no secret", self.kid));
+            }
+            return Ok(());
+        }
+
+        let read = |path:&Option<String>| -> Result<Option<Vec<u8>>, String>{
+            match path {
+                Some(path) => std::fs::read(path).map(Some).map_err(|e| format!("Could not read key file {}: {}", path, e)),
+                None => Ok(None),
+            }
+        };
+
+        self.private_pem = read(&self.private_key_file)?;
+        self.public_pem = read(&self.public_key_file)?;
+
+        if self.public_pem.is_none() {
+            return Err(format!("{} key {} needs a public_key_file", self.alg.name(), self.kid));
+        }
+
+        // fail at startup rather than on the first signin
+        self.decoding_key()?;
+
+        if self.private_pem.is_some() {
+            self.check_pair()?;
+        }
+
+        Ok(())
+    }
+
+    // Each file can be a valid key on its own while the two do not belong together, which would
+    // only show when the first token fails to verify. A probe signed with one half has to verify
+    // with the other.
+    fn check_pair(&self) -> Result<(), String>{
+        let probe = format!("{}.{}", self.kid, self.alg.name());
+        let algorithm = self.alg.algorithm();
+
+        let signature = jsonwebtoken::crypto::sign(probe.as_bytes(), &self.encoding_key()?, algorithm)
+            .map_err(|e| format!("Could not sign with the private key for {}: {}", self.kid, e))?;
+
+        match jsonwebtoken::crypto::verify(&signature, probe.as_bytes(), &self.decoding_key()?, algorithm) {
+            Ok(true) => Ok(()),
+            _ => Err(format!("The private and public key files for {} are not a key pair", self.kid)),
+        }
+    }
+
+    fn encoding_key(&self) -> Result<EncodingKey, String>{
+        let pem = self.private_pem.as_deref().unwrap_or_default();
+
+        let key = match self.alg {
+            KeyAlg::HS256 => return Ok(EncodingKey::from_secret(self.secret.as_bytes())),
+            _ if self.private_pem.is_none() => return Err(format!("Key {} has no private key to sign with", self.kid)),
+            KeyAlg::RS256 => EncodingKey::from_rsa_pem(pem),
+            KeyAlg::EdDSA => EncodingKey::from_ed_pem(pem),
+        };
+
+        key.map_err(|e| format!("Invalid private key for {}: {}", self.kid, e))
+    }
+
+    fn decoding_key(&self) -> Result<DecodingKey, String>{
+        let pem = self.public_pem.as_deref().unwrap_or_default();
+
+        let key = match self.alg {
+            KeyAlg::HS256 => return Ok(DecodingKey::from_secret(self.secret.as_bytes())),
+            KeyAlg::RS256 => DecodingKey::from_rsa_pem(pem),
+            KeyAlg::EdDSA => DecodingKey::from_ed_pem(pem),
+        };
+
+        key.map_err(|e| format!("Invalid public key for {}: {}", self.kid, e))
+    }
+
+    /// The public key in JWK form, `None` for HMAC keys which must never be published.
+    pub fn jwk(&self) -> Result<Option<Jwk>, String>{
+        let pem = match (self.alg, &self.public_pem) {
+            (KeyAlg::HS256, _) | (_, None) => return Ok(None),
+            (_, Some(pem)) => String::from_utf8_lossy(pem).to_string(),
+        };
+
+        let mut jwk = Jwk{
+            kty: "",
+            kid: self.kid.clone(),
+            alg: self.alg.name(),
+            use_: "sig",
+            n: None,
+            e: None,
+            crv: None,
+            x: None,
+        };
+
+        match self.alg {
+            KeyAlg::RS256 => {
+                let key = RsaPublicKey::from_public_key_pem(&pem)
+                    .or_else(|_| RsaPublicKey::from_pkcs1_pem(&pem))
+                    .map_err(|e| format!("Invalid RSA public key for {}: {}", self.kid, e))?;
+
+                jwk.kty = "RSA";
+                jwk.n = Some(URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()));
+                jwk.e = Some(URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()));
+            },
+            KeyAlg::EdDSA => {
+                let der = pem.lines()
+                    .filter(|l| !l.starts_with("-----"))
+                    .collect::<String>();
+                let der = STANDARD.decode(der).map_err(|e| format!("Invalid Ed25519 public key for {}: {}", self.kid, e))?;
+
+                // SubjectPublicKeyInfo of an Ed25519 key is a fixed 12 byte prefix and the 32 byte key
+                if der.len() != 44 || der[..12] != ED25519_SPKI_PREFIX {
+                    return Err(format!("Invalid Ed25519 public key for {}", self.kid));
+                }
+
+                jwk.kty = "OKP";
+                jwk.crv = Some("Ed25519");
+                jwk.x = Some(URL_SAFE_NO_PAD.encode(&der[12..]));
+            },
+            KeyAlg::HS256 => unreachable!(),
+        }
+
+        Ok(Some(jwk))
+    }
+}
+
+const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+/// A public key as published at `/.well-known/jwks.json` (RFC 7517).
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct Jwk {
+    pub kty: &'static str,
+    pub kid: String,
+    pub alg: &'static str,
+    #[serde(rename = "use")]
+    pub use_: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+}
+
+/// All keys that may verify a token, exactly one of them signs new ones.
+/// Stored as JSON in the file named by `JWT_KEYRING_FILE`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct KeyRing {
+    pub active: String,
+    pub keys: Vec<SigningKey>,
+}
+
+impl KeyRing {
+    pub fn single(kid:&str, secret:&str) -> KeyRing{
+        KeyRing::with_key(SigningKey::hmac(kid, secret, 0))
+    }
+
+    pub fn with_key(key:SigningKey) -> KeyRing{
+        KeyRing{
+            active: key.kid.clone(),
+            keys: vec![key],
+        }
+    }
+
+    pub fn active_key(&self) -> Option<&SigningKey>{
+        self.keys.iter().find(|k| k.kid == self.active)
+    }
+
+    /// The active key, or a retired one whose deadline has not passed.
+    pub fn verification_key(&self, kid:&str, now:i64) -> Option<&SigningKey>{
+        self.keys.iter().find(|k| {
+            k.kid == kid && (k.kid == self.active || k.accept_until.is_some_and(|until| until > now))
+        })
+    }
+
+    /// Makes a fresh HMAC key active, see `rotate_to`.
+    pub fn rotate(&mut self, now:i64, grace:i64) -> String{
+        let secret = generate_secret_token();
+        let kid = format!("{}-{}", now, &hash_token(&secret)[..8]);
+
+        self.rotate_to(now, grace, SigningKey::hmac(&kid, &secret, now))
+    }
+
+    /// Makes `key` active. The old one keeps verifying for `grace` seconds, which should be
+    /// at least the token lifetime so no one is logged out. Keys past their deadline are dropped.
+    pub fn rotate_to(&mut self, now:i64, grace:i64, key:SigningKey) -> String{
+        for old in self.keys.iter_mut() {
+            if old.kid == self.active {
+                old.accept_until = Some(now + grace);
+            }
+        }
+
+        self.keys.retain(|k| k.accept_until.is_none_or(|until| until > now));
+
+        let kid = key.kid.clone();
+        self.keys.push(key);
+        self.active = kid.clone();
+
+        kid
+    }
+
+    /// Public keys of every asymmetric key that still verifies tokens.
+    pub fn jwks(&self, now:i64) -> Result<Vec<Jwk>, String>{
+        let mut keys = vec![];
+
+        for key in &self.keys {
+            if self.verification_key(&key.kid, now).is_none() {
+                continue;
+            }
+
+            if let Some(jwk) = key.jwk()? {
+                keys.push(jwk);
+            }
+        }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
+        Ok(keys)
+    }
+
+    /// Algorithms of the published keys that still verify tokens, for the discovery document.
+    pub fn public_algorithms(&self, now:i64) -> Vec<&'static str>{
+        let mut algs: Vec<&'static str> = self.keys.iter()
+            .filter(|k| k.alg != KeyAlg::HS256 && self.verification_key(&k.kid, now).is_some())
+            .map(|k| k.alg.name())
+            .collect();
+
+        algs.sort();
+        algs.dedup();
+        algs
+    }
+
+    pub fn load(path:&str) -> Result<KeyRing, String>{
+        let content = std::fs::read_to_string(path).map_err(|e| format!("Could not read key ring {}: {}", path, e))?;
+        let mut ring: KeyRing = serde_json::from_str(&content).map_err(|e| format!("Invalid key ring {}: {}", path, e))?;
+
+        for key in ring.keys.iter_mut() {
+            key.read_key_files()?;
+        }
+
+        match ring.active_key() {
+            None => Err(format!("Key ring {} has no key with the active kid '{}'", path, ring.active)),
+            Some(key) if key.alg != KeyAlg::HS256 && key.private_key_file.is_none() => {
+                Err(format!("Active key {} in {} needs a private_key_file", key.kid, path))
+            },
+            Some(_) => Ok(ring),
+        }
+    }
+
+    /// Writes the ring readable by the owner only. It goes to a temporary file first and replaces
+    /// `path` in one rename, so a crash never leaves half a ring behind.
+    pub fn save(&self, path:&str) -> Result<(), String>{
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let temp = format!("{}.tmp", path);
+
+        // the mode only applies to a new file, a leftover from a crash may have another one
+        let _ = std::fs::remove_file(&temp);
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        options.open(&temp)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| std::fs::rename(&temp, path))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&temp);
+                format!("Could not write key ring {}: {}", path, e)
+            })
+    }
 }
 
-pub fn generate_jwt_token(email: String) -> Result<String, String>{
-    let key = b"secret";
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub iss: String,
+    pub aud: String,
+    /// unique per token so a single one can be revoked, empty for tokens issued before it existed
+    #[serde(default)]
+    pub jti: String,
+    /// the session the token was issued for, tokens without one are not tied to a session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// the user's roles when the token was issued
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    /// `iat` in milliseconds, 0 in tokens issued before it existed
+    #[serde(default)]
+    pub iat_ms: i64,
+}
+
+impl Claims {
+
+    /// When the token was issued in unix milliseconds. Older tokens only have the second, they
+    /// count as issued at its end so a cutoff in that second still catches them.
+    pub fn issued_at_ms(&self) -> i64{
+        match self.iat_ms {
+            0 => self.iat as i64 * 1000 + 999,
+            iat_ms => iat_ms,
+        }
+    }
+}
 
-    let tomorrow = Utc::now() + Duration::days(1);
+pub fn generate_jwt_token(config:&JwtConfig, email: String, sid: Option<String>, roles: Vec<String>) -> Result<String, String>{
+    let now = Utc::now();
+    let expiry = now + Duration::seconds(config.ttl);
 
     let claims = Claims{
         sub: email,
-        exp: tomorrow.timestamp() as usize,
+        exp: expiry.timestamp() as usize,
+        iat: now.timestamp() as usize,
+        iss: config.issuer.clone(),
+        aud: config.audience.clone(),
+        jti: generate_secret_token()[..32].to_string(),
+        sid,
+        roles,
+        iat_ms: now.timestamp_millis(),
     };
 
-    match encode(&Header::default(), &claims, &EncodingKey::from_secret(key)) {
+    let key = config.keys.active_key();
+
+    if key.is_none(){
+        return Err(String::from("No active signing key"));
+    }
+
+    let key = key.unwrap();
+
+    let header = Header{
+        kid: Some(key.kid.clone()),
+        ..Header::new(key.alg.algorithm())
+    };
+
+    match encode(&header, &claims, &key.encoding_key()?) {
         Ok(t) => Ok(t),
         Err(_) => Err(String::from("Error while encoding the ok")),
     }
@@ -30,25 +456,36 @@ pub fn generate_jwt_token(email: String) -> Result<String, String>{
 }
 
 
-pub fn decode_token(token:&str) -> Result<String, String>{
-    // TODO: ADD THIS IN ENV
-    let key = b"secret";
+pub fn decode_token(config:&JwtConfig, token:&str) -> Result<Claims, String>{
+    // tokens from before key ids were introduced carry none, only the active key may verify those
+    let kid = decode_header(token).ok().and_then(|h| h.kid).unwrap_or_else(|| config.keys.active.clone());
+    let key = config.keys.verification_key(&kid, Utc::now().timestamp());
+
+    if key.is_none(){
+        return Err(String::from("Unknown or retired signing key"));
+    }
+
+    let key = key.unwrap();
 
-    let validation = Validation::default();
+    // the algorithm comes from our key, never from the token's header
+    let mut validation = Validation::new(key.alg.algorithm());
+    validation.set_issuer(&[&config.issuer]);
+    validation.set_audience(&[&config.audience]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
 
-    match decode::<Claims>(token, &DecodingKey::from_secret(key), &validation) {
-        Ok(c) => Ok(c.claims.sub),
+    match decode::<Claims>(token, &key.decoding_key()?, &validation) {
+        Ok(c) => Ok(c.claims),
         Err(_e) => Err(String::from("Errrr while decoding the token"))
     }
 }
 
 
-pub fn get_hashed_password(password:&str) -> Result<String, String>{
+pub fn get_hashed_password(config:&HashConfig, password:&str) -> Result<String, String>{
     
     let salt = SaltString::generate(&mut OsRng);
 
 
-    let argon2 = Argon2::default();
+    let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, config.params()?);
 
     let hashed_res = argon2.hash_password(password.as_bytes(), &salt);
 
@@ -59,6 +496,7 @@ pub fn get_hashed_password(password:&str) -> Result<String, String>{
     }
 }
 
+// verifies with the parameters stored in the hash, whatever they were
 pub fn verify_password(actual_hash:&str, password:&String) -> bool{
     let parsed_hash = PasswordHash::new(actual_hash);
     if parsed_hash.is_err(){
@@ -67,4 +505,129 @@ pub fn verify_password(actual_hash:&str, password:&String) -> bool{
 
     let res = Argon2::default().verify_password(password.as_bytes(), &parsed_hash.unwrap()).is_ok();
     res
-}
\ No newline at end of file
+}
+
+/// Whether `actual_hash` was made differently from how `config` hashes now, and should be
+/// replaced once the password is known.
+pub fn needs_rehash(config:&HashConfig, actual_hash:&str) -> bool{
+    let parsed_hash = PasswordHash::new(actual_hash);
+    if parsed_hash.is_err(){
+        return false;
+    }
+
+    let parsed_hash = parsed_hash.unwrap();
+
+    if parsed_hash.algorithm != argon2::Algorithm::Argon2id.ident() || parsed_hash.version != Some(argon2::Version::V0x13 as u32) {
+        return true;
+    }
+
+    let params = Params::try_from(&parsed_hash);
+
+    params.is_err() || params.is_ok_and(|p| p.m_cost() != config.memory_kib || p.t_cost() != config.iterations || p.p_cost() != config.parallelism)
+}
+
+fn to_hex(bytes:&[u8]) -> String{
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A random, URL safe secret with 256 bits of entropy, for tokens that are handed out once.
+pub fn generate_secret_token() -> String{
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// Secret tokens are stored as their SHA-256, they are random enough not to need a salt.
+pub fn hash_token(token:&str) -> String{
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+/// Percent-encodes everything but unreserved characters, for query strings and URIs.
+pub fn percent_encode(value:&str) -> String{
+    value.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+/// Undoes `%XX` escapes, `None` when one is malformed or the result is not UTF-8.
+pub fn percent_decode(value:&str) -> Option<String>{
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] != b'%' {
+            decoded.push(bytes[index]);
+            index += 1;
+            continue;
+        }
+
+        let hex = bytes.get(index + 1..index + 3).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+        decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+        index += 3;
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+
+#[cfg(test)]
+mod tests{
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    use crate::config::AppConfig;
+
+    use super::{decode_token, generate_jwt_token, KeyAlg, KeyRing, SigningKey};
+
+    #[test]
+    fn should_rotate_key_ring(){
+        let mut ring = KeyRing::single("first", "4f9c2a7e1b8d6f3a0c5e9b2d7a4f1c8e");
+
+        let second = ring.rotate(1_000, 100);
+        assert_eq!(ring.active, second);
+        assert_eq!(ring.keys.len(), 2);
+
+        // the retired key verifies until its deadline
+        assert!(ring.verification_key("first", 1_099).is_some());
+        assert!(ring.verification_key("first", 1_100).is_none());
+        assert!(ring.verification_key(&second, 5_000).is_some());
+
+        // and is dropped by the next rotation after it
+        let third = ring.rotate(2_000, 100);
+        assert_eq!(ring.keys.iter().map(|k| k.kid.clone()).collect::<Vec<_>>(), vec![second.clone(), third]);
+        assert!(ring.verification_key(&second, 2_050).is_some());
+    }
+
+    #[test]
+    fn should_sign_with_pem_keys(){
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/keys");
+        let mut config = AppConfig::dev().jwt;
+
+        let ed = SigningKey::from_pem_files("ed", KeyAlg::EdDSA, Some(format!("{}/ed25519_private.pem", dir)), Some(format!("{}/ed25519_public.pem", dir)), 0).unwrap();
+        config.keys = KeyRing::with_key(ed);
+
+        let token = generate_jwt_token(&config, "vk@gmail.com".to_string(), None, vec![]).unwrap();
+        assert_eq!(decode_token(&config, &token).unwrap().sub, "vk@gmail.com");
+
+        let jwk = config.keys.active_key().unwrap().jwk().unwrap().unwrap();
+        assert_eq!((jwk.kty, jwk.crv), ("OKP", Some("Ed25519")));
+        assert_eq!(jwk.x.unwrap().len(), 43);
+
+        // an HS256 token keyed with the public key must not pass for an EdDSA one
+        let public_pem = std::fs::read(format!("{}/ed25519_public.pem", dir)).unwrap();
+        let header = Header{kid: Some("ed".to_string()), ..Default::default()};
+        let forged = encode(&header, &serde_json::json!({"sub": "vk@gmail.com", "exp": 4_000_000_000u64, "iss": "rust-int", "aud": "rust-int"}), &EncodingKey::from_secret(&public_pem)).unwrap();
+        assert!(decode_token(&config, &forged).is_err());
+
+        // a key without its private half can verify but not sign
+        let verify_only = SigningKey::from_pem_files("ed", KeyAlg::EdDSA, None, Some(format!("{}/ed25519_public.pem", dir)), 0).unwrap();
+        config.keys = KeyRing::with_key(verify_only);
+        assert!(generate_jwt_token(&config, "vk@gmail.com".to_string(), None, vec![]).is_err());
+        assert_eq!(decode_token(&config, &token).unwrap().sub, "vk@gmail.com");
+
+        // two valid keys that are not one pair are turned away when loaded
+        let mismatched = SigningKey::from_pem_files("ed", KeyAlg::EdDSA, Some(format!("{}/ed25519_private.pem", dir)), Some(format!("{}/ed25519_other_public.pem", dir)), 0);
+        assert!(mismatched.unwrap_err().contains("not a key pair"));
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+/// A personal access token for scripts, stored as the SHA-256 of the token.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct AccessToken{
+    pub id: String,
+    pub user_email: String,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    /// unix seconds
+    pub created_at: i64,
+    /// none for a token that works until it is revoked
+    pub expires_at: Option<i64>,
+    pub last_used: Option<i64>,
+    /// the OAuth app the token was issued to, none for a personal access token
+    #[serde(default)]
+    pub client_id: Option<String>,
+}
+
+impl AccessToken {
+
+    pub fn add_token(tokens: &mut Vec<AccessToken>, token: AccessToken){
+        tokens.push(token);
+    }
+
+    pub fn get_user_tokens(tokens: &[AccessToken], email: &str) -> Vec<AccessToken>{
+        tokens.iter().filter(|t| t.user_email == email).cloned().collect()
+    }
+
+    /// The token with `token_hash` unless it expired.
+    pub fn find(tokens: &[AccessToken], token_hash: &str, now: i64) -> Option<AccessToken>{
+        tokens.iter()
+            .find(|t| t.token_hash == token_hash && t.expires_at.is_none_or(|e| e > now))
+            .cloned()
+    }
+
+    pub fn touch(tokens: &mut [AccessToken], id: &str, now: i64){
+        if let Some(token) = tokens.iter_mut().find(|t| t.id == id) {
+            token.last_used = Some(now);
+        }
+    }
+
+    /// Revokes what `client_id` was issued, for one user or with `email` none for all of them.
+    pub fn revoke_client(tokens: &mut Vec<AccessToken>, client_id: &str, email: Option<&str>){
+        tokens.retain(|t| !(t.client_id.as_deref() == Some(client_id) && email.is_none_or(|e| t.user_email == e)));
+    }
+
+    pub fn revoke_user(tokens: &mut Vec<AccessToken>, email: &str){
+        tokens.retain(|t| t.user_email != email);
+    }
+
+    pub fn revoke(tokens: &mut Vec<AccessToken>, id: &str, email: &str) -> Result<String, String>{
+        let before = tokens.len();
+        tokens.retain(|t| !(t.id == id && t.user_email == email));
+
+        if tokens.len() == before {
+            return Err(String::from("Token not found"));
+        }
+
+        Ok(String::from("Token revoked"))
+    }
+}
@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+/// A secret calendar feed URL. Only the hash of the token is kept, each user has at most one.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FeedToken{
+    pub token_hash: String,
+    pub user_email: String,
+    /// unix seconds
+    pub created_at: i64,
+}
+
+impl FeedToken {
+
+    /// Stores a new feed token for the user, replacing (and so revoking) any previous one.
+    pub fn rotate(feeds: &mut Vec<FeedToken>, feed: FeedToken){
+        feeds.retain(|f| f.user_email != feed.user_email);
+        feeds.push(feed);
+    }
+
+    pub fn revoke(feeds: &mut Vec<FeedToken>, email: &String) -> Result<String, String>{
+        let before = feeds.len();
+        feeds.retain(|f| f.user_email != *email);
+
+        if feeds.len() == before {
+            return Err(String::from("No feed to revoke"));
+        }
+
+        Ok(String::from("Feed revoked"))
+    }
+
+    pub fn get_feed(feeds: &[FeedToken], token_hash: &str) -> Option<FeedToken>{
+        feeds.iter().find(|f| f.token_hash == token_hash).cloned()
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+use super::{RowError, TodoRecord};
+
+pub const HEADER: &str = "id,title,done,project,priority,created_on,completed_on,contexts,extras,uid\n";
+
+// CSV cells cannot hold lists, so contexts and extras are space separated like in todo.txt:
+// `home phone` and `due:2025-01-31 effort:2`.
+#[derive(Serialize, Deserialize)]
+struct CsvRow{
+    #[serde(default)]
+    id: Option<u32>,
+    title: String,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    project: Option<String>,
+    #[serde(default)]
+    priority: Option<char>,
+    #[serde(default)]
+    created_on: Option<String>,
+    #[serde(default)]
+    completed_on: Option<String>,
+    #[serde(default)]
+    contexts: String,
+    #[serde(default)]
+    extras: String,
+    #[serde(default)]
+    uid: Option<String>,
+}
+
+impl CsvRow {
+    fn from_record(record: &TodoRecord) -> CsvRow{
+        CsvRow{
+            id: record.id,
+            title: record.title.clone(),
+            done: record.done,
+            project: record.project.clone(),
+            priority: record.priority,
+            created_on: record.created_on.clone(),
+            completed_on: record.completed_on.clone(),
+            contexts: record.contexts.join(" "),
+            extras: record.extras.iter().map(|(k, v)| format!("{}:{}", k, v)).collect::<Vec<_>>().join(" "),
+            uid: record.uid.clone(),
+        }
+    }
+
+    fn into_record(self) -> Result<TodoRecord, String>{
+        let mut extras = std::collections::BTreeMap::new();
+
+        for pair in self.extras.split_whitespace() {
+            match pair.split_once(':') {
+                Some((key, value)) if !key.is_empty() && !value.is_empty() => {
+                    extras.insert(key.to_string(), value.to_string());
+                },
+                _ => return Err(format!("extras must be key:value pairs, got '{}'", pair)),
+            }
+        }
+
+        Ok(TodoRecord{
+            id: self.id,
+            uid: self.uid.filter(|u| !u.is_empty()),
+            title: self.title,
+            done: self.done,
+            project: self.project.filter(|p| !p.is_empty()),
+            priority: self.priority,
+            created_on: self.created_on.filter(|d| !d.is_empty()),
+            completed_on: self.completed_on.filter(|d| !d.is_empty()),
+            contexts: self.contexts.split_whitespace().map(String::from).collect(),
+            extras,
+        })
+    }
+}
+
+/// Serializes a single record as one CSV line, so an export can be written out row by row.
+pub fn write_record(record: &TodoRecord) -> Result<String, String>{
+    let mut writer = ::csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+
+    if let Err(e) = writer.serialize(CsvRow::from_record(record)) {
+        return Err(e.to_string());
+    }
+
+    let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+}
+
+/// Parses CSV with a header row. Columns may come in any order, `id` and `project` are optional.
+pub fn parse(input: &[u8]) -> Result<(Vec<TodoRecord>, Vec<RowError>), String>{
+    let mut reader = ::csv::ReaderBuilder::new()
+        .trim(::csv::Trim::All)
+        .from_reader(input);
+
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => return Err(format!("Could not read the CSV header: {}", e)),
+    };
+
+    if !headers.iter().any(|h| h == "title") {
+        return Err(String::from("The CSV header must have a title column"));
+    }
+
+    let mut records = vec![];
+    let mut errors = vec![];
+
+    for (index, row) in reader.deserialize::<CsvRow>().enumerate() {
+        let record = row
+            .map_err(|e| match e.kind() {
+                ::csv::ErrorKind::Deserialize{err, ..} => err.to_string(),
+                _ => e.to_string(),
+            })
+            .and_then(CsvRow::into_record)
+            .and_then(|r| r.validate().map(|_| r));
+
+        match record {
+            Ok(record) => records.push(record),
+            Err(message) => errors.push(RowError{row: index + 1, message}),
+        }
+    }
+
+    Ok((records, errors))
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{parse, write_record, HEADER};
+    use crate::formats::TodoRecord;
+
+    #[test]
+    fn should_round_trip_csv(){
+        let record = TodoRecord{
+            id: Some(3),
+            uid: None,
+            title: String::from("Buy milk, eggs and \"good\" bread"),
+            done: true,
+            project: Some(String::from("errands")),
+            priority: Some('B'),
+            created_on: Some(String::from("2025-01-02")),
+            completed_on: None,
+            contexts: vec![String::from("store"), String::from("car")],
+            extras: [(String::from("due"), String::from("2025-01-05"))].into(),
+        };
+
+        let csv = format!("{}{}", HEADER, write_record(&record).unwrap());
+        let (records, errors) = parse(csv.as_bytes()).unwrap();
+
+        assert!(errors.is_empty());
+        assert_eq!(records, vec![record]);
+    }
+
+    #[test]
+    fn should_report_bad_rows(){
+        let csv = "title,done\nGo to Gym,false\n,true\nRead,maybe\n";
+        let (records, errors) = parse(csv.as_bytes()).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, None);
+        assert_eq!(errors.iter().map(|e| e.row).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(errors[0].message, "title cannot be empty");
+    }
+}
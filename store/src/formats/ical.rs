@@ -0,0 +1,434 @@
+//! iCalendar (RFC 5545) VTODO components.
+//!
+//! | todo           | VTODO                                        |
+//! |----------------|----------------------------------------------|
+//! | `id` / `uid`   | `UID`, `todo-<id>@rust-int` unless imported  |
+//! | `title`        | `SUMMARY`                                    |
+//! | `done`         | `STATUS:COMPLETED` / `STATUS:NEEDS-ACTION`   |
+//! | `completed_on` | `COMPLETED`                                  |
+//! | `created_on`   | `CREATED`                                    |
+//! | `priority`     | `PRIORITY`, `A`..`I` as 1..9                 |
+//! | `contexts`     | `CATEGORIES`                                 |
+//! | `project`      | `X-RUST-INT-PROJECT`                         |
+//! | `due` extra    | `DUE;VALUE=DATE`                             |
+
+use std::collections::BTreeMap;
+
+use super::{RowError, TodoRecord};
+
+pub const CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+
+pub const CALENDAR_START: &str = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//rust-int//todos//EN\r\n";
+pub const CALENDAR_END: &str = "END:VCALENDAR\r\n";
+
+const UID_PREFIX: &str = "todo-";
+const UID_SUFFIX: &str = "@rust-int";
+
+pub fn uid_for(record: &TodoRecord) -> String{
+    match (&record.uid, record.id) {
+        (Some(uid), _) => uid.clone(),
+        (None, Some(id)) => format!("{}{}{}", UID_PREFIX, id, UID_SUFFIX),
+        (None, None) => String::new(),
+    }
+}
+
+/// Copies from `existing` what a VTODO can not carry into `record` parsed from one, so writing a
+/// todo back from a calendar client keeps its other extras. A `due` date the VTODO could have
+/// carried is left out, its absence means the client cleared it.
+pub fn keep_unmapped(record: &mut TodoRecord, existing: &TodoRecord){
+    for (key, value) in &existing.extras {
+        if key == "due" && super::is_date(value) {
+            continue;
+        }
+
+        record.extras.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+}
+
+fn escape(text: &str) -> String{
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String{
+    let mut out = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+// Content lines longer than 75 octets continue on the next line after a single space.
+fn fold(line: &str) -> String{
+    let mut out = String::new();
+    let mut width = 0;
+
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+
+        out.push(c);
+        width += c.len_utf8();
+    }
+
+    out.push_str("\r\n");
+    out
+}
+
+fn to_ical_date(date: &str) -> String{
+    date.replace('-', "")
+}
+
+// Takes the date part of a DATE or DATE-TIME value, `20250103T101500Z` is `2025-01-03`.
+fn from_ical_date(value: &str) -> Option<String>{
+    let digits = value.get(0..8)?;
+
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(format!("{}-{}-{}", &digits[0..4], &digits[4..6], &digits[6..8]))
+}
+
+/// Writes one VTODO. `dtstamp` is the UTC time of the export, e.g. `20250101T120000Z`.
+pub fn write_vtodo(record: &TodoRecord, dtstamp: &str) -> String{
+    let mut lines = vec![
+        String::from("BEGIN:VTODO"),
+        format!("UID:{}", uid_for(record)),
+        format!("DTSTAMP:{}", dtstamp),
+        format!("SUMMARY:{}", escape(&record.title)),
+    ];
+
+    if record.done {
+        lines.push(String::from("STATUS:COMPLETED"));
+    } else {
+        lines.push(String::from("STATUS:NEEDS-ACTION"));
+    }
+
+    if let Some(completed_on) = &record.completed_on {
+        lines.push(format!("COMPLETED:{}T000000Z", to_ical_date(completed_on)));
+    }
+
+    if let Some(created_on) = &record.created_on {
+        lines.push(format!("CREATED:{}T000000Z", to_ical_date(created_on)));
+    }
+
+    if let Some(priority) = record.priority {
+        let level = (priority as u8 - b'A' + 1).min(9);
+        lines.push(format!("PRIORITY:{}", level));
+    }
+
+    if let Some(due) = record.extras.get("due").filter(|d| super::is_date(d)) {
+        lines.push(format!("DUE;VALUE=DATE:{}", to_ical_date(due)));
+    }
+
+    if !record.contexts.is_empty() {
+        let categories: Vec<String> = record.contexts.iter().map(|c| escape(c)).collect();
+        lines.push(format!("CATEGORIES:{}", categories.join(",")));
+    }
+
+    if let Some(project) = &record.project {
+        lines.push(format!("X-RUST-INT-PROJECT:{}", escape(project)));
+    }
+
+    lines.push(String::from("END:VTODO"));
+
+    lines.iter().map(|l| fold(l)).collect()
+}
+
+pub fn write_calendar(records: &[TodoRecord], dtstamp: &str) -> String{
+    let mut out = String::from(CALENDAR_START);
+
+    for record in records {
+        out.push_str(&write_vtodo(record, dtstamp));
+    }
+
+    out.push_str(CALENDAR_END);
+    out
+}
+
+struct Property{
+    name: String,
+    value: String,
+}
+
+fn unfold(input: &str) -> Vec<String>{
+    let mut lines: Vec<String> = vec![];
+
+    for line in input.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if let Some(rest) = line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')) {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(rest);
+                continue;
+            }
+        }
+
+        if !line.is_empty() {
+            lines.push(line.to_string());
+        }
+    }
+
+    lines
+}
+
+// NAME;PARAM=value;PARAM="quoted:value":VALUE, the first colon outside quotes ends the params.
+// Parameters are dropped, a TZID only shifts the time of day and only the date is kept.
+fn parse_property(line: &str) -> Option<Property>{
+    let mut quoted = false;
+    let mut split_at = None;
+
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => {
+                split_at = Some(index);
+                break;
+            },
+            _ => {},
+        }
+    }
+
+    let (head, value) = line.split_at(split_at?);
+    let name = head.split(';').next()?.to_ascii_uppercase();
+
+    Some(Property{name, value: value[1..].to_string()})
+}
+
+fn split_list(value: &str) -> Vec<String>{
+    let mut items = vec![];
+    let mut current = String::new();
+    let mut escaped = false;
+
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => items.push(std::mem::take(&mut current)),
+            '\\' if !escaped => {
+                escaped = true;
+                current.push(c);
+                continue;
+            },
+            c => current.push(c),
+        }
+        escaped = false;
+    }
+
+    items.push(current);
+
+    items.iter()
+        .map(|i| unescape(i.trim()))
+        .filter(|i| !i.is_empty())
+        .collect()
+}
+
+fn vtodo_to_record(properties: Vec<Property>) -> Result<TodoRecord, String>{
+    let mut record = TodoRecord{
+        id: None,
+        uid: None,
+        title: String::new(),
+        done: false,
+        project: None,
+        priority: None,
+        created_on: None,
+        completed_on: None,
+        contexts: vec![],
+        extras: BTreeMap::new(),
+    };
+
+    for property in properties {
+        let value = property.value.as_str();
+
+        match property.name.as_str() {
+            "UID" => {
+                let id = value.strip_prefix(UID_PREFIX)
+                    .and_then(|v| v.strip_suffix(UID_SUFFIX))
+                    .and_then(|v| v.parse::<u32>().ok());
+
+                match id {
+                    Some(id) => record.id = Some(id),
+                    None => record.uid = Some(value.to_string()),
+                }
+            },
+            "SUMMARY" => record.title = unescape(value).replace('\n', " "),
+            "STATUS" => record.done = record.done || value.eq_ignore_ascii_case("COMPLETED"),
+            "PERCENT-COMPLETE" => record.done = record.done || value.trim() == "100",
+            "COMPLETED" => {
+                record.done = true;
+                record.completed_on = from_ical_date(value);
+            },
+            "CREATED" => record.created_on = from_ical_date(value),
+            "PRIORITY" => {
+                // 0 means undefined
+                record.priority = match value.trim().parse::<u8>() {
+                    Ok(0) => None,
+                    Ok(level) if level <= 9 => Some((b'A' + level - 1) as char),
+                    _ => return Err(format!("PRIORITY must be 0 to 9, got '{}'", value)),
+                };
+            },
+            "DUE" => {
+                if let Some(due) = from_ical_date(value) {
+                    record.extras.insert(String::from("due"), due);
+                }
+            },
+            "CATEGORIES" => record.contexts.extend(split_list(value)),
+            "X-RUST-INT-PROJECT" => record.project = Some(unescape(value)),
+            _ => {},
+        }
+    }
+
+    record.validate()?;
+
+    Ok(record)
+}
+
+/// Reads every VTODO of a calendar, other components (VEVENT, VTIMEZONE, VALARM) are skipped.
+/// Errors carry the position of the VTODO in the file.
+pub fn parse(input: &[u8]) -> Result<(Vec<TodoRecord>, Vec<RowError>), String>{
+    let input = match std::str::from_utf8(input) {
+        Ok(input) => input,
+        Err(_) => return Err(String::from("iCalendar files must be UTF-8")),
+    };
+
+    let lines = unfold(input);
+
+    if !lines.first().is_some_and(|l| l.eq_ignore_ascii_case("BEGIN:VCALENDAR")) {
+        return Err(String::from("Expected BEGIN:VCALENDAR"));
+    }
+
+    let mut records = vec![];
+    let mut errors = vec![];
+    let mut components: Vec<String> = vec![];
+    let mut properties = vec![];
+    let mut count = 0;
+
+    for line in lines {
+        let property = parse_property(&line);
+
+        if property.is_none() {
+            continue;
+        }
+
+        let property = property.unwrap();
+
+        match property.name.as_str() {
+            "BEGIN" => {
+                let component = property.value.to_ascii_uppercase();
+
+                if component == "VTODO" {
+                    properties.clear();
+                }
+
+                components.push(component);
+            },
+            "END" => {
+                let component = components.pop();
+
+                if component.as_deref() == Some("VTODO") {
+                    count += 1;
+
+                    match vtodo_to_record(std::mem::take(&mut properties)) {
+                        Ok(record) => records.push(record),
+                        Err(message) => errors.push(RowError{row: count, message}),
+                    }
+                }
+            },
+            _ => {
+                // only the VTODO's own properties, not those of a VALARM inside it
+                if components.last().map(String::as_str) == Some("VTODO") {
+                    properties.push(property);
+                }
+            },
+        }
+    }
+
+    Ok((records, errors))
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{parse, write_calendar};
+    use crate::formats::TodoRecord;
+
+    fn parse_ok(input: &str) -> Vec<TodoRecord>{
+        let (records, errors) = parse(input.as_bytes()).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        records
+    }
+
+    #[test]
+    fn should_round_trip_vtodos(){
+        let records = parse_ok(include_str!("../../fixtures/ical/thunderbird.ics"));
+        let calendar = write_calendar(&records, "20250101T000000Z");
+
+        assert!(calendar.lines().all(|l| l.len() <= 76));
+        assert_eq!(parse_ok(&calendar), records);
+
+        let mut record = records[0].clone();
+        record.uid = None;
+        record.id = Some(7);
+
+        let calendar = write_calendar(&[record], "20250101T000000Z");
+        assert!(calendar.contains("UID:todo-7@rust-int\r\n"));
+        assert_eq!(parse_ok(&calendar)[0].id, Some(7));
+    }
+
+    #[test]
+    fn should_read_thunderbird_tasks(){
+        let records = parse_ok(include_str!("../../fixtures/ical/thunderbird.ics"));
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].uid.as_deref(), Some("0b6a7e0f-2bd4-4a4c-9a38-1d5b8e6f7c21"));
+        assert_eq!(records[0].title, "Renew passport, then book flights");
+        assert_eq!(records[0].priority, Some('A'));
+        assert_eq!(records[0].extras.get("due").map(String::as_str), Some("2025-03-14"));
+        assert_eq!(records[0].contexts, vec!["Personal", "Travel"]);
+        assert!(!records[0].done);
+
+        assert!(records[1].done);
+        assert_eq!(records[1].completed_on.as_deref(), Some("2025-02-02"));
+        assert_eq!(records[1].title, "A task with a rather long summary that Thunderbird folded over more than one line");
+    }
+
+    #[test]
+    fn should_read_apple_reminders(){
+        let records = parse_ok(include_str!("../../fixtures/ical/apple-reminders.ics"));
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].title, "Buy milk");
+        assert_eq!(records[0].priority, None);
+        assert_eq!(records[0].created_on.as_deref(), Some("2025-01-10"));
+        assert!(records[1].done);
+        assert_eq!(records[1].priority, Some('E'));
+    }
+
+    #[test]
+    fn should_read_nextcloud_tasks(){
+        let (records, errors) = parse(include_bytes!("../../fixtures/ical/nextcloud.ics")).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].title, "Write report");
+        assert!(records[0].done);
+        assert_eq!(records[1].title, "Sub task; with \"quotes\"");
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].row, 3);
+    }
+}
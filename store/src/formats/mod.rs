@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::todo::Todo;
+
+pub mod csv;
+pub mod ical;
+pub mod todotxt;
+
+/// The fields of a todo that travel in an export, without the owner. Importing a record
+/// always assigns it to the importing user.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TodoRecord{
+    #[serde(default)]
+    pub id: Option<u32>,
+    #[serde(default)]
+    pub uid: Option<String>,
+    pub title: String,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default)]
+    pub project: Option<String>,
+    #[serde(default)]
+    pub priority: Option<char>,
+    #[serde(default)]
+    pub created_on: Option<String>,
+    #[serde(default)]
+    pub completed_on: Option<String>,
+    #[serde(default)]
+    pub contexts: Vec<String>,
+    #[serde(default)]
+    pub extras: BTreeMap<String, String>,
+}
+
+/// A row of an import that could not be used, `row` counts data rows from 1.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct RowError{
+    pub row: usize,
+    pub message: String,
+}
+
+impl TodoRecord {
+    pub fn from_todo(todo: &Todo) -> TodoRecord{
+        TodoRecord{
+            id: Some(todo.id),
+            uid: todo.uid.clone(),
+            title: todo.title.clone(),
+            done: todo.done,
+            project: todo.project.clone(),
+            priority: todo.priority,
+            created_on: todo.created_on.clone(),
+            completed_on: todo.completed_on.clone(),
+            contexts: todo.contexts.clone(),
+            extras: todo.extras.clone(),
+        }
+    }
+
+    /// The id is left at 0 when the record has none, `Todo::import_todo` then allocates one.
+    pub fn into_todo(self, email: String) -> Todo{
+        Todo{
+            id: self.id.unwrap_or(0),
+            uid: self.uid.filter(|u| !u.is_empty()),
+            title: self.title,
+            done: self.done,
+            user_email: email,
+            project: self.project.filter(|p| !p.is_empty()),
+            priority: self.priority,
+            created_on: self.created_on,
+            completed_on: self.completed_on,
+            contexts: self.contexts,
+            extras: self.extras,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String>{
+        if self.title.trim().is_empty() {
+            return Err(String::from("title cannot be empty"));
+        }
+
+        if let Some(priority) = self.priority {
+            if !priority.is_ascii_uppercase() {
+                return Err(String::from("priority must be a letter from A to Z"));
+            }
+        }
+
+        for date in [&self.created_on, &self.completed_on].into_iter().flatten() {
+            if !is_date(date) {
+                return Err(format!("'{}' is not a YYYY-MM-DD date", date));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the `YYYY-MM-DD` shape and that month and day are in range, not the calendar itself.
+pub fn is_date(value: &str) -> bool{
+    let parts: Vec<&str> = value.split('-').collect();
+
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 {
+        return false;
+    }
+
+    if !parts.iter().all(|p| p.bytes().all(|b| b.is_ascii_digit())) {
+        return false;
+    }
+
+    let month: u32 = parts[1].parse().unwrap_or(0);
+    let day: u32 = parts[2].parse().unwrap_or(0);
+
+    (1..=12).contains(&month) && (1..=31).contains(&day)
+}
+
+pub fn to_json(records: &[TodoRecord]) -> Result<String, String>{
+    serde_json::to_string(records).map_err(|e| e.to_string())
+}
+
+/// Parses a JSON array of records, collecting every bad element instead of stopping at the first.
+pub fn from_json(input: &[u8]) -> Result<(Vec<TodoRecord>, Vec<RowError>), String>{
+    let rows: Vec<Value> = match serde_json::from_slice(input) {
+        Ok(rows) => rows,
+        Err(e) => return Err(format!("Expected a JSON array of todos: {}", e)),
+    };
+
+    let mut records = vec![];
+    let mut errors = vec![];
+
+    for (index, row) in rows.into_iter().enumerate() {
+        let record = serde_json::from_value::<TodoRecord>(row)
+            .map_err(|e| e.to_string())
+            .and_then(|r| r.validate().map(|_| r));
+
+        match record {
+            Ok(record) => records.push(record),
+            Err(message) => errors.push(RowError{row: index + 1, message}),
+        }
+    }
+
+    Ok((records, errors))
+}
@@ -0,0 +1,330 @@
+//! The todo.txt format, one task per line:
+//!
+//! ```text
+//! x 2025-01-03 2025-01-01 Call the plumber +house @phone due:2025-01-05
+//! (A) 2025-01-01 Pay rent +house @online
+//! ```
+//!
+//! A leading `x` marks the task done and is followed by the completion and then the creation
+//! date. Open tasks may start with a `(A)`-style priority and a creation date. In the text,
+//! the first `+project` becomes the todo's project, `@context`s and `key:value` pairs are
+//! collected, and everything else is the title, including any further `+project`s. Done tasks
+//! keep their priority as `pri:A`, and their creation date as `created:2025-01-01` when there is
+//! no completion date to write it after.
+//!
+//! A title word that would read as one of the above, say `@home`, `key:value` or a leading `x`
+//! or date, is written with a `\` in front, and reading drops a leading `\` from every word.
+//! Line breaks in a title are written as spaces.
+//!
+//! Lines are written back in that canonical order: dates, title, project, contexts, extras
+//! sorted by key. A line already in that order comes back unchanged.
+
+use std::collections::BTreeMap;
+
+use super::{is_date, RowError, TodoRecord};
+
+pub const CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+fn parse_priority(token: &str) -> Option<char>{
+    let mut chars = token.chars();
+
+    match (chars.next(), chars.next(), chars.next(), chars.next()) {
+        (Some('('), Some(p), Some(')'), None) if p.is_ascii_uppercase() => Some(p),
+        _ => None,
+    }
+}
+
+// `key:value` with no empty side, and not a URL such as `https://example.com`
+fn parse_extra(token: &str) -> Option<(&str, &str)>{
+    let (key, value) = token.split_once(':')?;
+
+    if key.is_empty() || value.is_empty() || value.starts_with("//") || value.contains(':') {
+        return None;
+    }
+
+    Some((key, value))
+}
+
+// Whether a title word has to be escaped to be read back as part of the title. `first` is the
+// title's first word, which could be taken for a done mark, priority or date.
+fn needs_escape(word: &str, first: bool) -> bool{
+    word.starts_with('\\')
+        || (word.len() > 1 && (word.starts_with('+') || word.starts_with('@')))
+        || parse_extra(word).is_some()
+        || (first && (word == "x" || parse_priority(word).is_some() || is_date(word)))
+}
+
+pub fn parse_line(line: &str) -> Result<TodoRecord, String>{
+    let mut tokens = line.split_whitespace().peekable();
+
+    let mut record = TodoRecord{
+        id: None,
+        uid: None,
+        title: String::new(),
+        done: false,
+        project: None,
+        priority: None,
+        created_on: None,
+        completed_on: None,
+        contexts: vec![],
+        extras: BTreeMap::new(),
+    };
+
+    if tokens.peek() == Some(&"x") {
+        tokens.next();
+        record.done = true;
+
+        if let Some(date) = tokens.next_if(|t| is_date(t)) {
+            record.completed_on = Some(date.to_string());
+
+            if let Some(date) = tokens.next_if(|t| is_date(t)) {
+                record.created_on = Some(date.to_string());
+            }
+        }
+    } else {
+        if let Some(priority) = tokens.peek().and_then(|t| parse_priority(t)) {
+            tokens.next();
+            record.priority = Some(priority);
+        }
+
+        if let Some(date) = tokens.next_if(|t| is_date(t)) {
+            record.created_on = Some(date.to_string());
+        }
+    }
+
+    let mut words = vec![];
+
+    for token in tokens {
+        if let Some(word) = token.strip_prefix('\\') {
+            words.push(word);
+            continue;
+        }
+
+        if let Some(project) = token.strip_prefix('+').filter(|p| !p.is_empty()) {
+            if record.project.is_none() {
+                record.project = Some(project.to_string());
+                continue;
+            }
+        } else if let Some(context) = token.strip_prefix('@').filter(|c| !c.is_empty()) {
+            record.contexts.push(context.to_string());
+            continue;
+        } else if let Some((key, value)) = parse_extra(token) {
+            if key == "pri" && record.done && record.priority.is_none() {
+                let priority = value.chars().next().filter(|p| value.len() == 1 && p.is_ascii_uppercase());
+
+                if priority.is_none() {
+                    return Err(format!("'{}' is not a valid priority", value));
+                }
+
+                record.priority = priority;
+            } else if key == "created" && record.done && record.created_on.is_none() && is_date(value) {
+                record.created_on = Some(value.to_string());
+            } else {
+                record.extras.insert(key.to_string(), value.to_string());
+            }
+            continue;
+        }
+
+        words.push(token);
+    }
+
+    record.title = words.join(" ");
+    record.validate()?;
+
+    Ok(record)
+}
+
+pub fn write_line(record: &TodoRecord) -> String{
+    let mut parts: Vec<String> = vec![];
+
+    if record.done {
+        parts.push(String::from("x"));
+
+        // the creation date can only be written after a completion date
+        if let Some(completed_on) = &record.completed_on {
+            parts.push(completed_on.clone());
+
+            if let Some(created_on) = &record.created_on {
+                parts.push(created_on.clone());
+            }
+        }
+    } else {
+        if let Some(priority) = record.priority {
+            parts.push(format!("({})", priority));
+        }
+
+        if let Some(created_on) = &record.created_on {
+            parts.push(created_on.clone());
+        }
+    }
+
+    // projects ending the title stay bare after the first one, which is written ahead of them
+    let words: Vec<&str> = record.title.split_whitespace().collect();
+    let trailing = match record.project {
+        Some(_) => words.iter().rev().take_while(|w| w.len() > 1 && w.starts_with('+')).count(),
+        None => 0,
+    };
+    let (text, other_projects) = words.split_at(words.len() - trailing);
+
+    for (index, word) in text.iter().enumerate() {
+        match needs_escape(word, index == 0) {
+            true => parts.push(format!("\\{}", word)),
+            false => parts.push(word.to_string()),
+        }
+    }
+
+    if let Some(project) = &record.project {
+        parts.push(format!("+{}", project));
+    }
+
+    parts.extend(other_projects.iter().map(|p| p.to_string()));
+
+    for context in &record.contexts {
+        parts.push(format!("@{}", context));
+    }
+
+    let mut extras = record.extras.clone();
+
+    if record.done {
+        if let Some(priority) = record.priority {
+            extras.insert(String::from("pri"), priority.to_string());
+        }
+
+        if record.completed_on.is_none() {
+            if let Some(created_on) = &record.created_on {
+                extras.insert(String::from("created"), created_on.clone());
+            }
+        }
+    }
+
+    for (key, value) in extras {
+        parts.push(format!("{}:{}", key, value));
+    }
+
+    parts.join(" ")
+}
+
+/// Parses a whole file, skipping blank lines. Errors carry the line number in the file.
+pub fn parse(input: &[u8]) -> Result<(Vec<TodoRecord>, Vec<RowError>), String>{
+    let input = match std::str::from_utf8(input) {
+        Ok(input) => input,
+        Err(_) => return Err(String::from("todo.txt files must be UTF-8")),
+    };
+
+    let mut records = vec![];
+    let mut errors = vec![];
+
+    for (index, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse_line(line) {
+            Ok(record) => records.push(record),
+            Err(message) => errors.push(RowError{row: index + 1, message}),
+        }
+    }
+
+    Ok((records, errors))
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{parse, parse_line, write_line};
+    use crate::formats::TodoRecord;
+
+    #[test]
+    fn should_parse_todotxt_fields(){
+        let record = parse_line("x 2025-01-03 2025-01-01 Call the plumber +house @phone @home due:2025-01-05 pri:B").unwrap();
+
+        assert!(record.done);
+        assert_eq!(record.completed_on.as_deref(), Some("2025-01-03"));
+        assert_eq!(record.created_on.as_deref(), Some("2025-01-01"));
+        assert_eq!(record.title, "Call the plumber");
+        assert_eq!(record.project.as_deref(), Some("house"));
+        assert_eq!(record.contexts, vec!["phone", "home"]);
+        assert_eq!(record.extras.get("due").map(String::as_str), Some("2025-01-05"));
+        assert_eq!(record.priority, Some('B'));
+
+        let record = parse_line("(A) 2025-01-01 Read https://example.com +reading +books").unwrap();
+
+        assert!(!record.done);
+        assert_eq!(record.priority, Some('A'));
+        assert_eq!(record.title, "Read https://example.com +books");
+        assert_eq!(record.project.as_deref(), Some("reading"));
+        assert!(record.extras.is_empty());
+    }
+
+    #[test]
+    fn should_round_trip_todotxt_lines(){
+        let lines = [
+            "Buy milk",
+            "(A) Pay rent +house @online",
+            "(C) 2025-01-01 Water the plants @home due:2025-01-02 repeat:weekly",
+            "x Done without dates",
+            "x 2025-01-03 Done on a day +work",
+            "x 2025-01-03 2025-01-01 Call the plumber +house @phone due:2025-01-05 pri:B",
+            "2025-02-01 Plan trip +travel +family @laptop",
+        ];
+
+        for line in lines {
+            let record = parse_line(line).unwrap();
+            assert_eq!(write_line(&record), line);
+        }
+    }
+
+    #[test]
+    fn should_normalize_to_the_same_todo(){
+        let record = parse_line("(B) @phone Call +work mom due:2025-01-05").unwrap();
+        let written = write_line(&record);
+
+        assert_eq!(written, "(B) Call mom +work @phone due:2025-01-05");
+        assert_eq!(parse_line(&written).unwrap(), record);
+    }
+
+    #[test]
+    fn should_report_bad_lines(){
+        let (records, errors) = parse(b"Buy milk\n\n+project @only\nx 2025-01-03 Done pri:low\n").unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(errors.iter().map(|e| e.row).collect::<Vec<_>>(), vec![3, 4]);
+    }
+
+    #[test]
+    fn should_keep_adversarial_titles(){
+        let titles = [
+            "x marks the spot",
+            "(A) is the best grade",
+            "2025-01-01 was a Wednesday",
+            "Email @bob about +launch",
+            "Set ratio:16 or pri:A",
+            r"Map \\server\share and \x",
+            "+only",
+        ];
+
+        for title in titles {
+            for done in [false, true] {
+                let record = parse_line("Placeholder").map(|r| TodoRecord{title: title.to_string(), done, ..r}).unwrap();
+                let written = write_line(&record);
+
+                assert_eq!(parse_line(&written).unwrap(), record, "{}", written);
+            }
+        }
+
+        let record = parse_line("Placeholder").map(|r| TodoRecord{title: String::from("Email @bob\nx 2025-01-01 injected"), ..r}).unwrap();
+        let written = write_line(&record);
+
+        assert_eq!(written, r"Email \@bob x 2025-01-01 injected");
+        assert_eq!(parse(written.as_bytes()).unwrap().0.len(), 1);
+    }
+
+    #[test]
+    fn should_keep_creation_date_of_tasks_done_on_no_date(){
+        let record = parse_line("x Done without a completion date created:2025-01-01").unwrap();
+
+        assert_eq!(record.created_on.as_deref(), Some("2025-01-01"));
+        assert!(record.extras.is_empty());
+        assert_eq!(write_line(&record), "x Done without a completion date created:2025-01-01");
+    }
+}
@@ -2,3 +2,15 @@ pub use serde::{Deserialize, Serialize};
 
 pub mod user;
 pub mod todo;
+pub mod formats;
+pub mod feed;
+pub mod refresh;
+pub mod revocation;
+pub mod session;
+pub mod reset;
+pub mod verification;
+pub mod lockout;
+pub mod mfa;
+pub mod access_token;
+pub mod oauth;
+pub mod oidc;
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+/// Failed signins for one key, e.g. `account:vk@gmail.com` or `ip:10.0.0.7`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FailedAttempts{
+    pub key: String,
+    pub failures: u32,
+    /// unix seconds
+    pub last_failure: i64,
+    /// no signin is tried for the key before this
+    pub blocked_until: i64,
+}
+
+/// Written when a key reaches the lockout threshold.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct LockoutEvent{
+    pub key: String,
+    pub failures: u32,
+    pub locked_at: i64,
+    pub locked_until: i64,
+}
+
+// The oldest events are dropped past this
+const MAX_EVENTS: usize = 1000;
+// Keys tracked at once, guesses at ever new emails must not grow the table without end
+const MAX_KEYS: usize = 10_000;
+
+impl FailedAttempts {
+
+    pub fn blocked_until(attempts: &[FailedAttempts], key: &str, now: i64) -> Option<i64>{
+        attempts.iter()
+            .find(|a| a.key == key && a.blocked_until > now)
+            .map(|a| a.blocked_until)
+    }
+
+    /// Counts a failure and returns how many there are, starting over when the previous one is
+    /// more than `window` seconds old.
+    pub fn record_failure(attempts: &mut Vec<FailedAttempts>, key: &str, now: i64, window: i64) -> u32{
+        match attempts.iter_mut().find(|a| a.key == key) {
+            Some(attempt) => {
+                if now - attempt.last_failure > window {
+                    attempt.failures = 0;
+                }
+
+                attempt.failures += 1;
+                attempt.last_failure = now;
+                attempt.failures
+            },
+            None => {
+                FailedAttempts::prune(attempts, now, window);
+                attempts.push(FailedAttempts{key: key.to_string(), failures: 1, last_failure: now, blocked_until: 0});
+                1
+            },
+        }
+    }
+
+    // Forgets keys whose failures and block are both over, then if still full the ones that are
+    // least recently failed, blocked keys last so flooding can not lift a lockout early
+    fn prune(attempts: &mut Vec<FailedAttempts>, now: i64, window: i64){
+        attempts.retain(|a| now - a.last_failure <= window || a.blocked_until > now);
+
+        if attempts.len() < MAX_KEYS {
+            return;
+        }
+
+        // a tenth at once, so a flood of new keys does not sort on every one
+        attempts.sort_by_key(|a| (a.blocked_until > now, a.last_failure));
+        attempts.drain(..attempts.len() - MAX_KEYS * 9 / 10);
+    }
+
+    pub fn block(attempts: &mut [FailedAttempts], key: &str, until: i64){
+        if let Some(attempt) = attempts.iter_mut().find(|a| a.key == key) {
+            attempt.blocked_until = attempt.blocked_until.max(until);
+        }
+    }
+
+    pub fn reset(attempts: &mut Vec<FailedAttempts>, key: &str){
+        attempts.retain(|a| a.key != key);
+    }
+}
+
+impl LockoutEvent {
+
+    pub fn record(events: &mut Vec<LockoutEvent>, event: LockoutEvent){
+        events.push(event);
+
+        if events.len() > MAX_EVENTS {
+            events.remove(0);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::{FailedAttempts, MAX_KEYS};
+
+    #[test]
+    fn should_bound_tracked_keys(){
+        let mut attempts = vec![];
+
+        FailedAttempts::record_failure(&mut attempts, "account:old", 0, 60);
+        FailedAttempts::record_failure(&mut attempts, "account:locked", 0, 60);
+        FailedAttempts::block(&mut attempts, "account:locked", 1000);
+
+        // past the window only the blocked key is kept
+        FailedAttempts::record_failure(&mut attempts, "account:new", 100, 60);
+        assert_eq!(attempts.iter().map(|a| a.key.as_str()).collect::<Vec<_>>(), vec!["account:locked", "account:new"]);
+
+        for i in 0..MAX_KEYS * 2 {
+            FailedAttempts::record_failure(&mut attempts, &format!("account:{}", i), 100 + i as i64 / 100, 60);
+        }
+
+        assert!(attempts.len() <= MAX_KEYS);
+        assert_eq!(FailedAttempts::blocked_until(&attempts, "account:locked", 200), Some(1000));
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+/// A TOTP authenticator of one user. The secret has to stay readable to compute codes, so unlike
+/// the recovery codes it is not hashed.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TotpEnrollment{
+    pub user_email: String,
+    /// base32, as shown to the authenticator app
+    pub secret: String,
+    /// set once the user proved the app works, signin asks for a code from then on
+    pub confirmed: bool,
+    pub created_at: i64,
+    /// the last time step a code was accepted for, a code works only once
+    pub last_used_step: i64,
+    /// hashes of the unused recovery codes
+    pub recovery_codes: Vec<String>,
+}
+
+/// Handed out by signin after the password checked out, traded for tokens together with a code.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct MfaChallenge{
+    pub token_hash: String,
+    pub user_email: String,
+    pub expires_at: i64,
+    pub failed_codes: u32,
+}
+
+impl TotpEnrollment {
+
+    pub fn get_enrollment(enrollments: &[TotpEnrollment], email: &String) -> Option<TotpEnrollment>{
+        enrollments.iter().find(|e| e.user_email == *email).cloned()
+    }
+
+    pub fn is_enabled(enrollments: &[TotpEnrollment], email: &String) -> bool{
+        enrollments.iter().any(|e| e.user_email == *email && e.confirmed)
+    }
+
+    /// Starts over with a new secret unless the user already has a confirmed one.
+    pub fn enroll(enrollments: &mut Vec<TotpEnrollment>, enrollment: TotpEnrollment) -> Result<String, String>{
+        if TotpEnrollment::is_enabled(enrollments, &enrollment.user_email) {
+            return Err(String::from("Two-factor authentication is enabled already"));
+        }
+
+        enrollments.retain(|e| e.user_email != enrollment.user_email);
+        enrollments.push(enrollment);
+
+        Ok(String::from("Enrollment started"))
+    }
+
+    pub fn confirm(enrollments: &mut [TotpEnrollment], email: &String, step: i64, recovery_codes: Vec<String>) -> Result<String, String>{
+        let enrollment = enrollments.iter_mut().find(|e| e.user_email == *email);
+
+        if enrollment.is_none(){
+            return Err(String::from("Enrollment not found"));
+        }
+
+        let enrollment = enrollment.unwrap();
+        enrollment.confirmed = true;
+        enrollment.last_used_step = step;
+        enrollment.recovery_codes = recovery_codes;
+
+        Ok(String::from("Two-factor authentication enabled"))
+    }
+
+    /// Records that a code for `step` was accepted, refusing steps at or before the last one.
+    pub fn use_step(enrollments: &mut [TotpEnrollment], email: &String, step: i64) -> Result<String, String>{
+        let enrollment = enrollments.iter_mut().find(|e| e.user_email == *email);
+
+        if enrollment.is_none(){
+            return Err(String::from("Enrollment not found"));
+        }
+
+        let enrollment = enrollment.unwrap();
+
+        if step <= enrollment.last_used_step {
+            return Err(String::from("Code used already"));
+        }
+
+        enrollment.last_used_step = step;
+
+        Ok(String::from("Code accepted"))
+    }
+
+    /// Uses up the recovery code with `code_hash`.
+    pub fn redeem_recovery_code(enrollments: &mut [TotpEnrollment], email: &String, code_hash: &String) -> Result<usize, String>{
+        let enrollment = enrollments.iter_mut().find(|e| e.user_email == *email && e.confirmed);
+
+        if enrollment.is_none(){
+            return Err(String::from("Enrollment not found"));
+        }
+
+        let enrollment = enrollment.unwrap();
+        let before = enrollment.recovery_codes.len();
+        enrollment.recovery_codes.retain(|c| c != code_hash);
+
+        if enrollment.recovery_codes.len() == before {
+            return Err(String::from("Invalid recovery code"));
+        }
+
+        Ok(enrollment.recovery_codes.len())
+    }
+
+    pub fn remove(enrollments: &mut Vec<TotpEnrollment>, email: &String){
+        enrollments.retain(|e| e.user_email != *email);
+    }
+}
+
+impl MfaChallenge {
+
+    pub fn issue(challenges: &mut Vec<MfaChallenge>, challenge: MfaChallenge, now: i64){
+        challenges.retain(|c| c.expires_at > now);
+        challenges.push(challenge);
+    }
+
+    /// The email of a valid challenge, without using it up.
+    pub fn get_email(challenges: &[MfaChallenge], token_hash: &str, now: i64) -> Option<String>{
+        challenges.iter()
+            .find(|c| c.token_hash == token_hash && c.expires_at > now)
+            .map(|c| c.user_email.clone())
+    }
+
+    /// Counts a wrong code, the challenge is dropped after `max_failures` of them.
+    pub fn fail(challenges: &mut Vec<MfaChallenge>, token_hash: &str, max_failures: u32){
+        if let Some(challenge) = challenges.iter_mut().find(|c| c.token_hash == token_hash) {
+            challenge.failed_codes += 1;
+        }
+
+        challenges.retain(|c| c.token_hash != token_hash || c.failed_codes < max_failures);
+    }
+
+    pub fn revoke_user(challenges: &mut Vec<MfaChallenge>, email: &str){
+        challenges.retain(|c| c.user_email != email);
+    }
+
+    pub fn redeem(challenges: &mut Vec<MfaChallenge>, token_hash: &str, now: i64) -> Result<String, String>{
+        let email = MfaChallenge::get_email(challenges, token_hash, now);
+
+        if email.is_none(){
+            return Err(String::from("Invalid or expired challenge"));
+        }
+
+        challenges.retain(|c| c.token_hash != token_hash);
+
+        Ok(email.unwrap())
+    }
+}
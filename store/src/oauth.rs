@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+/// An app registered to act on behalf of users.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct OAuthClient{
+    pub client_id: String,
+    pub name: String,
+    /// the user who registered the app, client credentials tokens act as them
+    pub owner_email: String,
+    /// SHA-256 of the secret of a confidential client, none for a public one such as a CLI
+    pub secret_hash: Option<String>,
+    pub redirect_uris: Vec<String>,
+    /// the most the app may ask for
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+}
+
+/// Handed to the app on its redirect URI, traded for an access token once.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct AuthorizationCode{
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_email: String,
+    /// the redirect URI the authorization request named, the token request has to repeat it.
+    /// `None` when it was left out for the client's only registered one
+    pub redirect_uri: Option<String>,
+    pub scopes: Vec<String>,
+    /// the PKCE S256 challenge the token request has to answer
+    pub code_challenge: String,
+    pub expires_at: i64,
+}
+
+/// What a user allowed an app to do.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Consent{
+    pub user_email: String,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    pub granted_at: i64,
+}
+
+impl OAuthClient {
+
+    pub fn add_client(clients: &mut Vec<OAuthClient>, client: OAuthClient){
+        clients.push(client);
+    }
+
+    pub fn get_client(clients: &[OAuthClient], client_id: &str) -> Option<OAuthClient>{
+        clients.iter().find(|c| c.client_id == client_id).cloned()
+    }
+
+    pub fn get_user_clients(clients: &[OAuthClient], email: &str) -> Vec<OAuthClient>{
+        clients.iter().filter(|c| c.owner_email == email).cloned().collect()
+    }
+
+    pub fn remove(clients: &mut Vec<OAuthClient>, client_id: &str, email: &str) -> Result<String, String>{
+        let before = clients.len();
+        clients.retain(|c| !(c.client_id == client_id && c.owner_email == email));
+
+        if clients.len() == before {
+            return Err(String::from("Client not found"));
+        }
+
+        Ok(String::from("Client removed"))
+    }
+}
+
+impl AuthorizationCode {
+
+    pub fn issue(codes: &mut Vec<AuthorizationCode>, code: AuthorizationCode, now: i64){
+        codes.retain(|c| c.expires_at > now);
+        codes.push(code);
+    }
+
+    /// Uses up the code, which works once whether the token request succeeds or not. Another
+    /// client presenting it does not use it up, or anyone could burn codes they intercepted.
+    pub fn redeem(codes: &mut Vec<AuthorizationCode>, code_hash: &str, client_id: &str, now: i64) -> Result<AuthorizationCode, String>{
+        let position = codes.iter().position(|c| c.code_hash == code_hash);
+
+        if position.is_none(){
+            return Err(String::from("Invalid authorization code"));
+        }
+
+        if codes[position.unwrap()].client_id != client_id {
+            return Err(String::from("The code was issued to another client"));
+        }
+
+        let code = codes.remove(position.unwrap());
+
+        if code.expires_at <= now {
+            return Err(String::from("Authorization code expired"));
+        }
+
+        Ok(code)
+    }
+
+    pub fn revoke_client(codes: &mut Vec<AuthorizationCode>, client_id: &str){
+        codes.retain(|c| c.client_id != client_id);
+    }
+}
+
+impl Consent {
+
+    /// Records the scopes, adding to what the user allowed the app before.
+    pub fn grant(consents: &mut Vec<Consent>, consent: Consent){
+        match consents.iter_mut().find(|c| c.user_email == consent.user_email && c.client_id == consent.client_id) {
+            Some(existing) => {
+                for scope in consent.scopes {
+                    if !existing.scopes.contains(&scope) {
+                        existing.scopes.push(scope);
+                    }
+                }
+                existing.granted_at = consent.granted_at;
+            },
+            None => consents.push(consent),
+        }
+    }
+
+    pub fn covers(consents: &[Consent], email: &str, client_id: &str, scopes: &[String]) -> bool{
+        consents.iter()
+            .find(|c| c.user_email == email && c.client_id == client_id)
+            .is_some_and(|c| scopes.iter().all(|s| c.scopes.contains(s)))
+    }
+
+    pub fn get_user_consents(consents: &[Consent], email: &str) -> Vec<Consent>{
+        consents.iter().filter(|c| c.user_email == email).cloned().collect()
+    }
+
+    pub fn revoke(consents: &mut Vec<Consent>, email: &str, client_id: &str) -> Result<String, String>{
+        let before = consents.len();
+        consents.retain(|c| !(c.user_email == email && c.client_id == client_id));
+
+        if consents.len() == before {
+            return Err(String::from("Consent not found"));
+        }
+
+        Ok(String::from("Consent revoked"))
+    }
+
+    pub fn revoke_client(consents: &mut Vec<Consent>, client_id: &str){
+        consents.retain(|c| c.client_id != client_id);
+    }
+
+    pub fn revoke_user(consents: &mut Vec<Consent>, email: &str){
+        consents.retain(|c| c.user_email != email);
+    }
+}
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+
+/// A sign in started at an OpenID Connect provider, waiting for the browser to come back.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct OidcLogin{
+    /// SHA-256 of the `state` sent to the provider
+    pub state_hash: String,
+    pub provider: String,
+    /// the ID token has to carry it back
+    pub nonce: String,
+    /// the PKCE verifier for the code exchange, only kept for the few minutes the login may take
+    pub code_verifier: String,
+    pub expires_at: i64,
+}
+
+impl OidcLogin {
+
+    pub fn issue(logins: &mut Vec<OidcLogin>, login: OidcLogin, now: i64){
+        logins.retain(|l| l.expires_at > now);
+        logins.push(login);
+    }
+
+    /// Uses up the login started with `state_hash` at `provider`.
+    pub fn redeem(logins: &mut Vec<OidcLogin>, state_hash: &str, provider: &str, now: i64) -> Result<OidcLogin, String>{
+        let position = logins.iter().position(|l| l.state_hash == state_hash && l.provider == provider);
+
+        if position.is_none(){
+            return Err(String::from("Unknown sign in, start over"));
+        }
+
+        let login = logins.remove(position.unwrap());
+
+        if login.expires_at <= now {
+            return Err(String::from("The sign in took too long, start over"));
+        }
+
+        Ok(login)
+    }
+}
+
+/// An account at an OpenID Connect provider, tied to a local user on their first sign in with it.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct OidcIdentity{
+    /// the `iss` of the provider's ID tokens
+    pub issuer: String,
+    /// the `sub` the provider knows the user by, unlike the email it never changes
+    pub subject: String,
+    pub user_email: String,
+    pub linked_at: i64,
+}
+
+impl OidcIdentity {
+
+    pub fn find(identities: &[OidcIdentity], issuer: &str, subject: &str) -> Option<OidcIdentity>{
+        identities.iter().find(|i| i.issuer == issuer && i.subject == subject).cloned()
+    }
+
+    pub fn link(identities: &mut Vec<OidcIdentity>, identity: OidcIdentity) -> Result<String, String>{
+        if identities.iter().any(|i| i.issuer == identity.issuer && i.subject == identity.subject) {
+            return Err(String::from("This provider account is linked already"));
+        }
+
+        identities.push(identity);
+        Ok(String::from("Provider account linked"))
+    }
+
+    pub fn remove_user(identities: &mut Vec<OidcIdentity>, user_email: &str){
+        identities.retain(|i| i.user_email != user_email);
+    }
+}
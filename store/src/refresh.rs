@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+/// An opaque refresh token, only its hash is kept. Every token handed out since a signin
+/// belongs to one family, and each is used once: refreshing replaces it with the next one.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RefreshToken{
+    pub token_hash: String,
+    pub family_id: String,
+    pub user_email: String,
+    /// unix seconds
+    pub created_at: i64,
+    pub expires_at: i64,
+    /// When the family ends however often it was refreshed, set at signin
+    pub family_expires_at: i64,
+    /// Set once the token has been exchanged, presenting it again means it was stolen
+    #[serde(default)]
+    pub replaced: bool,
+}
+
+impl RefreshToken {
+
+    /// Stores a token that starts a new family, dropping every token that has expired by now.
+    pub fn issue(tokens: &mut Vec<RefreshToken>, token: RefreshToken){
+        tokens.retain(|t| t.expires_at > token.created_at);
+        tokens.push(token);
+    }
+
+    /// Exchanges the token with `token_hash` for `next`, which joins its family and user. `next`
+    /// never outlives the family.
+    ///
+    /// A token that was already exchanged revokes its whole family, so both the thief and the
+    /// user have to sign in again.
+    pub fn rotate(tokens: &mut Vec<RefreshToken>, token_hash: &str, mut next: RefreshToken) -> Result<RefreshToken, String>{
+        let current = tokens.iter_mut().find(|t| t.token_hash == token_hash);
+
+        if current.is_none(){
+            return Err(String::from("Invalid refresh token"));
+        }
+
+        let current = current.unwrap();
+
+        if current.replaced {
+            let family_id = current.family_id.clone();
+            RefreshToken::revoke_family(tokens, &family_id);
+            return Err(String::from("Refresh token was already used, sign in again"));
+        }
+
+        if current.expires_at <= next.created_at {
+            return Err(String::from("Refresh token expired, sign in again"));
+        }
+
+        if current.family_expires_at <= next.created_at {
+            let family_id = current.family_id.clone();
+            RefreshToken::revoke_family(tokens, &family_id);
+            return Err(String::from("Session expired, sign in again"));
+        }
+
+        current.replaced = true;
+        next.family_id = current.family_id.clone();
+        next.user_email = current.user_email.clone();
+        next.family_expires_at = current.family_expires_at;
+        next.expires_at = next.expires_at.min(next.family_expires_at);
+
+        tokens.push(next.clone());
+
+        Ok(next)
+    }
+
+    pub fn revoke_family(tokens: &mut Vec<RefreshToken>, family_id: &str){
+        tokens.retain(|t| t.family_id != family_id);
+    }
+
+    /// Revokes the family of the user's token with `token_hash`, i.e. that signin's session.
+    pub fn revoke_token_family(tokens: &mut Vec<RefreshToken>, token_hash: &str, email: &str) -> Result<String, String>{
+        let family_id = tokens.iter()
+            .find(|t| t.token_hash == token_hash && t.user_email == email)
+            .map(|t| t.family_id.clone());
+
+        if family_id.is_none(){
+            return Err(String::from("Invalid refresh token"));
+        }
+
+        RefreshToken::revoke_family(tokens, &family_id.unwrap());
+
+        Ok(String::from("Refresh token revoked"))
+    }
+
+    pub fn revoke_user(tokens: &mut Vec<RefreshToken>, email: &str){
+        tokens.retain(|t| t.user_email != email);
+    }
+}
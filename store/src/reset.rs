@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+/// A password reset token as mailed to the user. Only its hash is kept and it works once.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ResetToken{
+    pub token_hash: String,
+    pub user_email: String,
+    /// unix seconds
+    pub expires_at: i64,
+}
+
+impl ResetToken {
+
+    /// Stores the token, replacing any earlier one of the user, and drops expired tokens.
+    pub fn issue(tokens: &mut Vec<ResetToken>, token: ResetToken, now: i64){
+        tokens.retain(|t| t.expires_at > now && t.user_email != token.user_email);
+        tokens.push(token);
+    }
+
+    /// The email of a valid token, without using it up.
+    pub fn get_email(tokens: &[ResetToken], token_hash: &str, now: i64) -> Option<String>{
+        tokens.iter()
+            .find(|t| t.token_hash == token_hash && t.expires_at > now)
+            .map(|t| t.user_email.clone())
+    }
+
+    pub fn revoke_user(tokens: &mut Vec<ResetToken>, email: &str){
+        tokens.retain(|t| t.user_email != email);
+    }
+
+    /// Uses up the token and returns the email it was issued for.
+    pub fn redeem(tokens: &mut Vec<ResetToken>, token_hash: &str, now: i64) -> Result<String, String>{
+        let index = tokens.iter().position(|t| t.token_hash == token_hash);
+
+        if index.is_none(){
+            return Err(String::from("Invalid or used reset token"));
+        }
+
+        let token = tokens.remove(index.unwrap());
+
+        if token.expires_at <= now {
+            return Err(String::from("Reset token expired, request a new one"));
+        }
+
+        Ok(token.user_email)
+    }
+}
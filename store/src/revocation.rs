@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+/// An access token that was logged out before it expired, identified by its `jti` claim.
+/// There is no point keeping it once the token has expired by itself.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RevokedToken{
+    pub jti: String,
+    pub user_email: String,
+    /// unix seconds, the token's `exp`
+    pub expires_at: i64,
+}
+
+/// Every token of the user issued at or before `not_before` is invalid.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TokenCutoff{
+    pub user_email: String,
+    /// unix milliseconds, a signin in the same second as a logout everywhere must still work
+    pub not_before_ms: i64,
+}
+
+impl RevokedToken {
+
+    /// Adds the token to the list and drops the entries that have expired by `now`.
+    pub fn revoke(revoked: &mut Vec<RevokedToken>, token: RevokedToken, now: i64){
+        revoked.retain(|t| t.expires_at > now && t.jti != token.jti);
+        revoked.push(token);
+    }
+
+    pub fn is_revoked(revoked: &[RevokedToken], jti: &str, now: i64) -> bool{
+        revoked.iter().any(|t| t.jti == jti && t.expires_at > now)
+    }
+}
+
+impl TokenCutoff {
+
+    /// Moves the user's cutoff to `not_before_ms`. It never moves back, that would revive tokens.
+    pub fn set(cutoffs: &mut Vec<TokenCutoff>, cutoff: TokenCutoff){
+        match cutoffs.iter_mut().find(|c| c.user_email == cutoff.user_email) {
+            Some(existing) => existing.not_before_ms = existing.not_before_ms.max(cutoff.not_before_ms),
+            None => cutoffs.push(cutoff),
+        }
+    }
+
+    pub fn is_cut_off(cutoffs: &[TokenCutoff], email: &str, issued_at_ms: i64) -> bool{
+        cutoffs.iter().any(|c| c.user_email == email && issued_at_ms <= c.not_before_ms)
+    }
+}
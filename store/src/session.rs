@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+/// One signin on one device. Access tokens carry the session id as `sid`, and the refresh
+/// tokens of that signin use it as their family id.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Session{
+    pub id: String,
+    pub user_email: String,
+    /// unix seconds
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl Session {
+
+    pub fn add_session(sessions: &mut Vec<Session>, session: Session){
+        sessions.push(session);
+    }
+
+    pub fn get_user_sessions(sessions: &[Session], email: &str) -> Vec<Session>{
+        sessions.iter().filter(|s| s.user_email == email).cloned().collect()
+    }
+
+    pub fn is_active(sessions: &[Session], id: &str) -> bool{
+        sessions.iter().any(|s| s.id == id)
+    }
+
+    pub fn touch(sessions: &mut [Session], id: &str, now: i64){
+        if let Some(session) = sessions.iter_mut().find(|s| s.id == id) {
+            session.last_seen = now;
+        }
+    }
+
+    pub fn revoke(sessions: &mut Vec<Session>, id: &str, email: &str) -> Result<String, String>{
+        let before = sessions.len();
+        sessions.retain(|s| !(s.id == id && s.user_email == email));
+
+        if sessions.len() == before {
+            return Err(String::from("Session not found"));
+        }
+
+        Ok(String::from("Session revoked"))
+    }
+
+    /// Drops a session whatever its user, e.g. once its refresh tokens were revoked.
+    pub fn remove(sessions: &mut Vec<Session>, id: &str){
+        sessions.retain(|s| s.id != id);
+    }
+
+    pub fn revoke_user(sessions: &mut Vec<Session>, email: &str){
+        sessions.retain(|s| s.user_email != email);
+    }
+}
@@ -1,23 +1,62 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
 pub struct Todo{
     pub id: u32,
     pub title: String,
     pub done: bool,
     pub user_email:String,
+    #[serde(default)]
+    pub project: Option<String>,
+    /// `A` is the most important, as in todo.txt
+    #[serde(default)]
+    pub priority: Option<char>,
+    /// dates are kept as `YYYY-MM-DD`
+    #[serde(default)]
+    pub created_on: Option<String>,
+    #[serde(default)]
+    pub completed_on: Option<String>,
+    #[serde(default)]
+    pub contexts: Vec<String>,
+    /// free form `key:value` metadata such as `due:2025-01-31`
+    #[serde(default)]
+    pub extras: BTreeMap<String, String>,
+    /// the UID a calendar client gave the todo, kept so it is exported back unchanged
+    #[serde(default)]
+    pub uid: Option<String>,
+}
+
+
+/// Hands out todo ids. An id is never given out twice, not even after its todo is deleted, since
+/// calendar clients may still hold the todo under it.
+#[derive(Debug, Default)]
+pub struct TodoIds{
+    last: u32,
+}
+
+impl TodoIds {
+    /// The next id, also past any id already in `todos`
+    pub fn next(&mut self, todos: &[Todo]) -> u32{
+        let highest = todos.iter().map(|t| t.id).max().unwrap_or(0);
+
+        self.last = self.last.max(highest) + 1;
+        self.last
+    }
 }
 
 
 impl Todo {
-    pub fn add_todo(title:String, done: bool, email: String, todos: &mut Vec<Todo>) -> Todo{
+    pub fn add_todo(title:String, done: bool, email: String, todos: &mut Vec<Todo>, ids: &mut TodoIds) -> Todo{
 
-        let id = (todos.len() + 1) as u32;
+        let id = ids.next(todos);
         let todo = Todo{
             id,
             done,
             title,
             user_email: email,
+            ..Default::default()
         };
 
         todos.push(todo.clone());
@@ -36,12 +75,8 @@ impl Todo {
         user_todos
     }
 
-    pub fn get_todo(id:u32, todos: &Vec<Todo>) -> Option<Todo>{
-        let res = todos.iter().find(|t|t.id == id);
-        match res {
-            Some(val) => Some(val.clone()),
-            None => None,
-        }
+    pub fn get_todo(id:u32, todos: &[Todo]) -> Option<Todo>{
+        todos.iter().find(|t|t.id == id).cloned()
     }
 
     pub fn update_todo(id:u32, email:String, title:String, done:bool, todos:&mut Vec<Todo>) -> Result<String, String>{
@@ -68,5 +103,110 @@ impl Todo {
 
 
     }
-    
-}
\ No newline at end of file
+
+    pub fn patch_todo(id:u32, email:String, title:Option<String>, done:Option<bool>, todos:&mut [Todo]) -> Result<Todo, String>{
+        let todo = todos.iter_mut().find(|t| t.id == id);
+
+        if todo.is_none(){
+            return Err(String::from("Enter Valid todo id"));
+        }
+
+        let todo = todo.unwrap();
+
+        if todo.user_email != email {
+            return Err(String::from("UNAUTHORISED"));
+        }
+
+        // every field is applied in one go, so a rejected patch never leaves a half-updated todo
+        if let Some(title) = title {
+            todo.title = title;
+        }
+        if let Some(done) = done {
+            todo.done = done;
+        }
+
+        Ok(todo.clone())
+    }
+
+    fn check_owned(ids:&[u32], email:&String, todos:&[Todo]) -> Result<(), String>{
+        let mut missing = vec![];
+        let mut foreign = vec![];
+
+        for id in ids {
+            match todos.iter().find(|t| t.id == *id) {
+                None => missing.push(id.to_string()),
+                Some(todo) if todo.user_email != *email => foreign.push(id.to_string()),
+                Some(_) => {},
+            }
+        }
+
+        if !missing.is_empty(){
+            return Err(format!("Enter Valid todo ids: {}", missing.join(", ")));
+        }
+
+        if !foreign.is_empty(){
+            return Err(format!("UNAUTHORISED: {}", foreign.join(", ")));
+        }
+
+        Ok(())
+    }
+
+    /// Applies `change` to every listed todo, or to none of them if any id is unknown or not owned by `email`.
+    pub fn bulk_update(ids:&[u32], email:String, todos:&mut [Todo], change: impl Fn(&mut Todo)) -> Result<Vec<Todo>, String>{
+        Todo::check_owned(ids, &email, todos)?;
+
+        let mut updated = vec![];
+
+        for todo in todos.iter_mut() {
+            if ids.contains(&todo.id) {
+                change(todo);
+                updated.push(todo.clone());
+            }
+        }
+
+        Ok(updated)
+    }
+
+    /// Deletes every listed todo, or none of them if any id is unknown or not owned by `email`.
+    pub fn delete_todos(ids:&[u32], email:String, todos:&mut Vec<Todo>) -> Result<Vec<u32>, String>{
+        Todo::check_owned(ids, &email, todos)?;
+
+        todos.retain(|t| !ids.contains(&t.id));
+
+        let mut deleted = ids.to_vec();
+        deleted.sort();
+        deleted.dedup();
+
+        Ok(deleted)
+    }
+
+    pub fn delete_user_todos(email:&String, todos:&mut Vec<Todo>) -> Vec<u32>{
+        let deleted = todos.iter().filter(|t| t.user_email == *email).map(|t| t.id).collect();
+        todos.retain(|t| t.user_email != *email);
+        deleted
+    }
+
+    /// Stores an imported todo for `email`. When the id or calendar uid names one of their existing
+    /// todos it is overwritten in place, otherwise the todo is added under a fresh id. Returns the
+    /// stored todo and whether it replaced an existing one.
+    pub fn import_todo(mut todo:Todo, email:String, todos:&mut Vec<Todo>, ids: &mut TodoIds) -> (Todo, bool){
+        todo.user_email = email;
+
+        let existing = todos.iter_mut().find(|t| {
+            let same = t.id == todo.id || (todo.uid.is_some() && t.uid == todo.uid);
+            same && t.user_email == todo.user_email
+        });
+
+        if let Some(existing) = existing {
+            todo.id = existing.id;
+            *existing = todo.clone();
+            return (todo, true);
+        }
+
+        todo.id = ids.next(todos);
+        todos.push(todo.clone());
+
+        (todo, false)
+    }
+
+}
@@ -5,18 +5,21 @@ pub struct User{
     pub email: String,
     pub name: String,
     pub password:String,
+    /// whether the user opened the link mailed at signup
+    #[serde(default)]
+    pub verified: bool,
+    /// e.g. `admin`, copied into the JWTs the user is issued
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// a disabled user can not sign in and their tokens stop working
+    #[serde(default)]
+    pub disabled: bool,
 }
 
 impl User {
 
-    pub fn get_user(users: &Vec<User> , email: &String) -> Option<User>{
-        let user_exist = users.iter().find(|u| u.email == *email);
-
-        if user_exist.is_none(){
-            return None;
-        }
-
-        let user = user_exist.unwrap().clone();
+    pub fn get_user(users: &[User] , email: &String) -> Option<User>{
+        let user = users.iter().find(|u| u.email == *email)?.clone();
 
         Some(user)
     }
@@ -27,13 +30,102 @@ impl User {
 
         match existing_user_res {
             Some(_val) => {
-                return Err(String::from("User exists already"));
+                Err(String::from("User exists already"))
             },
             None => {
                 users.push(user.clone());
-                return Ok(String::from("User created Successfully"));
+                Ok(String::from("User created Successfully"))
             }
         }
 
     }
-}
\ No newline at end of file
+
+    pub fn set_password(users: &mut [User], email: &String, password: String) -> Result<String, String>{
+        let user = users.iter_mut().find(|u| u.email == *email);
+
+        if user.is_none(){
+            return Err(String::from("User not found"));
+        }
+
+        user.unwrap().password = password;
+
+        Ok(String::from("Password updated"))
+    }
+
+    pub fn set_verified(users: &mut [User], email: &String) -> Result<String, String>{
+        let user = users.iter_mut().find(|u| u.email == *email);
+
+        if user.is_none(){
+            return Err(String::from("User not found"));
+        }
+
+        user.unwrap().verified = true;
+
+        Ok(String::from("Email verified"))
+    }
+
+    pub fn set_disabled(users: &mut [User], email: &String, disabled: bool) -> Result<String, String>{
+        let user = users.iter_mut().find(|u| u.email == *email);
+
+        if user.is_none(){
+            return Err(String::from("User not found"));
+        }
+
+        user.unwrap().disabled = disabled;
+
+        Ok(String::from(if disabled { "User disabled" } else { "User enabled" }))
+    }
+
+    pub fn grant_role(users: &mut [User], email: &String, role: &str) -> Result<String, String>{
+        let user = users.iter_mut().find(|u| u.email == *email);
+
+        if user.is_none(){
+            return Err(String::from("User not found"));
+        }
+
+        let user = user.unwrap();
+
+        if !user.roles.iter().any(|r| r == role) {
+            user.roles.push(role.to_string());
+        }
+
+        Ok(String::from("Role granted"))
+    }
+
+    pub fn revoke_role(users: &mut [User], email: &String, role: &str) -> Result<String, String>{
+        let user = users.iter_mut().find(|u| u.email == *email);
+
+        if user.is_none(){
+            return Err(String::from("User not found"));
+        }
+
+        user.unwrap().roles.retain(|r| r != role);
+
+        Ok(String::from("Role revoked"))
+    }
+
+    pub fn has_role(users: &[User], email: &String, role: &str) -> bool{
+        users.iter().any(|u| u.email == *email && u.roles.iter().any(|r| r == role))
+    }
+
+    /// Users whose email or name contains `query`, ignoring case.
+    pub fn search(users: &[User], query: &str) -> Vec<User>{
+        let query = query.to_lowercase();
+
+        users.iter()
+            .filter(|u| u.email.to_lowercase().contains(&query) || u.name.to_lowercase().contains(&query))
+            .cloned()
+            .collect()
+    }
+
+    pub fn remove_user(users: &mut Vec<User>, email: &String) -> Result<String, String>{
+        let before = users.len();
+        users.retain(|u| u.email != *email);
+
+        if users.len() == before {
+            return Err(String::from("User not found"));
+        }
+
+        Ok(String::from("User deleted"))
+    }
+}
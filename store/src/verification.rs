@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+/// The token in an email verification link. Only its hash is kept, a user has at most one.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct VerificationToken{
+    pub token_hash: String,
+    pub user_email: String,
+    /// unix seconds
+    pub sent_at: i64,
+    pub expires_at: i64,
+}
+
+impl VerificationToken {
+
+    /// Stores the token in place of the user's previous one, which stops working.
+    pub fn issue(tokens: &mut Vec<VerificationToken>, token: VerificationToken){
+        tokens.retain(|t| t.user_email != token.user_email && t.expires_at > token.sent_at);
+        tokens.push(token);
+    }
+
+    /// When the user's current link was sent, to throttle resending it.
+    pub fn last_sent(tokens: &[VerificationToken], email: &str) -> Option<i64>{
+        tokens.iter().find(|t| t.user_email == email).map(|t| t.sent_at)
+    }
+
+    pub fn revoke_user(tokens: &mut Vec<VerificationToken>, email: &str){
+        tokens.retain(|t| t.user_email != email);
+    }
+
+    /// Uses up the token and returns the email it verifies.
+    pub fn redeem(tokens: &mut Vec<VerificationToken>, token_hash: &str, now: i64) -> Result<String, String>{
+        let index = tokens.iter().position(|t| t.token_hash == token_hash);
+
+        if index.is_none(){
+            return Err(String::from("Invalid or used verification link"));
+        }
+
+        let token = tokens.remove(index.unwrap());
+
+        if token.expires_at <= now {
+            return Err(String::from("Verification link expired, request a new one"));
+        }
+
+        Ok(token.user_email)
+    }
+}